@@ -0,0 +1,114 @@
+//! Regression baselines for dmatex creation and the per-frame swapchain paths, against the
+//! mock server on the first gpu that can share dmatexes
+
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use stardust_xr_cme::{
+    dmatex::Dmatex,
+    mock::MockSetup,
+    swapchain::{Swapchain, SwapchainFrameHandle},
+};
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use vulkano::{
+    command_buffer::{SemaphoreSubmitInfo, SubmitInfo},
+    format::Format,
+    image::ImageUsage,
+    sync::PipelineStages,
+};
+
+const SIZE: [u32; 2] = [1024, 1024];
+
+fn dmatex_creation(c: &mut Criterion) {
+    let setup = MockSetup::new().unwrap();
+    let format = &setup.formats[&Format::R8G8B8A8_UNORM];
+    c.bench_function("dmatex creation", |b| {
+        b.iter(|| {
+            Dmatex::new(
+                &setup.server,
+                &setup.dev,
+                &setup.render_dev,
+                DmatexSize::Dim2D(SIZE.into()),
+                format,
+                None,
+                ImageUsage::COLOR_ATTACHMENT,
+            )
+        })
+    });
+}
+
+/// hands the frame to the server without rendering anything
+fn submit_empty(setup: &MockSetup, frame: SwapchainFrameHandle<'_>) -> DmatexSubmitInfo {
+    frame
+        .submit(&setup.dev, &setup.queue, |wait, mut guard, signal| unsafe {
+            guard.submit(
+                &[SubmitInfo {
+                    wait_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(wait)
+                    }],
+                    signal_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(signal)
+                    }],
+                    ..Default::default()
+                }],
+                None,
+            )
+        })
+        .unwrap()
+}
+
+fn swapchain(setup: &MockSetup) -> Swapchain {
+    Swapchain::builder(
+        &setup.server,
+        &setup.dev,
+        &setup.render_dev,
+        DmatexSize::Dim2D(SIZE.into()),
+        &setup.formats[&Format::R8G8B8A8_UNORM],
+        ImageUsage::COLOR_ATTACHMENT,
+    )
+    .build()
+    .unwrap()
+}
+
+fn acquire_latency(c: &mut Criterion) {
+    let setup = MockSetup::new().unwrap();
+    let mut swapchain = swapchain(&setup);
+    c.bench_function("swapchain acquire", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let start = Instant::now();
+                let frame = swapchain.prepare_next_image();
+                frame.blocking_release_wait();
+                total += start.elapsed();
+                let submit = submit_empty(&setup, frame);
+                setup.server.present(&submit).unwrap();
+            }
+            total
+        })
+    });
+}
+
+fn submit_overhead(c: &mut Criterion) {
+    let setup = MockSetup::new().unwrap();
+    let mut swapchain = swapchain(&setup);
+    c.bench_function("swapchain submit", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let frame = swapchain.prepare_next_image();
+                frame.blocking_release_wait();
+                let start = Instant::now();
+                let submit = submit_empty(&setup, frame);
+                total += start.elapsed();
+                setup.server.present(&submit).unwrap();
+            }
+            total
+        })
+    });
+}
+
+criterion_group!(benches, dmatex_creation, acquire_latency, submit_overhead);
+criterion_main!(benches);
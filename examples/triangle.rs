@@ -0,0 +1,213 @@
+//! A spinning triangle rendered into a swapchain and presented to the mock server, the
+//! smallest complete renderer. Run with `cargo run --example triangle --features examples`
+
+use std::time::Instant;
+
+use stardust_xr_cme::{mock::MockSetup, render_context::RenderContext, swapchain::Swapchain};
+use stardust_xr_fusion::drawable::DmatexSize;
+use vulkano::{
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassBeginInfo,
+        SubpassContents,
+    },
+    format::Format,
+    image::{ImageLayout, ImageUsage},
+    pipeline::{
+        GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::ColorBlendState,
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, Subpass},
+};
+
+const SIZE: [u32; 2] = [512, 512];
+const FRAMES: u32 = 600;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+            #version 450
+
+            layout(push_constant) uniform PushConstants {
+                float angle;
+            } push_constants;
+
+            layout(location = 0) out vec3 v_color;
+
+            const vec2 POSITIONS[3] = vec2[](vec2(0.0, -0.7), vec2(0.6, 0.5), vec2(-0.6, 0.5));
+            const vec3 COLORS[3] = vec3[](vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+
+            void main() {
+                float s = sin(push_constants.angle);
+                float c = cos(push_constants.angle);
+                vec2 position = mat2(c, s, -s, c) * POSITIONS[gl_VertexIndex];
+                gl_Position = vec4(position, 0.0, 1.0);
+                v_color = COLORS[gl_VertexIndex];
+            }
+        ",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+            #version 450
+
+            layout(location = 0) in vec3 v_color;
+            layout(location = 0) out vec4 color;
+
+            void main() {
+                color = vec4(v_color, 1.0);
+            }
+        ",
+    }
+}
+
+fn main() {
+    let setup = MockSetup::new().unwrap();
+    let context = RenderContext::new(&setup.dev);
+    let format = &setup.formats[&Format::R8G8B8A8_UNORM];
+
+    let render_pass = vulkano::single_pass_renderpass!(
+        setup.dev.clone(),
+        attachments: {
+            color: {
+                format: format.vk_format(),
+                samples: 1,
+                load_op: Clear,
+                store_op: Store,
+            },
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {},
+        },
+    )
+    .unwrap();
+    let vs = vs::load(setup.dev.clone())
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let fs = fs::load(setup.dev.clone())
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vs),
+        PipelineShaderStageCreateInfo::new(fs),
+    ];
+    let layout = PipelineLayout::new(
+        setup.dev.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(setup.dev.clone())
+            .unwrap(),
+    )
+    .unwrap();
+    let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+    let pipeline = GraphicsPipeline::new(
+        setup.dev.clone(),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(VertexInputState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState {
+                viewports: [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [SIZE[0] as f32, SIZE[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            }),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.num_color_attachments(),
+                Default::default(),
+            )),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )
+    .unwrap();
+
+    // a framebuffer per image, recreated together with the images
+    let framebuffer_pass = render_pass.clone();
+    let mut swapchain = Swapchain::builder(
+        &setup.server,
+        &setup.dev,
+        &setup.render_dev,
+        DmatexSize::Dim2D(SIZE.into()),
+        format,
+        ImageUsage::COLOR_ATTACHMENT,
+    )
+    .layout_transitions(ImageLayout::ColorAttachmentOptimal, ImageLayout::General)
+    .build_with(move |dmatex| {
+        Framebuffer::new(
+            framebuffer_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![dmatex.create_view()],
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    })
+    .unwrap();
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        let frame = swapchain.prepare_next_image();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            context.command_buffer_allocator().clone(),
+            setup.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0, 0.0, 0.0, 0.0].into())],
+                    ..RenderPassBeginInfo::framebuffer(frame.data().clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    angle: start.elapsed().as_secs_f32(),
+                },
+            )
+            .unwrap();
+        unsafe { builder.draw(3, 1, 0, 0) }.unwrap();
+        builder.end_render_pass(Default::default()).unwrap();
+        let submit = frame
+            .submit_command_buffer(&setup.dev, &setup.queue, builder.build().unwrap())
+            .unwrap();
+        // the mock server plays compositor, a real app sets a material parameter instead
+        setup.server.present(&submit).unwrap();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{FRAMES} frames in {elapsed:?}, {:?} per frame",
+        elapsed / FRAMES
+    );
+}
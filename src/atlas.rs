@@ -0,0 +1,392 @@
+//! Packing many small surfaces into one large dmatex
+//!
+//! The [`Atlas`] only does the bookkeeping, the image itself is usually a [`Swapchain`] the
+//! regions get rendered or uploaded into, every region then gets its own material using the
+//! uv transform from [`Atlas::uv`].
+//!
+//! [`Swapchain`]: crate::swapchain::Swapchain
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AtlasRegionId(u64);
+
+/// A rectangle in atlas pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl AtlasRect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+    fn union(&self, other: &AtlasRect) -> AtlasRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        AtlasRect {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+    fn intersect(&self, other: &AtlasRect) -> Option<AtlasRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then_some(AtlasRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// Maps the 0..1 uvs of a quad onto its region, `uv * scale + offset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasUv {
+    pub offset: [f32; 2],
+    pub scale: [f32; 2],
+}
+
+/// A horizontal strip of the atlas, regions in it are placed left to right
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// unused horizontal spans as (x, width), sorted by x
+    free: Vec<(u32, u32)>,
+    regions: usize,
+}
+
+/// Shelf packer with per region damage tracking
+///
+/// Damage is tracked separately for every image of the swapchain the atlas is drawn into,
+/// so each image catches up on what changed since it was last presented.
+pub struct Atlas<const IMAGES: usize = 3> {
+    width: u32,
+    height: u32,
+    padding: u32,
+    shelves: Vec<Shelf>,
+    regions: HashMap<AtlasRegionId, AtlasRect>,
+    next_id: u64,
+    image_damage: [HashMap<AtlasRegionId, AtlasRect>; IMAGES],
+    next_image: usize,
+}
+
+impl<const IMAGES: usize> Atlas<IMAGES> {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            padding: 1,
+            shelves: Vec::new(),
+            regions: HashMap::new(),
+            next_id: 0,
+            image_damage: [(); IMAGES].map(|_| HashMap::new()),
+            next_image: 0,
+        }
+    }
+    /// empty pixels kept around every region so linear filtering doesn't bleed neighbours in,
+    /// defaults to 1
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// Reserves a `width`x`height` region, returns None if the atlas is full.
+    /// The new region starts out completely damaged.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasRegionId> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let padded_width = width + self.padding * 2;
+        let padded_height = height + self.padding * 2;
+        if padded_width > self.width {
+            return None;
+        }
+
+        let (x, y) = self.place(padded_width, padded_height)?;
+        let rect = AtlasRect {
+            x: x + self.padding,
+            y: y + self.padding,
+            width,
+            height,
+        };
+        let id = AtlasRegionId(self.next_id);
+        self.next_id += 1;
+        self.regions.insert(id, rect);
+        for damage in &mut self.image_damage {
+            damage.insert(id, rect);
+        }
+        Some(id)
+    }
+    fn place(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let fits = |shelf: &Shelf| shelf.free.iter().position(|(_, w)| *w >= width);
+        // best fitting existing shelf first, shelves that are a lot taller than needed
+        // waste too much space and only get used if there is no other option
+        let candidate = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| shelf.height >= height && shelf.height <= height * 2)
+            .filter_map(|(i, shelf)| Some((i, fits(shelf)?, shelf.height)))
+            .min_by_key(|(_, _, shelf_height)| *shelf_height);
+        let (shelf, span) = match candidate {
+            Some((i, span, _)) => (i, span),
+            None => match self.new_shelf(height) {
+                Some(i) => (i, 0),
+                None => self
+                    .shelves
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, shelf)| shelf.height >= height)
+                    .find_map(|(i, shelf)| Some((i, fits(shelf)?)))?,
+            },
+        };
+        let shelf = &mut self.shelves[shelf];
+        let (x, free_width) = shelf.free[span];
+        // guillotine cut, whatever is left of the span stays free
+        if free_width == width {
+            shelf.free.remove(span);
+        } else {
+            shelf.free[span] = (x + width, free_width - width);
+        }
+        shelf.regions += 1;
+        Some((x, shelf.y))
+    }
+    fn new_shelf(&mut self, height: u32) -> Option<usize> {
+        // an emptied shelf can be reused at any height it can hold
+        if let Some(i) = self
+            .shelves
+            .iter()
+            .position(|shelf| shelf.regions == 0 && shelf.height >= height)
+        {
+            return Some(i);
+        }
+        let y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+        if y + height > self.height {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            free: vec![(0, self.width)],
+            regions: 0,
+        });
+        Some(self.shelves.len() - 1)
+    }
+    /// Frees the region, its pixels can be handed out again right away
+    pub fn free(&mut self, id: AtlasRegionId) {
+        let Some(rect) = self.regions.remove(&id) else {
+            return;
+        };
+        for damage in &mut self.image_damage {
+            damage.remove(&id);
+        }
+        let x = rect.x - self.padding;
+        let y = rect.y - self.padding;
+        let width = rect.width + self.padding * 2;
+        let Some(shelf) = self.shelves.iter_mut().find(|s| s.y == y) else {
+            return;
+        };
+        let index = shelf.free.partition_point(|(v, _)| *v < x);
+        shelf.free.insert(index, (x, width));
+        // merge with the neighbouring spans
+        if index + 1 < shelf.free.len() && x + width == shelf.free[index + 1].0 {
+            shelf.free[index].1 += shelf.free[index + 1].1;
+            shelf.free.remove(index + 1);
+        }
+        if index > 0 && shelf.free[index - 1].0 + shelf.free[index - 1].1 == x {
+            shelf.free[index - 1].1 += shelf.free[index].1;
+            shelf.free.remove(index);
+        }
+        shelf.regions -= 1;
+        // trailing empty shelves give their height back to the rest of the atlas
+        while self.shelves.last().is_some_and(|s| s.regions == 0) {
+            self.shelves.pop();
+        }
+    }
+    /// The pixels of the region inside the atlas, use this as the crop/viewport when drawing it
+    pub fn rect(&self, id: AtlasRegionId) -> Option<AtlasRect> {
+        self.regions.get(&id).copied()
+    }
+    /// The uv transform for the material of the region
+    pub fn uv(&self, id: AtlasRegionId) -> Option<AtlasUv> {
+        let rect = self.rect(id)?;
+        Some(AtlasUv {
+            offset: [
+                rect.x as f32 / self.width as f32,
+                rect.y as f32 / self.height as f32,
+            ],
+            scale: [
+                rect.width as f32 / self.width as f32,
+                rect.height as f32 / self.height as f32,
+            ],
+        })
+    }
+    pub fn regions(&self) -> impl Iterator<Item = (AtlasRegionId, AtlasRect)> + '_ {
+        self.regions.iter().map(|(id, rect)| (*id, *rect))
+    }
+    /// Marks `rect`, relative to the region's top left corner, as changed
+    pub fn mark_dirty(&mut self, id: AtlasRegionId, rect: AtlasRect) {
+        let Some(region) = self.regions.get(&id) else {
+            return;
+        };
+        let Some(rect) = AtlasRect {
+            x: region.x + rect.x,
+            y: region.y + rect.y,
+            ..rect
+        }
+        .intersect(region) else {
+            return;
+        };
+        for damage in &mut self.image_damage {
+            damage
+                .entry(id)
+                .and_modify(|v| *v = v.union(&rect))
+                .or_insert(rect);
+        }
+    }
+    pub fn mark_region_dirty(&mut self, id: AtlasRegionId) {
+        let Some(region) = self.regions.get(&id).copied() else {
+            return;
+        };
+        for damage in &mut self.image_damage {
+            damage.insert(id, region);
+        }
+    }
+    /// Takes the damage the next swapchain image has to catch up on, in atlas pixels.
+    /// Call this once per presented frame, the images are expected round robin.
+    pub fn take_damage(&mut self) -> Vec<(AtlasRegionId, AtlasRect)> {
+        let damage = std::mem::take(&mut self.image_damage[self.next_image]);
+        self.next_image = (self.next_image + 1) % IMAGES;
+        damage.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> AtlasRect {
+        AtlasRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn packs_until_full() {
+        let mut atlas = Atlas::<1>::new(64, 64).with_padding(0);
+        let ids = (0..16)
+            .map(|_| atlas.allocate(16, 16).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(atlas.allocate(16, 16), None);
+        let rects = ids
+            .iter()
+            .map(|id| atlas.rect(*id).unwrap())
+            .collect::<Vec<_>>();
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.right() <= 64 && a.bottom() <= 64, "{a:?}");
+            for b in &rects[i + 1..] {
+                assert_eq!(a.intersect(b), None, "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_oversize_regions() {
+        let mut atlas = Atlas::<1>::new(64, 64);
+        assert_eq!(atlas.allocate(0, 8), None);
+        assert_eq!(atlas.allocate(65, 8), None);
+        assert_eq!(atlas.allocate(8, 65), None);
+        // the padding has to fit as well
+        assert_eq!(atlas.allocate(64, 8), None);
+        assert_eq!(atlas.allocate(8, 64), None);
+        let id = atlas.allocate(62, 62).unwrap();
+        assert_eq!(atlas.rect(id), Some(rect(1, 1, 62, 62)));
+    }
+
+    #[test]
+    fn reuses_freed_regions() {
+        let mut atlas = Atlas::<1>::new(64, 64).with_padding(0);
+        let ids = (0..16)
+            .map(|_| atlas.allocate(16, 16).unwrap())
+            .collect::<Vec<_>>();
+        let freed = atlas.rect(ids[5]).unwrap();
+        atlas.free(ids[5]);
+        assert_eq!(atlas.rect(ids[5]), None);
+        let id = atlas.allocate(16, 16).unwrap();
+        assert_eq!(atlas.rect(id), Some(freed));
+        assert_eq!(atlas.allocate(16, 16), None);
+        // once everything is freed the whole atlas is available again
+        for id in ids.into_iter().chain([id]) {
+            atlas.free(id);
+        }
+        let id = atlas.allocate(64, 64).unwrap();
+        assert_eq!(atlas.rect(id), Some(rect(0, 0, 64, 64)));
+    }
+
+    #[test]
+    fn dirty_rects_are_merged_per_image() {
+        let mut atlas = Atlas::<2>::new(64, 64);
+        let id = atlas.allocate(32, 32).unwrap();
+        let region = atlas.rect(id).unwrap();
+        // new regions are damaged completely in every image
+        assert_eq!(atlas.take_damage(), [(id, region)]);
+        assert_eq!(atlas.take_damage(), [(id, region)]);
+        assert!(atlas.take_damage().is_empty());
+
+        // relative to the region, outside parts are clipped
+        atlas.mark_dirty(id, rect(2, 2, 4, 4));
+        atlas.mark_dirty(id, rect(10, 8, 2, 6));
+        let merged = rect(3, 3, 10, 12);
+        assert_eq!(atlas.take_damage(), [(id, merged)]);
+        atlas.mark_dirty(id, rect(30, 30, 10, 10));
+        assert_eq!(
+            atlas.take_damage(),
+            [(id, merged.union(&rect(31, 31, 2, 2)))]
+        );
+        assert_eq!(atlas.take_damage(), [(id, rect(31, 31, 2, 2))]);
+        atlas.mark_dirty(id, rect(40, 40, 4, 4));
+        assert!(atlas.take_damage().is_empty());
+    }
+
+    #[test]
+    fn uv_covers_the_region() {
+        let mut atlas = Atlas::<1>::new(256, 128).with_padding(0);
+        let first = atlas.allocate(64, 32).unwrap();
+        let second = atlas.allocate(64, 32).unwrap();
+        assert_eq!(
+            atlas.uv(first),
+            Some(AtlasUv {
+                offset: [0.0, 0.0],
+                scale: [0.25, 0.25],
+            })
+        );
+        assert_eq!(
+            atlas.uv(second),
+            Some(AtlasUv {
+                offset: [0.25, 0.0],
+                scale: [0.25, 0.25],
+            })
+        );
+        atlas.free(second);
+        assert_eq!(atlas.uv(second), None);
+    }
+}
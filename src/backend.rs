@@ -0,0 +1,186 @@
+use std::{
+    future::Future,
+    os::fd::OwnedFd,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, Weak,
+        mpsc::{Receiver, Sender, channel},
+    },
+};
+
+use stardust_xr_fusion::{
+    ClientHandle,
+    drawable::{
+        DmatexPlane, DmatexSize, enumerate_dmatex_formats, get_primary_render_device_id,
+        import_dmatex, unregister_dmatex,
+    },
+    node::{NodeError, NodeResult},
+};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::format::ColorSpace;
+
+pub type BackendFuture<T, E = NodeError> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+/// The server side of cme, implemented for [`ClientHandle`] and, with the `testing` feature,
+/// for [`MockServer`](crate::mock::MockServer)
+pub trait Backend: Send + Sync + 'static {
+    fn generate_id(&self) -> u64;
+    fn primary_render_device_id(self: Arc<Self>) -> BackendFuture<u64>;
+    fn enumerate_dmatex_formats(
+        self: Arc<Self>,
+        render_node_id: u64,
+    ) -> BackendFuture<Vec<ServerFormat>>;
+    fn import_dmatex(self: Arc<Self>, import: DmatexImport) -> Result<(), ImportError>;
+    /// Resolves once the server handled the import of `dmatex_id`, so frames submitted
+    /// afterwards find it, with [`ImportError::Rejected`] if it didn't accept it
+    fn confirm_dmatex(self: Arc<Self>, dmatex_id: u64) -> BackendFuture<(), ImportError>;
+    /// Tells the server to forget `dmatex_id`, it must not be shown or acquired anymore
+    fn unregister_dmatex(self: Arc<Self>, dmatex_id: u64) -> NodeResult<()>;
+    /// Subscribes to how the client's drawables are shown, every subscription gets every
+    /// event sent after it was made
+    fn surface_events(self: Arc<Self>) -> SurfaceEvents;
+}
+
+/// How the server shows the client's drawables, see [`Backend::surface_events`]
+#[derive(Debug, Clone)]
+pub enum SurfaceEvent {
+    /// whether anyone can see them, e.g. false while the panel is minimized or the item stowed
+    Visible(bool),
+    /// whether the client has the user's focus
+    Focused(bool),
+    /// the pixel size they should be rendered at, e.g. a panel's toplevel size
+    PreferredSize(DmatexSize),
+}
+
+/// A subscription to [`SurfaceEvent`]s, polled with `try_iter` once per frame
+pub type SurfaceEvents = Receiver<SurfaceEvent>;
+
+/// The subscriptions of every [`ClientHandle`], dead clients and subscriptions are dropped on
+/// the next publish
+static CLIENT_SURFACE_EVENTS: Mutex<Vec<(Weak<ClientHandle>, Sender<SurfaceEvent>)>> =
+    Mutex::new(Vec::new());
+
+/// Hands `event` to every [surface event](Backend::surface_events) subscription of `client`.
+/// The protocol has no events for visibility, focus or preferred sizes yet, they're spread
+/// over the fusion events where the server tells about them (panel toplevel size changes,
+/// items being stowed, ...), so the handlers of those publish them here
+pub fn publish_surface_event(client: &Arc<ClientHandle>, event: SurfaceEvent) {
+    CLIENT_SURFACE_EVENTS
+        .lock()
+        .unwrap()
+        .retain(|(subscribed, sender)| {
+            if subscribed.strong_count() == 0 {
+                return false;
+            }
+            !std::ptr::eq(subscribed.as_ptr(), Arc::as_ptr(client))
+                || sender.send(event.clone()).is_ok()
+        });
+}
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    /// the server already has a dmatex with this id, a fresh id can be tried
+    #[error("dmatex id {0} is already in use")]
+    IdCollision(u64),
+    /// the server handled the import but doesn't know the dmatex afterwards
+    #[error("the server rejected dmatex {0}")]
+    Rejected(u64),
+    #[error(transparent)]
+    Node(#[from] NodeError),
+}
+
+/// A single format/modifier combination the server can import
+#[derive(Debug, Clone, Copy)]
+pub struct ServerFormat {
+    pub fourcc: u32,
+    pub is_srgb: bool,
+    pub drm_modifier: u64,
+    pub planes: u32,
+}
+
+/// Everything the server needs to import a dmatex
+pub struct DmatexImport {
+    pub dmatex_id: u64,
+    pub size: DmatexSize,
+    pub fourcc: u32,
+    pub drm_modifier: u64,
+    pub srgb: bool,
+    pub array_layers: Option<u32>,
+    pub planes: Vec<DmatexPlane>,
+    pub timeline: OwnedFd,
+    /// None leaves it up to the server, which assumes [`ColorSpace::Srgb`]. Stardust servers
+    /// can't be told about anything else yet, [`ClientHandle`] only warns about it
+    pub color_space: Option<ColorSpace>,
+}
+
+impl Backend for ClientHandle {
+    fn generate_id(&self) -> u64 {
+        ClientHandle::generate_id(self)
+    }
+    fn primary_render_device_id(self: Arc<Self>) -> BackendFuture<u64> {
+        Box::pin(async move { get_primary_render_device_id(&self).await })
+    }
+    fn enumerate_dmatex_formats(
+        self: Arc<Self>,
+        render_node_id: u64,
+    ) -> BackendFuture<Vec<ServerFormat>> {
+        Box::pin(async move {
+            let formats = enumerate_dmatex_formats(&self, render_node_id).await?;
+            Ok(formats
+                .into_iter()
+                .map(|v| ServerFormat {
+                    fourcc: v.format,
+                    is_srgb: v.is_srgb,
+                    drm_modifier: v.drm_modifier,
+                    planes: v.planes,
+                })
+                .collect())
+        })
+    }
+    fn import_dmatex(self: Arc<Self>, import: DmatexImport) -> Result<(), ImportError> {
+        // the protocol can't carry a colorspace yet
+        if let Some(color_space) = import.color_space
+            && color_space != ColorSpace::Srgb
+        {
+            warn!(
+                "the server can't be told about colorspaces yet, dmatex {} will be composited as srgb instead of {color_space:?}",
+                import.dmatex_id
+            );
+        }
+        import_dmatex(
+            &self,
+            import.dmatex_id,
+            import.size,
+            import.fourcc,
+            import.drm_modifier,
+            import.srgb,
+            import.array_layers,
+            &import.planes,
+            import.timeline.into(),
+        )?;
+        Ok(())
+    }
+    fn confirm_dmatex(self: Arc<Self>, _dmatex_id: u64) -> BackendFuture<(), ImportError> {
+        // the import is a signal without a reply, but messages are handled in order, so the
+        // server is done with it once any later method call returns. Which one doesn't
+        // matter, this is the cheapest. The protocol has no way to report a rejected import
+        // yet, the server only logs it
+        Box::pin(async move {
+            get_primary_render_device_id(&self).await?;
+            Ok(())
+        })
+    }
+    fn unregister_dmatex(self: Arc<Self>, dmatex_id: u64) -> NodeResult<()> {
+        unregister_dmatex(&self, dmatex_id)
+    }
+    fn surface_events(self: Arc<Self>) -> SurfaceEvents {
+        let (sender, events) = channel();
+        CLIENT_SURFACE_EVENTS
+            .lock()
+            .unwrap()
+            .push((Arc::downgrade(&self), sender));
+        events
+    }
+}
@@ -0,0 +1,181 @@
+//! CPU drawn 2D surfaces using tiny-skia, uploaded into a [`Swapchain`] through a staging buffer
+
+use std::sync::Arc;
+
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use tiny_skia::{IntRect, Pixmap, PixmapMut};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CommandBufferUsage, CopyBufferToImageInfo,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::ImageUsage,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::{
+    backend::Backend,
+    format::DmatexFormat,
+    render_device::RenderDevice,
+    swapchain::{Swapchain, SwapchainError},
+};
+
+/// A tiny-skia pixmap backed swapchain, only the damaged region is uploaded on present
+pub struct Canvas {
+    dev: Arc<Device>,
+    swapchain: Swapchain,
+    pixmap: Pixmap,
+    /// damage each swapchain image still has to catch up on,
+    /// the swapchain hands out its images round robin
+    image_damage: [Option<IntRect>; 3],
+    next_image: usize,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+}
+
+impl Canvas {
+    /// `format` has to be `R8G8B8A8_UNORM` or `R8G8B8A8_SRGB`, which matches tiny-skia's
+    /// premultiplied rgba8 pixels
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        width: u32,
+        height: u32,
+        format: &DmatexFormat,
+    ) -> Result<Self, SwapchainError> {
+        assert!(
+            matches!(
+                format.vk_format(),
+                Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB
+            ),
+            "canvas needs an rgba8 format, got {:?}",
+            format.vk_format()
+        );
+        let swapchain = Swapchain::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim2D([width, height].into()),
+            format,
+            ImageUsage::TRANSFER_DST,
+        )
+        .build()?;
+        let full = IntRect::from_xywh(0, 0, width, height);
+        Ok(Self {
+            dev: dev.clone(),
+            swapchain,
+            pixmap: Pixmap::new(width, height).expect("canvas size can't be 0"),
+            image_damage: [full; 3],
+            next_image: 0,
+            memory_allocator: Arc::new(StandardMemoryAllocator::new_default(dev.clone())),
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+        })
+    }
+    pub fn width(&self) -> u32 {
+        self.pixmap.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.pixmap.height()
+    }
+    /// draws into the pixmap and marks `damage` as dirty
+    pub fn draw(&mut self, damage: IntRect, draw: impl FnOnce(&mut PixmapMut)) {
+        draw(&mut self.pixmap.as_mut());
+        self.mark_dirty(damage);
+    }
+    /// direct access to the pixmap, changes have to be reported with [`Self::mark_dirty`]
+    pub fn pixmap_mut(&mut self) -> PixmapMut<'_> {
+        self.pixmap.as_mut()
+    }
+    pub fn pixmap(&self) -> &Pixmap {
+        &self.pixmap
+    }
+    pub fn mark_dirty(&mut self, rect: IntRect) {
+        let Some(rect) = IntRect::from_xywh(0, 0, self.width(), self.height())
+            .and_then(|bounds| bounds.intersect(&rect))
+        else {
+            return;
+        };
+        for damage in &mut self.image_damage {
+            *damage = Some(match damage {
+                Some(v) => union(v, &rect),
+                None => rect,
+            });
+        }
+    }
+    pub fn mark_all_dirty(&mut self) {
+        self.image_damage = [IntRect::from_xywh(0, 0, self.width(), self.height()); 3];
+    }
+    /// Uploads the damaged region into the next swapchain image,
+    /// returns None if nothing changed since the last present
+    pub fn present(
+        &mut self,
+        queue: &Arc<Queue>,
+    ) -> Result<Option<DmatexSubmitInfo>, SwapchainError> {
+        let Some(damage) = self.image_damage[self.next_image].take() else {
+            return Ok(None);
+        };
+        self.next_image = (self.next_image + 1) % self.image_damage.len();
+        let frame = self.swapchain.prepare_next_image();
+        let image = frame.image();
+
+        // upload whole rows so the pixmap's row pitch can be reused as is
+        let row_size = self.pixmap.width() as usize * 4;
+        let rows = damage.top() as usize..damage.bottom() as usize;
+        let staging = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            self.pixmap.data()[rows.start * row_size..rows.end * row_size]
+                .iter()
+                .copied(),
+        )
+        .unwrap();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    buffer_offset: damage.left() as u64 * 4,
+                    buffer_row_length: self.pixmap.width(),
+                    image_subresource: image.subresource_layers(),
+                    image_offset: [damage.left() as u32, damage.top() as u32, 0],
+                    image_extent: [damage.width(), damage.height(), 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(staging, image.clone())
+            })
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        frame
+            .submit_command_buffer(&self.dev, queue, command_buffer)
+            .map(Some)
+    }
+}
+
+fn union(a: &IntRect, b: &IntRect) -> IntRect {
+    let left = a.left().min(b.left());
+    let top = a.top().min(b.top());
+    let right = a.right().max(b.right());
+    let bottom = a.bottom().max(b.bottom());
+    IntRect::from_ltrb(left, top, right, bottom).unwrap()
+}
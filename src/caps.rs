@@ -0,0 +1,73 @@
+//! Optional device capabilities cme has fast paths for. Everything else falls back to
+//! slower paths on devices without them instead of failing, and reports a
+//! [`DiagnosticEvent::CapabilityMissing`] when it does.
+
+use vulkano::{
+    Version,
+    device::Device,
+    sync::semaphore::{ExternalSemaphoreHandleType, ExternalSemaphoreInfo},
+};
+
+use crate::diagnostics::{DiagnosticEvent, report};
+
+/// What a device was created with and its driver supports, beyond
+/// [`Dmatex::required_device_exts`](crate::dmatex::Dmatex::required_device_exts). Enable
+/// [`Dmatex::optional_device_exts`](crate::dmatex::Dmatex::optional_device_exts) for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceCaps {
+    /// sync fd semaphores can be imported, so submissions can wait for the server's release
+    /// on the gpu
+    pub sync_fd_import: bool,
+    /// sync fd semaphores can be exported, so submissions can signal the acquire point on the
+    /// gpu
+    pub sync_fd_export: bool,
+    /// multi-planar images can be created with separate memory per plane
+    pub disjoint_planes: bool,
+    /// the modifiers of a format can be queried and images created with explicit modifiers,
+    /// without it dmatexes are created with linear tiling and dmabufs can't be imported
+    pub modifier_query: bool,
+}
+
+impl DeviceCaps {
+    pub fn new(dev: &Device) -> Self {
+        let extensions = dev.enabled_extensions();
+        let sync_fd = extensions
+            .khr_external_semaphore_fd
+            .then(|| {
+                dev.physical_device()
+                    .external_semaphore_properties(ExternalSemaphoreInfo::handle_type(
+                        ExternalSemaphoreHandleType::SyncFd,
+                    ))
+                    .ok()
+            })
+            .flatten();
+        Self {
+            sync_fd_import: sync_fd.as_ref().is_some_and(|v| v.importable),
+            sync_fd_export: sync_fd.as_ref().is_some_and(|v| v.exportable),
+            disjoint_planes: dev.api_version() >= Version::V1_1
+                || extensions.khr_sampler_ycbcr_conversion,
+            modifier_query: extensions.ext_image_drm_format_modifier,
+        }
+    }
+    /// whether frames can be synchronized with the server entirely on the gpu, otherwise
+    /// swapchains use [host sync](crate::swapchain::SwapchainBuilder::host_sync)
+    pub fn gpu_sync(&self) -> bool {
+        self.sync_fd_import && self.sync_fd_export
+    }
+    /// reports `capability` as missing unless `supported`, returns `supported`
+    pub(crate) fn check(supported: bool, capability: DeviceCapability) -> bool {
+        if !supported {
+            report(DiagnosticEvent::CapabilityMissing(capability));
+        }
+        supported
+    }
+}
+
+/// A field of [`DeviceCaps`], what's reported when a fallback is used for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceCapability {
+    SyncFdImport,
+    SyncFdExport,
+    DisjointPlanes,
+    ModifierQuery,
+}
@@ -0,0 +1,153 @@
+//! RenderDoc captures of swapchain frames, for debugging textures shared with the server
+//!
+//! cme never presents, so RenderDoc's own frame boundaries don't exist. After
+//! [`trigger_capture_on_next_frame`] the next [`SwapchainFrameHandle::submit`] is wrapped in a
+//! frame capture, and the capture's comments name the dmatex id and timeline points so it can
+//! be matched up with what the server captured. RenderDoc has to be injected already, e.g. by
+//! launching the app from it, nothing is loaded here.
+//!
+//! [`SwapchainFrameHandle::submit`]: crate::swapchain::SwapchainFrameHandle::submit
+
+use std::{
+    ffi::{CString, c_char, c_void},
+    ptr,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use libloading::os::unix::{Library, RTLD_NOW};
+use stardust_xr_fusion::drawable::DmatexSubmitInfo;
+use tracing::info;
+use vulkano::{Handle, VulkanObject, instance::Instance};
+
+/// `RTLD_NOLOAD` from `dlfcn.h`, only finds libraries that are loaded already
+const RTLD_NOLOAD: i32 = 0x4;
+/// `eRENDERDOC_API_Version_1_2_0`, the first version with capture comments
+const API_VERSION: u32 = 10200;
+
+static API: OnceLock<Option<RenderDoc>> = OnceLock::new();
+static CAPTURE_NEXT: AtomicBool = AtomicBool::new(false);
+
+/// whether the app runs under RenderDoc
+pub fn is_available() -> bool {
+    api().is_some()
+}
+
+/// Captures the next frame submitted by any swapchain, returns false without RenderDoc
+pub fn trigger_capture_on_next_frame() -> bool {
+    if !is_available() {
+        return false;
+    }
+    CAPTURE_NEXT.store(true, Ordering::Relaxed);
+    true
+}
+
+/// A frame capture in progress, started right before a frame's submissions
+pub(crate) struct FrameCapture {
+    api: &'static RenderDoc,
+    device: *mut c_void,
+}
+
+/// starts a capture if one was triggered, None otherwise
+pub(crate) fn begin_frame(instance: &Instance) -> Option<FrameCapture> {
+    let api = api()?;
+    if !CAPTURE_NEXT.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    // RENDERDOC_DEVICEPOINTER_FROM_VKINSTANCE, the dispatch table pointer of the instance
+    let device = unsafe { *(instance.handle().as_raw() as *const *mut c_void) };
+    unsafe { (api.table().start_frame_capture)(device, ptr::null_mut()) };
+    Some(FrameCapture { api, device })
+}
+
+impl FrameCapture {
+    /// ends the capture and tags it with the submitted frame
+    pub(crate) fn end(self, submit: &DmatexSubmitInfo, name: Option<&str>) {
+        let table = self.api.table();
+        if unsafe { (table.end_frame_capture)(self.device, ptr::null_mut()) } == 0 {
+            return;
+        }
+        let comment = format!(
+            "{}dmatex {} acquire point {} release point {}",
+            name.map(|name| format!("{name}: ")).unwrap_or_default(),
+            submit.dmatex_id,
+            submit.acquire_point,
+            submit.release_point,
+        );
+        info!("captured frame of {comment}");
+        let comment = CString::new(comment).unwrap();
+        // a null path tags the latest capture
+        unsafe { (table.set_capture_file_comments)(ptr::null(), comment.as_ptr()) };
+    }
+}
+
+fn api() -> Option<&'static RenderDoc> {
+    API.get_or_init(|| unsafe { RenderDoc::load() }).as_ref()
+}
+
+struct RenderDoc {
+    table: *const ApiTable,
+    /// keeps the table valid
+    _library: Library,
+}
+// the api is thread safe, the table is never written to
+unsafe impl Send for RenderDoc {}
+unsafe impl Sync for RenderDoc {}
+
+impl RenderDoc {
+    unsafe fn load() -> Option<Self> {
+        let library =
+            unsafe { Library::open(Some("librenderdoc.so"), RTLD_NOW | RTLD_NOLOAD) }.ok()?;
+        let get_api = unsafe {
+            library.get::<unsafe extern "C" fn(u32, *mut *const ApiTable) -> i32>(
+                b"RENDERDOC_GetAPI\0",
+            )
+        }
+        .ok()?;
+        let mut table = ptr::null();
+        if unsafe { get_api(API_VERSION, &mut table) } != 1 || table.is_null() {
+            return None;
+        }
+        Some(Self {
+            table,
+            _library: library,
+        })
+    }
+    fn table(&self) -> &ApiTable {
+        unsafe { &*self.table }
+    }
+}
+
+type Unused = *const c_void;
+
+/// `RENDERDOC_API_1_2_0` from `renderdoc_app.h`, only the used functions are typed
+#[repr(C)]
+struct ApiTable {
+    _get_api_version: Unused,
+    _set_capture_option_u32: Unused,
+    _set_capture_option_f32: Unused,
+    _get_capture_option_u32: Unused,
+    _get_capture_option_f32: Unused,
+    _set_focus_toggle_keys: Unused,
+    _set_capture_keys: Unused,
+    _get_overlay_bits: Unused,
+    _mask_overlay_bits: Unused,
+    _remove_hooks: Unused,
+    _unload_crash_handler: Unused,
+    _set_capture_file_path_template: Unused,
+    _get_capture_file_path_template: Unused,
+    _get_num_captures: Unused,
+    _get_capture: Unused,
+    _trigger_capture: Unused,
+    _is_target_control_connected: Unused,
+    _launch_replay_ui: Unused,
+    _set_active_window: Unused,
+    start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+    _is_frame_capturing: Unused,
+    end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+    _trigger_multi_frame_capture: Unused,
+    set_capture_file_comments:
+        unsafe extern "C" fn(file_path: *const c_char, comments: *const c_char),
+}
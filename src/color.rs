@@ -0,0 +1,166 @@
+//! Declares the color space content is authored in, so it reaches the server the way it
+//! composites: bt.709 primaries, decoded with the srgb transfer function when the dmatex is
+//! flagged srgb and taken as linear otherwise.
+//!
+//! [`ColorOutput`] picks the format variant and [`Srgbness`] that make the server decode the
+//! content correctly, and only falls back to a conversion pass run with
+//! [`SwapchainBuilder::post_process`] when no flag can. The pass needs the
+//! `shader_storage_image_read_without_format` and `shader_storage_image_write_without_format`
+//! device features, alpha is passed through unchanged.
+
+use std::{collections::HashMap, sync::Arc};
+
+use thiserror::Error;
+use vulkano::{device::Device, format::Format, image::ImageUsage};
+
+use crate::{
+    dmatex::Srgbness,
+    format::DmatexFormat,
+    fourcc::VulkanoFormatExtension,
+    post_process::{PostProcess, PostProcessError},
+    swapchain::SwapchainBuilder,
+};
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/color.comp",
+    }
+}
+
+mod cs_layered {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/color.comp",
+        define: [("LAYERED", "1")],
+    }
+}
+
+/// The color space the values written into a swapchain are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkingSpace {
+    /// bt.709 primaries without a transfer function, what shading in linear light produces
+    LinearRec709,
+    /// bt.709 primaries with the srgb transfer function, e.g. values straight from 8 bit
+    /// images or ui toolkits
+    Srgb,
+    /// display p3 primaries with the srgb transfer function, e.g. content made on apple
+    /// devices. Colors outside of bt.709 are clipped
+    DisplayP3,
+}
+
+/// How a swapchain has to be created so the server shows content in a [`WorkingSpace`] as
+/// authored
+pub struct ColorOutput {
+    space: WorkingSpace,
+    format: DmatexFormat,
+    srgbness: Srgbness,
+    pass: Option<Arc<PostProcess>>,
+}
+
+impl ColorOutput {
+    /// Picks the variant of `format` from the negotiated `formats` and the srgb flag for
+    /// `space`, and a conversion pass if those aren't enough. `layered` has to match whether
+    /// the swapchain will have array layers
+    pub fn new(
+        dev: &Arc<Device>,
+        space: WorkingSpace,
+        format: &DmatexFormat,
+        formats: &HashMap<Format, DmatexFormat>,
+        layered: bool,
+    ) -> Result<Self, ColorError> {
+        // the hardware srgb encoding is only wanted for linear content
+        let unorm = if format.vk_format().is_srgb() {
+            formats
+                .values()
+                .find(|v| v.vk_format().to_srgb() == Some(format.vk_format()))
+                .ok_or(ColorError::NoUnormVariant(format.vk_format()))?
+        } else {
+            format
+        };
+        let srgb = unorm
+            .vk_format()
+            .to_srgb()
+            .and_then(|srgb| formats.get(&srgb));
+        // without an srgb variant the server takes the values as linear
+        let can_flag_srgb = unorm.vk_format().to_srgb().is_some();
+        let (format, srgbness, conversion) = match space {
+            WorkingSpace::LinearRec709 => match srgb {
+                Some(srgb) => (srgb, Srgbness::Auto, None),
+                None => (unorm, Srgbness::Linear, None),
+            },
+            WorkingSpace::Srgb if can_flag_srgb => (unorm, Srgbness::Srgb, None),
+            WorkingSpace::Srgb => (unorm, Srgbness::Linear, Some([1, 0, 0])),
+            WorkingSpace::DisplayP3 if can_flag_srgb => (unorm, Srgbness::Srgb, Some([1, 1, 1])),
+            WorkingSpace::DisplayP3 => (unorm, Srgbness::Linear, Some([1, 1, 0])),
+        };
+        let pass = conversion
+            .map(|push_constants: [u32; 3]| {
+                let module = if layered {
+                    cs_layered::load(dev.clone())
+                } else {
+                    cs::load(dev.clone())
+                }
+                .map_err(|err| PostProcessError::Shader(err.to_string()))?;
+                let pass = PostProcess::from_entry_point(
+                    dev,
+                    module.entry_point("main").unwrap(),
+                    [8, 8],
+                )?;
+                pass.set_push_constants(
+                    &push_constants
+                        .into_iter()
+                        .flat_map(u32::to_ne_bytes)
+                        .collect::<Vec<_>>(),
+                );
+                Ok::<_, PostProcessError>(Arc::new(pass))
+            })
+            .transpose()?;
+        Ok(Self {
+            space,
+            format: format.clone(),
+            srgbness,
+            pass,
+        })
+    }
+    pub fn space(&self) -> WorkingSpace {
+        self.space
+    }
+    /// the format to create the swapchain with
+    pub fn format(&self) -> &DmatexFormat {
+        &self.format
+    }
+    /// the srgb flag the server has to get, set by [`Self::apply`]
+    pub fn srgbness(&self) -> Srgbness {
+        self.srgbness
+    }
+    /// the conversion pass, None if the format and srgb flag are enough
+    pub fn pass(&self) -> Option<&Arc<PostProcess>> {
+        self.pass.as_ref()
+    }
+    /// usage the swapchain needs on top of your own, storage for the conversion pass
+    pub fn usage(&self) -> ImageUsage {
+        if self.pass.is_some() {
+            ImageUsage::STORAGE
+        } else {
+            ImageUsage::empty()
+        }
+    }
+    /// Sets the srgb flag and the conversion pass on a swapchain created with
+    /// [`Self::format`] and [`Self::usage`]
+    pub fn apply<'a>(&self, builder: SwapchainBuilder<'a>) -> SwapchainBuilder<'a> {
+        let builder = builder.srgbness(self.srgbness);
+        match &self.pass {
+            Some(pass) => builder.post_process(pass),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ColorError {
+    #[error("the server has no unorm variant of {0:?} to write encoded values into")]
+    NoUnormVariant(Format),
+    #[error(transparent)]
+    PostProcess(#[from] PostProcessError),
+}
@@ -0,0 +1,68 @@
+use tracing::{debug, error, warn};
+use vulkano::{
+    VulkanObject,
+    device::DeviceOwned,
+    instance::{
+        InstanceCreateInfo,
+        debug::{
+            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessengerCallback,
+            DebugUtilsMessengerCreateInfo, ValidationFeatureEnable,
+        },
+    },
+};
+
+/// sets the `VK_EXT_debug_utils` name of `object`, noop if the extension isn't enabled
+pub(crate) fn set_object_name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if !device.instance().enabled_extensions().ext_debug_utils {
+        return;
+    }
+    if let Err(err) = device.set_debug_utils_object_name(object, Some(name)) {
+        warn!("failed to set debug name {name:?}: {err}");
+    }
+}
+
+/// Enables `VK_LAYER_KHRONOS_validation` with synchronization validation and forwards its
+/// messages to `tracing`, instance creation fails if the layer isn't installed.
+///
+/// Pair this with [`SwapchainBuilder::foreign_ownership_transfers`] to get a clean report,
+/// without the ownership transfers every dmatex access is reported as a hazard
+///
+/// [`SwapchainBuilder::foreign_ownership_transfers`]: crate::swapchain::SwapchainBuilder::foreign_ownership_transfers
+pub fn enable_validation(create_info: &mut InstanceCreateInfo) {
+    const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+    if !create_info
+        .enabled_layers
+        .iter()
+        .any(|l| l == VALIDATION_LAYER)
+    {
+        create_info.enabled_layers.push(VALIDATION_LAYER.into());
+    }
+    create_info.enabled_extensions.ext_debug_utils = true;
+    create_info.enabled_extensions.ext_validation_features = true;
+    create_info
+        .enabled_validation_features
+        .push(ValidationFeatureEnable::SynchronizationValidation);
+    create_info
+        .debug_utils_messengers
+        .push(DebugUtilsMessengerCreateInfo {
+            message_severity: DebugUtilsMessageSeverity::ERROR
+                | DebugUtilsMessageSeverity::WARNING
+                | DebugUtilsMessageSeverity::INFO,
+            message_type: DebugUtilsMessageType::GENERAL
+                | DebugUtilsMessageType::VALIDATION
+                | DebugUtilsMessageType::PERFORMANCE,
+            ..DebugUtilsMessengerCreateInfo::user_callback(unsafe {
+                DebugUtilsMessengerCallback::new(|severity, _, data| {
+                    let id = data.message_id_name.unwrap_or_default();
+                    if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+                        error!("[{id}] {}", data.message);
+                    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+                        warn!("[{id}] {}", data.message);
+                    } else {
+                        debug!("[{id}] {}", data.message);
+                    }
+                })
+            })
+        });
+}
@@ -0,0 +1,106 @@
+//! Notices the server moving to another gpu, e.g. when a laptop is docked or undocked.
+//!
+//! The protocol has no event for it, so [`DeviceWatcher::poll`] asks the server for its
+//! primary render device and compares it to the last one, call it every few seconds or when
+//! the app regains focus. On a change the app creates a new vulkan device for the new
+//! [`RenderDevice`] and recreates every swapchain with
+//! [`Swapchain::recreate_on`](crate::swapchain::Swapchain::recreate_on). Swapchains built
+//! with [`SwapchainBuilder::device_watcher`](crate::swapchain::SwapchainBuilder::device_watcher)
+//! remember whether that happened since the change.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use thiserror::Error;
+
+use crate::{
+    backend::Backend,
+    format::{EnumerateError, FormatTable},
+    render_device::{RenderDevice, RenderDeviceCreationError},
+};
+
+/// Tracks the server's primary render device
+pub struct DeviceWatcher {
+    client: Arc<dyn Backend>,
+    render_node_id: u64,
+    /// bumped on every change, shared with the [`DeviceToken`]s
+    generation: Arc<AtomicU64>,
+}
+
+impl DeviceWatcher {
+    /// `render_dev` is the device everything is currently created on
+    pub fn new<B: Backend>(client: &Arc<B>, render_dev: &RenderDevice) -> Self {
+        Self {
+            client: client.clone(),
+            render_node_id: render_dev.drm_node_id(),
+            generation: Arc::default(),
+        }
+    }
+    /// Asks the server for its primary render device, returns it if it changed since the
+    /// last poll. The previous device is kept if this fails, so it can simply be retried
+    pub async fn poll(&mut self) -> Result<Option<RenderDevice>, RenderDeviceCreationError> {
+        let id = self
+            .client
+            .clone()
+            .primary_render_device_id()
+            .await
+            .map_err(RenderDeviceCreationError::FailedToGetDeviceId)?;
+        if id == self.render_node_id {
+            return Ok(None);
+        }
+        let render_dev = RenderDevice::from_node_id(id)?;
+        self.render_node_id = id;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(render_dev))
+    }
+    /// Like [`Self::poll`], and enumerates `formats` again for the new device, the formats
+    /// negotiated for the old one can't be used on it
+    pub async fn poll_with_formats(
+        &mut self,
+        formats: &mut FormatTable,
+    ) -> Result<Option<RenderDevice>, DeviceWatchError> {
+        let Some(render_dev) = self.poll().await? else {
+            return Ok(None);
+        };
+        formats.refresh(&render_dev).await?;
+        Ok(Some(render_dev))
+    }
+    /// the drm node id of the device the server used at the last poll
+    pub fn render_node_id(&self) -> u64 {
+        self.render_node_id
+    }
+    /// a token that goes stale once the server switches gpus
+    pub fn token(&self) -> DeviceToken {
+        DeviceToken {
+            generation: self.generation.clone(),
+            seen: self.generation.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether something was created for the server's current gpu, see [`DeviceWatcher::token`]
+#[derive(Debug, Clone)]
+pub struct DeviceToken {
+    generation: Arc<AtomicU64>,
+    seen: u64,
+}
+impl DeviceToken {
+    /// whether the server switched gpus since the token was created or last renewed
+    pub fn is_stale(&self) -> bool {
+        self.generation.load(Ordering::Relaxed) != self.seen
+    }
+    /// marks the token current again, after everything was recreated on the new device
+    pub fn renew(&mut self) {
+        self.seen = self.generation.load(Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DeviceWatchError {
+    #[error(transparent)]
+    RenderDevice(#[from] RenderDeviceCreationError),
+    #[error(transparent)]
+    Formats(#[from] EnumerateError),
+}
@@ -0,0 +1,400 @@
+//! Checks whether a device can do everything cme needs before anything gets created,
+//! so a missing capability shows up as a readable report instead of a panic
+//!
+//! Conditions cme works around while running are [`DiagnosticEvent`]s. They're logged with
+//! `tracing` and handed to the sink set with [`set_sink`], e.g. so a GUI can tell users why
+//! a format was rejected or why an image ended up linear.
+
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use rustix::fs::{Mode, OFlags, open};
+use vulkano::{
+    buffer::{BufferUsage, ExternalBufferInfo},
+    device::physical::PhysicalDevice,
+    instance::Instance,
+    memory::ExternalMemoryHandleType,
+    sync::semaphore::{ExternalSemaphoreHandleType, ExternalSemaphoreInfo},
+};
+
+use tracing::{error, info, warn};
+use vulkano::format::Format;
+
+use crate::{caps::DeviceCapability, dmatex::Dmatex, format::UnmappedFormat};
+
+/// What `phys_dev` is missing, see [`diagnose`]
+#[derive(Debug, Clone)]
+pub struct CapabilityReport {
+    pub device_name: String,
+    /// required instance extensions that weren't enabled on the instance
+    pub missing_instance_extensions: Vec<&'static str>,
+    /// required device extensions the device doesn't support
+    pub missing_device_extensions: Vec<&'static str>,
+    /// required device features the device doesn't support
+    pub missing_features: Vec<&'static str>,
+    pub dmabuf_export: bool,
+    pub dmabuf_import: bool,
+    /// without it swapchains signal acquire points on the cpu, see [`Self::fallbacks`]
+    pub sync_fd_export: bool,
+    /// without it swapchains wait for releases on the cpu
+    pub sync_fd_import: bool,
+    /// whether the optional `ext_image_drm_format_modifier` is supported, without it
+    /// dmatexes are linear and dmabufs can't be imported
+    pub modifier_query: bool,
+    /// why the device's drm render node can't be used, None if it can be opened
+    pub drm_node_error: Option<String>,
+}
+
+impl CapabilityReport {
+    /// whether dmatexes can be created and shared on this device, the [fallbacks](Self::fallbacks)
+    /// only make that slower
+    pub fn is_supported(&self) -> bool {
+        self.missing_instance_extensions.is_empty()
+            && self.missing_device_extensions.is_empty()
+            && self.missing_features.is_empty()
+            && self.dmabuf_export
+            && self.dmabuf_import
+            && self.drm_node_error.is_none()
+    }
+    /// the optional capabilities that are missing, cme uses slower paths instead of them like
+    /// it does when they're missing on a [device](crate::caps::DeviceCaps)
+    pub fn fallbacks(&self) -> Vec<DeviceCapability> {
+        [
+            (self.sync_fd_import, DeviceCapability::SyncFdImport),
+            (self.sync_fd_export, DeviceCapability::SyncFdExport),
+            (self.modifier_query, DeviceCapability::ModifierQuery),
+        ]
+        .into_iter()
+        .filter_map(|(supported, capability)| (!supported).then_some(capability))
+        .collect()
+    }
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_supported() {
+            write!(f, "{} supports everything cme needs", self.device_name)?;
+            for capability in self.fallbacks() {
+                write!(f, "\n  {}", DiagnosticEvent::CapabilityMissing(capability))?;
+            }
+            return Ok(());
+        }
+        writeln!(f, "{} is missing capabilities cme needs:", self.device_name)?;
+        for ext in &self.missing_instance_extensions {
+            writeln!(f, "  instance extension {ext} is not enabled")?;
+        }
+        for ext in &self.missing_device_extensions {
+            writeln!(f, "  device extension {ext} is not supported")?;
+        }
+        for feature in &self.missing_features {
+            writeln!(f, "  device feature {feature} is not supported")?;
+        }
+        let capabilities = [
+            (self.dmabuf_export, "exporting dmabuf memory"),
+            (self.dmabuf_import, "importing dmabuf memory"),
+        ];
+        for (_, capability) in capabilities.iter().filter(|(supported, _)| !supported) {
+            writeln!(f, "  {capability} is not supported")?;
+        }
+        if let Some(err) = &self.drm_node_error {
+            writeln!(f, "  the drm render node is not usable: {err}")?;
+        }
+        for capability in self.fallbacks() {
+            writeln!(f, "  {}", DiagnosticEvent::CapabilityMissing(capability))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the extensions, features and external memory/semaphore capabilities cme needs,
+/// and whether the device's drm render node can be opened
+pub fn diagnose(instance: &Arc<Instance>, phys_dev: &Arc<PhysicalDevice>) -> CapabilityReport {
+    let missing_instance_extensions = Dmatex::required_instance_exts()
+        .difference(instance.enabled_extensions())
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+        .collect();
+    let missing_device_extensions = Dmatex::required_device_exts()
+        .difference(phys_dev.supported_extensions())
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+        .collect();
+    let missing_features = Dmatex::required_device_features()
+        .difference(phys_dev.supported_features())
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+        .collect();
+
+    // these fail validation when the extensions are missing, which is reported above
+    let memory = phys_dev
+        .external_buffer_properties(ExternalBufferInfo {
+            usage: BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            ..ExternalBufferInfo::handle_type(ExternalMemoryHandleType::DmaBuf)
+        })
+        .ok()
+        .map(|v| v.external_memory_properties);
+    let semaphore = phys_dev
+        .external_semaphore_properties(ExternalSemaphoreInfo::handle_type(
+            ExternalSemaphoreHandleType::SyncFd,
+        ))
+        .ok();
+
+    let props = phys_dev.properties();
+    let drm_node_error = match props.render_major.zip(props.render_minor) {
+        None => Some("the device doesn't report a render node".to_string()),
+        Some((major, minor)) => open(
+            format!("/dev/char/{major}:{minor}"),
+            OFlags::RDWR | OFlags::CLOEXEC,
+            Mode::empty(),
+        )
+        .err()
+        .map(|err| format!("failed to open render node {major}:{minor}: {err}")),
+    };
+
+    CapabilityReport {
+        device_name: props.device_name.clone(),
+        missing_instance_extensions,
+        missing_device_extensions,
+        missing_features,
+        dmabuf_export: memory.as_ref().is_some_and(|v| v.exportable),
+        dmabuf_import: memory.as_ref().is_some_and(|v| v.importable),
+        sync_fd_export: semaphore.as_ref().is_some_and(|v| v.exportable),
+        sync_fd_import: semaphore.as_ref().is_some_and(|v| v.importable),
+        modifier_query: phys_dev
+            .supported_extensions()
+            .ext_image_drm_format_modifier,
+        drm_node_error,
+    }
+}
+
+/// Something cme worked around or gave up on
+#[derive(Debug, Clone)]
+pub enum DiagnosticEvent {
+    /// a format the server advertised couldn't be used
+    FormatSkipped(UnmappedFormat),
+    /// a modifier was left out when creating a dmatex, once all others are rejected
+    /// only linear is left
+    ModifierRejected {
+        format: Format,
+        modifier: u64,
+        reason: ModifierRejection,
+    },
+    /// the modifier a dmatex was created with
+    ModifierChosen {
+        format: Format,
+        modifier: u64,
+        planes: u32,
+    },
+    /// the driver would rather share memory with other images, dmatexes get their own anyway
+    NoDedicatedAllocation { format: Format },
+    /// the driver needs a dedicated allocation, the image can't share memory with others
+    DedicatedAllocationRequired { format: Format },
+    /// none of the memory types in `memory_type_bits` can hold a dmatex
+    NoMemoryType { memory_type_bits: u32 },
+    /// dmabuf memory uses a type that isn't plain device local, which can be slow
+    NotDeviceLocal { memory_type: u32 },
+    /// allocating the memory of a dmatex or dma buffer failed
+    AllocationFailed { error: String },
+    /// the server already used the id of a new dmatex, the import is retried with a fresh one
+    IdCollision { dmatex_id: u64 },
+    /// the device lacks an optional capability, a slower path is used instead
+    CapabilityMissing(DeviceCapability),
+    /// a swapchain ran out of memory and got by with fewer images, see
+    /// [`SwapchainBuilder::min_images`](crate::swapchain::SwapchainBuilder::min_images)
+    FewerSwapchainImages { requested: usize, created: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModifierRejection {
+    /// vulkan and the server disagree on the plane count
+    PlaneCount { vulkan: u32, server: u32 },
+    /// the driver chose the modifier with a different plane count than it advertised
+    DriverPlaneCount { planes: u32, expected: Option<u32> },
+    /// the array layers aren't evenly spaced, the server only gets a single array pitch
+    NonUniformLayerStride,
+    /// a plane's row pitch isn't a multiple of the
+    /// [requested alignment](crate::dmatex::DmatexBuilder::row_pitch_alignment)
+    RowPitchAlignment { row_pitch: u64, alignment: u64 },
+}
+
+impl fmt::Display for DiagnosticEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FormatSkipped(format) => write!(f, "skipping server format: {format}"),
+            Self::ModifierRejected {
+                format,
+                modifier,
+                reason,
+            } => {
+                write!(f, "skipping modifier {modifier} of {format:?}: ")?;
+                match reason {
+                    ModifierRejection::PlaneCount { vulkan, server } => write!(
+                        f,
+                        "vulkan uses {vulkan} planes, the server expects {server}"
+                    ),
+                    ModifierRejection::DriverPlaneCount { planes, expected } => write!(
+                        f,
+                        "driver chose it with {planes} planes, expected {expected:?}"
+                    ),
+                    ModifierRejection::NonUniformLayerStride => {
+                        write!(f, "array layers don't have a uniform stride")
+                    }
+                    ModifierRejection::RowPitchAlignment {
+                        row_pitch,
+                        alignment,
+                    } => write!(
+                        f,
+                        "row pitch {row_pitch} isn't a multiple of {alignment} bytes"
+                    ),
+                }
+            }
+            Self::ModifierChosen {
+                format,
+                modifier,
+                planes,
+            } => write!(f, "modifier {modifier} of {format:?} needs {planes} planes"),
+            Self::NoDedicatedAllocation { format } => write!(
+                f,
+                "{format:?} dmatex image doesn't want a dedicated alloc, too bad"
+            ),
+            Self::DedicatedAllocationRequired { format } => write!(
+                f,
+                "{format:?} dmatex image needs a dedicated alloc, not using shared memory"
+            ),
+            Self::NoMemoryType { memory_type_bits } => write!(
+                f,
+                "unable to find memory type for dmatex plane in {memory_type_bits:#b}"
+            ),
+            Self::NotDeviceLocal { memory_type } => write!(
+                f,
+                "no plain device local memory type for dmabuf memory, using type {memory_type}"
+            ),
+            Self::AllocationFailed { error } => write!(f, "failed to allocate memory: {error}"),
+            Self::IdCollision { dmatex_id } => {
+                write!(f, "dmatex id {dmatex_id} is taken, retrying with a new one")
+            }
+            Self::CapabilityMissing(capability) => match capability {
+                DeviceCapability::SyncFdImport => {
+                    write!(f, "no sync fd import, waiting for releases on the cpu")
+                }
+                DeviceCapability::SyncFdExport => {
+                    write!(f, "no sync fd export, signalling acquire points on the cpu")
+                }
+                DeviceCapability::DisjointPlanes => {
+                    write!(
+                        f,
+                        "no disjoint image support, using one memory for all planes"
+                    )
+                }
+                DeviceCapability::ModifierQuery => write!(
+                    f,
+                    "VK_EXT_image_drm_format_modifier is not enabled, creating linear images"
+                ),
+            },
+            Self::FewerSwapchainImages { requested, created } => write!(
+                f,
+                "out of memory for swapchain images, using {created} instead of {requested}"
+            ),
+        }
+    }
+}
+
+/// Receives every [`DiagnosticEvent`], from whatever thread it happened on
+pub trait DiagnosticsSink: Send + Sync + 'static {
+    fn event(&self, event: &DiagnosticEvent);
+}
+impl<F: Fn(&DiagnosticEvent) + Send + Sync + 'static> DiagnosticsSink for F {
+    fn event(&self, event: &DiagnosticEvent) {
+        self(event)
+    }
+}
+
+static SINK: RwLock<Option<Arc<dyn DiagnosticsSink>>> = RwLock::new(None);
+
+/// Replaces the sink events are handed to, they're logged either way
+pub fn set_sink(sink: impl DiagnosticsSink) {
+    *SINK.write().unwrap() = Some(Arc::new(sink));
+}
+pub fn clear_sink() {
+    *SINK.write().unwrap() = None;
+}
+
+/// logs `event` and hands it to the sink
+pub(crate) fn report(event: DiagnosticEvent) {
+    match &event {
+        DiagnosticEvent::ModifierChosen { .. } => info!("{event}"),
+        DiagnosticEvent::AllocationFailed { .. } => error!("{event}"),
+        _ => warn!("{event}"),
+    }
+    let sink = SINK.read().unwrap().clone();
+    if let Some(sink) = sink {
+        sink.event(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> CapabilityReport {
+        CapabilityReport {
+            device_name: "test device".to_string(),
+            missing_instance_extensions: Vec::new(),
+            missing_device_extensions: Vec::new(),
+            missing_features: Vec::new(),
+            dmabuf_export: true,
+            dmabuf_import: true,
+            sync_fd_export: true,
+            sync_fd_import: true,
+            modifier_query: true,
+            drm_node_error: None,
+        }
+    }
+
+    #[test]
+    fn fallbacks_are_still_supported() {
+        assert!(report().is_supported());
+        assert!(report().fallbacks().is_empty());
+        let report = CapabilityReport {
+            sync_fd_export: false,
+            sync_fd_import: false,
+            modifier_query: false,
+            ..report()
+        };
+        assert!(report.is_supported());
+        assert_eq!(
+            report.fallbacks(),
+            [
+                DeviceCapability::SyncFdImport,
+                DeviceCapability::SyncFdExport,
+                DeviceCapability::ModifierQuery,
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_requirements_are_not_supported() {
+        for report in [
+            CapabilityReport {
+                dmabuf_export: false,
+                ..report()
+            },
+            CapabilityReport {
+                dmabuf_import: false,
+                ..report()
+            },
+            CapabilityReport {
+                missing_device_extensions: vec!["khr_external_memory_fd"],
+                ..report()
+            },
+            CapabilityReport {
+                drm_node_error: Some("no render node".to_string()),
+                ..report()
+            },
+        ] {
+            assert!(!report.is_supported(), "{report}");
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{fs::File, os::fd::OwnedFd, sync::Arc};
+
+use timeline_syncobj::timeline_syncobj::TimelineSyncObj;
+use vulkano::{
+    DeviceSize,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer, sys::RawBuffer},
+    device::{Device, DeviceOwned},
+    memory::{
+        DedicatedAllocation, DeviceMemory, ExternalMemoryHandleType, ExternalMemoryHandleTypes,
+        MemoryAllocateInfo, MemoryImportInfo, ResourceMemory,
+    },
+    sync::semaphore::Semaphore,
+};
+
+use crate::{
+    diagnostics::{DiagnosticEvent, report},
+    dmatex::{attach_to_timeline, exportable_semaphore, find_memory_type, timeline_wait_semaphore},
+    render_device::RenderDevice,
+};
+
+/// A buffer backed by an exported dmabuf, for sharing raw data like point clouds with sibling
+/// processes. The server has no buffer import, the fds have to be passed along some other way.
+/// Synchronized with a timeline syncobj the same way as a [`Dmatex`](crate::dmatex::Dmatex)
+pub struct DmaBuffer {
+    pub buffer: Arc<Buffer>,
+    pub timeline: TimelineSyncObj,
+    dmabuf: OwnedFd,
+}
+
+impl DmaBuffer {
+    pub fn new(
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        size: DeviceSize,
+        usage: BufferUsage,
+    ) -> Self {
+        let raw_buffer = raw_buffer(dev, size, usage);
+        let req = raw_buffer.memory_requirements();
+        let type_index = find_memory_type(dev.physical_device(), req.memory_type_bits)
+            .expect("unable to find memory type for dma buffer");
+        let mem = DeviceMemory::allocate(
+            dev.clone(),
+            MemoryAllocateInfo {
+                allocation_size: req.layout.size(),
+                memory_type_index: type_index,
+                dedicated_allocation: Some(DedicatedAllocation::Buffer(&raw_buffer)),
+                export_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
+                ..MemoryAllocateInfo::default()
+            },
+        )
+        .inspect_err(|err| {
+            report(DiagnosticEvent::AllocationFailed {
+                error: err.to_string(),
+            })
+        })
+        .unwrap();
+        let dmabuf = mem
+            .export_fd(ExternalMemoryHandleType::DmaBuf)
+            .unwrap()
+            .into();
+        let timeline = TimelineSyncObj::create(render_dev.drm_node()).unwrap();
+        // nothing to wait for before the first use
+        unsafe { timeline.signal(0) }.unwrap();
+        Self {
+            buffer: bind(raw_buffer, mem),
+            timeline,
+            dmabuf,
+        }
+    }
+    /// Imports a dmabuf exported by [`Self::export_fd`] in another process, `size` has to match
+    pub fn import(
+        dev: &Arc<Device>,
+        dmabuf: OwnedFd,
+        size: DeviceSize,
+        timeline: TimelineSyncObj,
+        usage: BufferUsage,
+    ) -> Self {
+        let raw_buffer = raw_buffer(dev, size, usage);
+        let req = raw_buffer.memory_requirements();
+        let type_index = find_memory_type(dev.physical_device(), req.memory_type_bits)
+            .expect("unable to find memory type for imported dma buffer");
+        // vulkan takes ownership of the fd, keep the original for exporting
+        let file = File::from(dmabuf.try_clone().unwrap());
+        let mem = unsafe {
+            DeviceMemory::import(
+                dev.clone(),
+                MemoryAllocateInfo {
+                    allocation_size: req.layout.size(),
+                    memory_type_index: type_index,
+                    dedicated_allocation: Some(DedicatedAllocation::Buffer(&raw_buffer)),
+                    ..MemoryAllocateInfo::default()
+                },
+                MemoryImportInfo::Fd {
+                    handle_type: ExternalMemoryHandleType::DmaBuf,
+                    file,
+                },
+            )
+        }
+        .unwrap();
+        Self {
+            buffer: bind(raw_buffer, mem),
+            timeline,
+            dmabuf,
+        }
+    }
+    pub fn size(&self) -> DeviceSize {
+        self.buffer.size()
+    }
+    pub fn subbuffer(&self) -> Subbuffer<[u8]> {
+        Subbuffer::new(self.buffer.clone())
+    }
+    /// a new fd for the dmabuf, for passing to another process
+    pub fn export_fd(&self) -> std::io::Result<OwnedFd> {
+        self.dmabuf.try_clone()
+    }
+    /// a new fd for the timeline syncobj, for passing to another process
+    pub fn export_timeline(&self) -> Result<OwnedFd, rustix::io::Errno> {
+        self.timeline.export()
+    }
+    /// a semaphore that's signalled once timeline `point` is reached
+    pub fn wait_semaphore(&self, point: u64) -> Arc<Semaphore> {
+        timeline_wait_semaphore(self.buffer.device(), &self.timeline, point)
+    }
+    /// a semaphore for a submission accessing the buffer, pass it to [`Self::attach_signal`]
+    /// after submitting
+    pub fn signal_semaphore(&self) -> Arc<Semaphore> {
+        exportable_semaphore(self.buffer.device())
+    }
+    /// makes timeline `point` wait for the submission signalling `semaphore`
+    pub fn attach_signal(&self, semaphore: &Semaphore, point: u64) {
+        attach_to_timeline(&self.timeline, semaphore, point);
+    }
+}
+
+fn raw_buffer(dev: &Arc<Device>, size: DeviceSize, usage: BufferUsage) -> RawBuffer {
+    RawBuffer::new(
+        dev.clone(),
+        BufferCreateInfo {
+            size,
+            usage,
+            external_memory_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+fn bind(raw_buffer: RawBuffer, mem: DeviceMemory) -> Arc<Buffer> {
+    match raw_buffer.bind_memory(ResourceMemory::new_dedicated(mem)) {
+        Ok(v) => Arc::new(v),
+        Err((err, _, _)) => panic!("failed to bind buffer mem: {err}"),
+    }
+}
@@ -1,35 +1,92 @@
-use std::{os::fd::OwnedFd, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::File,
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
 
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use rustix::io::Errno;
+use smallvec::SmallVec;
 use stardust_xr_fusion::{
-    ClientHandle,
-    drawable::{DmatexPlane, DmatexSize, import_dmatex},
+    drawable::{DmatexPlane, DmatexSize, DmatexSubmitInfo},
+    node::NodeResult,
 };
+use thiserror::Error;
 use timeline_syncobj::timeline_syncobj::TimelineSyncObj;
-use tracing::{error, info, warn};
 use vulkano::{
-    device::{Device, DeviceExtensions, DeviceFeatures},
+    Validated, ValidationError, VulkanError,
+    command_buffer::{
+        AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferSubmitInfo, CommandBufferUsage,
+        SemaphoreSubmitInfo, SubmitInfo, allocator::StandardCommandBufferAllocator,
+    },
+    device::{
+        Device, DeviceExtensions, DeviceFeatures, DeviceOwned, Queue, physical::PhysicalDevice,
+    },
+    format::{ClearColorValue, Format, FormatFeatures},
     image::{
-        Image, ImageCreateFlags, ImageCreateInfo, ImageTiling, ImageType, ImageUsage, sys::RawImage,
+        Image, ImageAspect, ImageCreateFlags, ImageCreateInfo, ImageMemory, ImageTiling, ImageType,
+        ImageUsage, SubresourceLayout,
+        sys::RawImage,
+        view::{ComponentSwizzle, ImageView, ImageViewCreateInfo, ImageViewType},
     },
     instance::InstanceExtensions,
     memory::{
         DedicatedAllocation, DeviceMemory, ExternalMemoryHandleType, ExternalMemoryHandleTypes,
-        MemoryAllocateInfo, MemoryPropertyFlags, ResourceMemory,
+        MemoryAllocateInfo, MemoryImportInfo, MemoryPropertyFlags, MemoryRequirements,
+        ResourceMemory,
+        allocator::{
+            AllocationType, GenericMemoryAllocatorCreateInfo, MemoryAllocator,
+            StandardMemoryAllocator,
+        },
+    },
+    sync::{
+        Sharing,
+        fence::{Fence, FenceCreateInfo},
+        semaphore::{
+            ExternalSemaphoreHandleType, ExternalSemaphoreHandleTypes, ImportSemaphoreFdInfo,
+            Semaphore, SemaphoreCreateInfo, SemaphoreImportFlags,
+        },
     },
 };
 
-use crate::{format::DmatexFormat, render_device::RenderDevice};
+use crate::{
+    backend::{Backend, BackendFuture, DmatexImport, ImportError},
+    caps::{DeviceCapability, DeviceCaps},
+    debug::set_object_name,
+    diagnostics::{DiagnosticEvent, ModifierRejection, report},
+    dmatex_handle::{DmatexHandle, PlaneFd},
+    format::{ColorSpace, DmatexFormat, VulkanoFormatExtension},
+    fourcc::is_opaque,
+    render_device::RenderDevice,
+    sync::{SyncProvider, SyncProviderFactory, timeline_syncobj},
+};
 
 pub struct Dmatex {
     pub image: Arc<Image>,
-    pub timeline: TimelineSyncObj,
+    pub timeline: Box<dyn SyncProvider>,
     pub dmatex_id: u64,
-    _client: Arc<ClientHandle>,
+    name: Option<String>,
+    color_space: Option<ColorSpace>,
+    fourcc: u32,
+    drm_modifier: u64,
+    /// the fourcc has no alpha channel, views return 1 for alpha instead of undefined values
+    opaque: bool,
+    /// what the server was told, see [`Srgbness`]
+    srgb: bool,
+    /// views by array layer, None is the view of the whole image
+    views: Mutex<HashMap<Option<u32>, Arc<ImageView>>>,
+    /// the memory type of every memory bound to the image
+    memory_types: SmallVec<[u32; 4]>,
+    client: Arc<dyn Backend>,
 }
 impl Dmatex {
-    // TODO: error handling
-    pub fn new(
-        client: &Arc<ClientHandle>,
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
         dev: &Arc<Device>,
         render_dev: &RenderDevice,
         size: DmatexSize,
@@ -37,136 +94,1518 @@ impl Dmatex {
         array_layers: Option<u32>,
         usage: ImageUsage,
     ) -> Self {
-        let modifiers = dev
+        let mut builder = Self::builder(client, dev, render_dev, size, format, usage);
+        builder.array_layers = array_layers;
+        builder.build()
+    }
+    /// A cube map with 6 square `edge`x`edge` faces, stored as array layers in the order
+    /// +x, -x, +y, -y, +z, -z
+    pub fn new_cube<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        edge: u32,
+        format: &DmatexFormat,
+        usage: ImageUsage,
+    ) -> Self {
+        Self::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim2D([edge, edge].into()),
+            format,
+            usage,
+        )
+        .cube_compatible()
+        .build()
+    }
+    /// A volume texture, the server gets the depth slice pitch of every plane
+    pub fn new_3d<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        extent: [u32; 3],
+        format: &DmatexFormat,
+        usage: ImageUsage,
+    ) -> Self {
+        Self::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim3D(extent.into()),
+            format,
+            usage,
+        )
+        .build()
+    }
+    pub fn builder<'a, B: Backend>(
+        client: &Arc<B>,
+        dev: &'a Arc<Device>,
+        render_dev: &'a RenderDevice,
+        size: DmatexSize,
+        format: &'a DmatexFormat,
+        usage: ImageUsage,
+    ) -> DmatexBuilder<'a> {
+        DmatexBuilder::new(client.clone(), dev, render_dev, size, format, usage)
+    }
+    /// the debug label set with [`DmatexBuilder::name`]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    /// the colorspace set with [`DmatexBuilder::color_space`]
+    pub fn color_space(&self) -> Option<ColorSpace> {
+        self.color_space
+    }
+    /// whether the server decodes this dmatex as srgb, see [`DmatexBuilder::srgbness`]
+    pub fn is_srgb(&self) -> bool {
+        self.srgb
+    }
+    /// Resolves once the server handled the import, with the error if it rejected it. Await it
+    /// before the first submit to be sure the server knows this dmatex
+    pub fn confirmed(&self) -> BackendFuture<(), ImportError> {
+        self.client.clone().confirm_dmatex(self.dmatex_id)
+    }
+    /// Tells the server to forget this dmatex, only do this once it released every frame.
+    /// Dropping doesn't unregister, the id stays taken until the client disconnects
+    pub fn unregister(&self) -> NodeResult<()> {
+        self.client.clone().unregister_dmatex(self.dmatex_id)
+    }
+    /// What was negotiated with the server for this dmatex, for logging and bug reports
+    pub fn descriptor(&self) -> DmatexDescriptor {
+        let planes = self
+            .image
+            .drm_format_modifier()
+            .map(|(_, planes)| planes)
+            .unwrap_or(1);
+        DmatexDescriptor {
+            size: match self.image.image_type() {
+                ImageType::Dim1d => DmatexSize::Dim1D(self.image.extent()[0]),
+                ImageType::Dim2d => {
+                    let [x, y, _] = self.image.extent();
+                    DmatexSize::Dim2D([x, y].into())
+                }
+                ImageType::Dim3d => DmatexSize::Dim3D(self.image.extent().into()),
+            },
+            fourcc: self.fourcc,
+            drm_modifier: self.drm_modifier,
+            format: format!("{:?}", self.image.format()),
+            array_layers: self.image.array_layers(),
+            memory: self
+                .memory_types
+                .iter()
+                .map(|&memory_type_index| {
+                    let memory_type = &self
+                        .image
+                        .device()
+                        .physical_device()
+                        .memory_properties()
+                        .memory_types[memory_type_index as usize];
+                    DmatexMemory {
+                        memory_type_index,
+                        heap_index: memory_type.heap_index,
+                        device_local: memory_type
+                            .property_flags
+                            .contains(MemoryPropertyFlags::DEVICE_LOCAL),
+                        host_visible: memory_type
+                            .property_flags
+                            .contains(MemoryPropertyFlags::HOST_VISIBLE),
+                    }
+                })
+                .collect(),
+            planes: PlaneExport::for_image(&self.image)
+                .memory_indices(planes)
+                .enumerate()
+                .filter_map(|(i, memory)| {
+                    // linear images without modifiers only have the color aspect
+                    let aspect = if self.image.tiling() == ImageTiling::DrmFormatModifier {
+                        memory_plane_aspect(i as u32)
+                    } else {
+                        ImageAspect::Color
+                    };
+                    let layout = self.image.subresource_layout(aspect, 0, 0).ok()?;
+                    Some(DmatexPlaneLayout {
+                        offset: self.memory_offset(memory) + layout.offset,
+                        row_pitch: layout.row_pitch,
+                        array_pitch: layout.array_pitch,
+                        depth_pitch: layout.depth_pitch,
+                    })
+                })
+                .collect(),
+        }
+    }
+    /// where the image starts in memory `index`, not 0 for [shared memory](SharedMemory)
+    fn memory_offset(&self, index: usize) -> u64 {
+        match self.image.memory() {
+            ImageMemory::Normal(memory) => memory[index].offset(),
+            _ => unreachable!("dmatexes are always bound to normal memory"),
+        }
+    }
+    /// A view of all layers, cube maps get a cube view. Alpha reads as 1 for formats without
+    /// alpha like `Xrgb8888`, unless the image is usable as an attachment or storage image.
+    /// The view is created once and cached
+    pub fn create_view(&self) -> Arc<ImageView> {
+        self.cached_view(None)
+    }
+    /// the highest point signalled on the timeline so far
+    pub fn current_point(&self) -> u64 {
+        self.timeline.current_point()
+    }
+    pub fn is_signalled(&self, point: u64) -> bool {
+        self.timeline
+            .blocking_wait(point, Some(Instant::now()))
+            .is_ok()
+    }
+    /// waits until `point` is signalled, `deadline` None waits forever
+    pub fn wait(&self, point: u64, deadline: Option<Instant>) -> Result<(), Errno> {
+        self.timeline.blocking_wait(point, deadline)
+    }
+    /// Signals `point` from the cpu, for engines scheduling their own submissions
+    ///
+    /// # Safety
+    /// the server reads the image once the acquire point of a submitted frame is signalled
+    /// and reuses nothing before the release point, so everything writing the image has to be
+    /// done. Points have to increase, and the ones a swapchain hands out must be left to it
+    pub unsafe fn signal(&self, point: u64) -> Result<(), Errno> {
+        unsafe { self.timeline.signal(point) }
+    }
+    /// a sync file signalled together with `point`, e.g. for a video encoder reading the
+    /// image once rendering is done
+    pub fn export_sync_file(&self, point: u64) -> Result<OwnedFd, Errno> {
+        self.timeline.export_sync_file_point(point)
+    }
+    /// Signals `point` once `sync_file` is signalled, e.g. to let a frame wait for external
+    /// gpu work on the same buffer
+    ///
+    /// # Safety
+    /// same as [`Self::signal`], the work behind `sync_file` counts as done at `point`
+    pub unsafe fn import_sync_file(
+        &self,
+        sync_file: BorrowedFd<'_>,
+        point: u64,
+    ) -> Result<(), Errno> {
+        self.timeline.import_sync_file_point(sync_file, point)
+    }
+    /// A 2D view of a single array layer, e.g. one eye of a stereo dmatex
+    pub fn create_layer_view(&self, layer: u32) -> Arc<ImageView> {
+        self.cached_view(Some(layer))
+    }
+    fn cached_view(&self, layer: Option<u32>) -> Arc<ImageView> {
+        self.views
+            .lock()
+            .unwrap()
+            .entry(layer)
+            .or_insert_with(|| {
+                let mut info = ImageViewCreateInfo::from_image(&self.image);
+                if let Some(layer) = layer {
+                    info.view_type = ImageViewType::Dim2d;
+                    info.subresource_range.array_layers = layer..layer + 1;
+                } else if self
+                    .image
+                    .flags()
+                    .contains(ImageCreateFlags::CUBE_COMPATIBLE)
+                {
+                    info.view_type = if self.image.array_layers() == 6 {
+                        ImageViewType::Cube
+                    } else {
+                        ImageViewType::CubeArray
+                    };
+                }
+                // attachments and storage images need the identity mapping
+                let attachment = self.image.usage().intersects(
+                    ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::INPUT_ATTACHMENT
+                        | ImageUsage::STORAGE,
+                );
+                if self.opaque && !attachment {
+                    info.component_mapping.a = ComponentSwizzle::One;
+                }
+                let view = ImageView::new(self.image.clone(), info).unwrap();
+                if let Some(name) = &self.name {
+                    match layer {
+                        Some(layer) => set_object_name(&view, &format!("{name} layer {layer}")),
+                        None => set_object_name(&view, name),
+                    }
+                }
+                view
+            })
+            .clone()
+    }
+}
+
+/// Whether the server decodes a dmatex's values as srgb
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Srgbness {
+    /// srgb exactly when the vulkan format is an srgb one
+    #[default]
+    Auto,
+    /// linear values, also for srgb formats
+    Linear,
+    /// srgb encoded values, also for unorm formats with an srgb variant that are written
+    /// with already encoded values
+    Srgb,
+}
+impl Srgbness {
+    /// the srgb flag sent to the server, None if [`Self::Srgb`] is requested for a format
+    /// without an srgb variant
+    pub fn resolve(self, format: Format) -> Option<bool> {
+        match self {
+            Self::Auto => Some(format.is_srgb()),
+            Self::Linear => Some(false),
+            Self::Srgb => (format.is_srgb() || format.to_srgb().is_some()).then_some(true),
+        }
+    }
+}
+
+/// Size, format and memory layout of a dmatex
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmatexDescriptor {
+    pub size: DmatexSize,
+    pub fourcc: u32,
+    pub drm_modifier: u64,
+    /// the vulkan format's name
+    pub format: String,
+    pub array_layers: u32,
+    /// one entry per memory allocation, usually one for the whole image
+    pub memory: Vec<DmatexMemory>,
+    pub planes: Vec<DmatexPlaneLayout>,
+}
+
+/// The memory type picked for a memory allocation of a dmatex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmatexMemory {
+    pub memory_type_index: u32,
+    pub heap_index: u32,
+    pub device_local: bool,
+    pub host_visible: bool,
+}
+
+/// Memory layout of a single plane, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmatexPlaneLayout {
+    pub offset: u64,
+    pub row_pitch: u64,
+    pub array_pitch: Option<u64>,
+    pub depth_pitch: Option<u64>,
+}
+
+pub struct DmatexBuilder<'a> {
+    client: Arc<dyn Backend>,
+    dev: &'a Arc<Device>,
+    render_dev: &'a RenderDevice,
+    size: DmatexSize,
+    format: &'a DmatexFormat,
+    array_layers: Option<u32>,
+    usage: ImageUsage,
+    name: Option<String>,
+    clear_queue: Option<Arc<Queue>>,
+    initial_clear: bool,
+    sharing: Sharing<SmallVec<[u32; 4]>>,
+    color_space: Option<ColorSpace>,
+    flags: ImageCreateFlags,
+    sync: Option<SyncProviderFactory>,
+    srgbness: Srgbness,
+    shared_memory: Option<Arc<SharedMemory>>,
+    row_pitch_alignment: Option<u64>,
+}
+impl<'a> DmatexBuilder<'a> {
+    pub(crate) fn new(
+        client: Arc<dyn Backend>,
+        dev: &'a Arc<Device>,
+        render_dev: &'a RenderDevice,
+        size: DmatexSize,
+        format: &'a DmatexFormat,
+        usage: ImageUsage,
+    ) -> Self {
+        Self {
+            client,
+            dev,
+            render_dev,
+            size,
+            format,
+            array_layers: None,
+            usage,
+            name: None,
+            clear_queue: None,
+            initial_clear: true,
+            sharing: Sharing::Exclusive,
+            color_space: None,
+            flags: ImageCreateFlags::empty(),
+            sync: None,
+            srgbness: Srgbness::Auto,
+            shared_memory: None,
+            row_pitch_alignment: None,
+        }
+    }
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = Some(array_layers);
+        self
+    }
+    /// Debug label applied to the image, its memory and the semaphores used when submitting,
+    /// only has an effect when `ext_debug_utils` is enabled on the instance
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    /// queue used for the [initial clear](Self::initial_clear)
+    pub fn clear_queue(mut self, queue: &Arc<Queue>) -> Self {
+        self.clear_queue = Some(queue.clone());
+        self
+    }
+    /// Clears the image to transparent with the [clear queue](Self::clear_queue) so the
+    /// server never shows uninitialized memory, timeline point 0 is signalled once the clear
+    /// is done. On by default, but only happens when a clear queue was set and never for
+    /// compressed formats, those have to be fully uploaded before the first submit
+    pub fn initial_clear(mut self, enabled: bool) -> Self {
+        self.initial_clear = enabled;
+        self
+    }
+    /// Lets the listed queue families use the image without ownership transfers, the render
+    /// queue's family has to be one of them
+    pub fn concurrent_sharing(
+        mut self,
+        queue_family_indices: impl IntoIterator<Item = u32>,
+    ) -> Self {
+        self.sharing = Sharing::Concurrent(queue_family_indices.into_iter().collect());
+        self
+    }
+    /// How the image's values are meant to be interpreted, passed on to the [`Backend`].
+    /// Stardust servers can't be told about a colorspace yet and composite everything as
+    /// [`ColorSpace::Srgb`], importing with any other one logs a warning. Hdr content has to
+    /// be tonemapped by the client until the protocol carries it
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = Some(color_space);
+        self
+    }
+    /// Allows cube views of the image, it has to be square and 2D. Sets the array layers to 6
+    /// unless a multiple of 6 was set already for a cube array
+    pub fn cube_compatible(mut self) -> Self {
+        self.flags |= ImageCreateFlags::CUBE_COMPATIBLE;
+        if self.array_layers.is_none_or(|v| v % 6 != 0) {
+            self.array_layers = Some(6);
+        }
+        self
+    }
+    /// Creates multi-planar images like yuv ones with separate memory for every plane, which
+    /// some drivers need or are faster with. Only modifiers that support it are considered
+    pub fn disjoint(mut self, enabled: bool) -> Self {
+        self.flags = if enabled {
+            self.flags.union(ImageCreateFlags::DISJOINT)
+        } else {
+            self.flags.difference(ImageCreateFlags::DISJOINT)
+        };
+        self
+    }
+    /// Creates the dmatex's timeline with `factory` instead of as a drm timeline syncobj
+    pub fn sync_provider(mut self, factory: SyncProviderFactory) -> Self {
+        self.sync = Some(factory);
+        self
+    }
+    /// Whether the server decodes the values as srgb, [`Srgbness::Auto`] by default
+    pub fn srgbness(mut self, srgbness: Srgbness) -> Self {
+        self.srgbness = srgbness;
+        self
+    }
+    /// Only uses layouts where the row pitch of every plane is a multiple of `alignment`
+    /// bytes, e.g. for video encoders reading the same dmabuf. Modifiers the driver lays out
+    /// differently are skipped, if none is left a linear image with padded rows is created
+    pub fn row_pitch_alignment(mut self, alignment: u64) -> Self {
+        assert!(alignment > 0, "the row pitch alignment can't be 0");
+        self.row_pitch_alignment = Some(alignment);
+        self
+    }
+    /// suballocates the image from `memory` instead of giving it its own allocation
+    pub(crate) fn shared_memory(mut self, memory: &Arc<SharedMemory>) -> Self {
+        self.shared_memory = Some(memory.clone());
+        self
+    }
+    /// whether [`Self::build`] will clear the image and signal timeline point 0
+    pub(crate) fn clears(&self) -> bool {
+        self.initial_clear && self.clear_queue.is_some() && !self.format.is_compressed()
+    }
+    /// Panics on failure, see [`Self::try_build`]
+    pub fn build(self) -> Dmatex {
+        self.try_build().unwrap()
+    }
+    /// Like [`Self::build`], but returns the error if the options don't fit the format, the
+    /// driver can't create the image, the server doesn't accept the import or there's no
+    /// memory left for it
+    pub fn try_build(self) -> Result<Dmatex, DmatexImportError> {
+        let clear_queue = self.clears().then_some(self.clear_queue).flatten();
+        let Self {
+            client,
+            dev,
+            render_dev,
+            size,
+            format,
+            array_layers,
+            usage,
+            name,
+            sharing,
+            color_space,
+            mut flags,
+            sync,
+            srgbness,
+            shared_memory,
+            row_pitch_alignment,
+            ..
+        } = self;
+        let srgb = srgbness
+            .resolve(format.vk_format())
+            .ok_or(DmatexImportError::NoSrgbVariant(format.vk_format()))?;
+        if flags.contains(ImageCreateFlags::CUBE_COMPATIBLE)
+            && !matches!(size, DmatexSize::Dim2D(v) if v.x == v.y)
+        {
+            return Err(DmatexImportError::NonSquareCube);
+        }
+        let caps = DeviceCaps::new(&dev);
+        if flags.intersects(ImageCreateFlags::DISJOINT) {
+            if format.vk_format().planes().len() < 2 {
+                return Err(DmatexImportError::NotMultiPlanar(format.vk_format()));
+            }
+            if !DeviceCaps::check(caps.disjoint_planes, DeviceCapability::DisjointPlanes) {
+                flags = flags.difference(ImageCreateFlags::DISJOINT);
+            }
+        }
+        let disjoint = flags.intersects(ImageCreateFlags::DISJOINT);
+        if matches!(size, DmatexSize::Dim3D(_)) && array_layers.is_some_and(|v| v != 1) {
+            return Err(DmatexImportError::LayeredVolume);
+        }
+        let usage = if clear_queue.is_some() {
+            usage | ImageUsage::TRANSFER_DST
+        } else {
+            usage
+        };
+        // the server expects a fixed plane count per modifier, a different one corrupts
+        // the image on its side
+        let mut modifiers = dev
             .physical_device()
             .format_properties(format.vk_format())
-            .unwrap()
+            .map_err(DmatexImportError::Validation)?
             .drm_format_modifier_properties
             .into_iter()
-            .map(|v| v.drm_format_modifier)
-            .filter(|modifier| format.variants().iter().any(|v| v.modifier == *modifier))
+            .filter(|props| {
+                let Some(variant) = format
+                    .variants()
+                    .iter()
+                    .find(|v| v.modifier == props.drm_format_modifier)
+                else {
+                    return false;
+                };
+                if variant.planes != props.drm_format_modifier_plane_count {
+                    report(DiagnosticEvent::ModifierRejected {
+                        format: format.vk_format(),
+                        modifier: props.drm_format_modifier,
+                        reason: ModifierRejection::PlaneCount {
+                            vulkan: props.drm_format_modifier_plane_count,
+                            server: variant.planes,
+                        },
+                    });
+                    return false;
+                }
+                !disjoint
+                    || props
+                        .drm_format_modifier_tiling_features
+                        .intersects(FormatFeatures::DISJOINT)
+            })
+            .map(|props| props.drm_format_modifier)
             .collect::<Vec<_>>();
+        let create_info = |tiling, drm_format_modifiers| ImageCreateInfo {
+            flags,
+            image_type: image_type(&size),
+            format: format.vk_format(),
+            view_formats: vec![],
+            extent: image_extent(&size),
+            array_layers: array_layers.unwrap_or(1),
+            tiling,
+            usage,
+            sharing: sharing.clone(),
+            drm_format_modifiers,
+            external_memory_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
+            ..Default::default()
+        };
+        let modifier_query =
+            DeviceCaps::check(caps.modifier_query, DeviceCapability::ModifierQuery);
+        let (raw_image, modifier, planes) = if !modifier_query {
+            // the driver picks the layout of linear images itself, always a single plane
+            if !format
+                .variants()
+                .iter()
+                .any(|v| v.modifier == u64::from(DrmModifier::Linear) && v.planes == 1)
+            {
+                return Err(DmatexImportError::NoLinearLayout(format.vk_format()));
+            }
+            let raw_image = RawImage::new(dev.clone(), create_info(ImageTiling::Linear, vec![]))?;
+            (raw_image, DrmModifier::Linear.into(), 1)
+        } else {
+            loop {
+                if modifiers.is_empty()
+                    && let Some(alignment) = row_pitch_alignment
+                {
+                    break aligned_linear_image(
+                        dev,
+                        format,
+                        || {
+                            create_info(
+                                ImageTiling::DrmFormatModifier,
+                                vec![DrmModifier::Linear.into()],
+                            )
+                        },
+                        alignment,
+                    )?;
+                }
+                if modifiers.is_empty() {
+                    return Err(DmatexImportError::NoModifier(format.vk_format()));
+                }
+                let raw_image = RawImage::new(
+                    dev.clone(),
+                    create_info(ImageTiling::DrmFormatModifier, modifiers.clone()),
+                )?;
+                let (modifier, planes) = raw_image.drm_format_modifier().unwrap();
+                let expected = format
+                    .variants()
+                    .iter()
+                    .find(|v| v.modifier == modifier)
+                    .map(|v| v.planes);
+                let reason = if expected != Some(planes) {
+                    // the driver can still pick a different layout than it advertised,
+                    // try again without that modifier until only linear is left
+                    ModifierRejection::DriverPlaneCount { planes, expected }
+                } else if !has_uniform_layer_stride(&raw_image, planes) {
+                    // the server only gets a single array pitch per plane
+                    ModifierRejection::NonUniformLayerStride
+                } else if let Some(alignment) = row_pitch_alignment
+                    && let Some(row_pitch) = unaligned_row_pitch(&raw_image, planes, alignment)
+                {
+                    ModifierRejection::RowPitchAlignment {
+                        row_pitch,
+                        alignment,
+                    }
+                } else {
+                    break (raw_image, modifier, planes);
+                };
+                report(DiagnosticEvent::ModifierRejected {
+                    format: format.vk_format(),
+                    modifier,
+                    reason,
+                });
+                modifiers.retain(|v| *v != modifier);
+            }
+        };
+        let mem_reqs = raw_image.memory_requirements();
+        report(DiagnosticEvent::ModifierChosen {
+            format: format.vk_format(),
+            modifier,
+            planes,
+        });
+        let shared_memory = shared_memory.filter(|_| {
+            // the planes of disjoint images need their own memory anyway
+            if disjoint {
+                return false;
+            }
+            if mem_reqs[0].requires_dedicated_allocation {
+                report(DiagnosticEvent::DedicatedAllocationRequired {
+                    format: format.vk_format(),
+                });
+                return false;
+            }
+            true
+        });
+        let mems = match shared_memory {
+            Some(shared) => shared.allocate(dev, &mem_reqs[0]).map(|v| vec![v]),
+            None => mem_reqs
+                .iter()
+                .map(|v| {
+                    let wants_decicated =
+                        v.prefers_dedicated_allocation || v.requires_dedicated_allocation;
+                    if !wants_decicated && !disjoint {
+                        report(DiagnosticEvent::NoDedicatedAllocation {
+                            format: format.vk_format(),
+                        });
+                    }
+                    let Some(type_index) =
+                        find_memory_type(dev.physical_device(), v.memory_type_bits)
+                    else {
+                        report(DiagnosticEvent::NoMemoryType {
+                            memory_type_bits: v.memory_type_bits,
+                        });
+                        return None;
+                    };
+                    vulkano::memory::DeviceMemory::allocate(
+                        dev.clone(),
+                        MemoryAllocateInfo {
+                            allocation_size: v.layout.size(),
+                            memory_type_index: type_index,
+                            // the planes of disjoint images can't have dedicated memory
+                            dedicated_allocation: (!disjoint)
+                                .then_some(DedicatedAllocation::Image(&raw_image)),
+                            export_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
+                            ..MemoryAllocateInfo::default()
+                        },
+                    )
+                    .inspect_err(|err| {
+                        report(DiagnosticEvent::AllocationFailed {
+                            error: err.to_string(),
+                        })
+                    })
+                    .ok()
+                    .map(ResourceMemory::new_dedicated)
+                })
+                .collect::<Option<Vec<_>>>(),
+        };
+        let mems = mems.ok_or(DmatexImportError::OutOfMemory)?;
+        let memory_types = mems
+            .iter()
+            .map(|v| v.device_memory().memory_type_index())
+            .collect();
+        if let Some(name) = &name {
+            for (i, mem) in mems.iter().enumerate() {
+                set_object_name(&**mem.device_memory(), &format!("{name} memory {i}"));
+            }
+        }
+        // shared memory is exported whole, the planes point at the image's part of it
+        let fds = mems
+            .iter()
+            .map(|v| {
+                v.device_memory()
+                    .export_fd(ExternalMemoryHandleType::DmaBuf)
+                    .map(|fd| (fd, v.offset()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let image = raw_image.bind_memory(mems).map_err(|(err, _, _)| err)?;
+        if let Some(name) = &name {
+            set_object_name(&image, name);
+        }
+        let image = Arc::new(image);
+        let timeline = match &sync {
+            Some(factory) => factory(render_dev),
+            None => timeline_syncobj(render_dev),
+        }
+        .map_err(DmatexImportError::Timeline)?;
+        if let Some(queue) = &clear_queue {
+            clear_image(queue, &image, &*timeline, caps.sync_fd_export)?;
+        }
+        // `planes` is the server's plane count for the modifier, checked when picking it
+        let export = PlaneExport::for_image(&image);
+        if export == PlaneExport::PerPlaneMemory && fds.len() != planes as usize {
+            return Err(DmatexImportError::PlaneCount {
+                expected: planes as usize,
+                got: fds.len(),
+            });
+        }
+        let layouts = export
+            .memory_indices(planes)
+            .enumerate()
+            .map(|(plane, memory)| {
+                // linear images without modifiers only have the color aspect
+                let aspect = if modifier_query {
+                    memory_plane_aspect(plane as u32)
+                } else {
+                    ImageAspect::Color
+                };
+                let layout = image
+                    .subresource_layout(aspect, 0, 0)
+                    .map_err(DmatexImportError::Validation)?;
+                Ok((memory, layout))
+            })
+            .collect::<Result<Vec<_>, DmatexImportError>>()?;
+        let dmatex_id = import_with_retry(&client, |dmatex_id| {
+            let planes = layouts
+                .iter()
+                .map(|(memory, layout)| {
+                    let (fd, memory_offset) = &fds[*memory];
+                    Ok(DmatexPlane {
+                        dmabuf_fd: OwnedFd::from(fd.try_clone().map_err(DmatexImportError::Dup)?)
+                            .into(),
+                        offset: (memory_offset + layout.offset) as u32,
+                        row_size: layout.row_pitch as u32,
+                        array_element_size: layout.array_pitch.unwrap_or(0) as u32,
+                        depth_slice_size: layout.depth_pitch.unwrap_or(0) as u32,
+                    })
+                })
+                .collect::<Result<Vec<_>, DmatexImportError>>()?;
+            Ok(DmatexImport {
+                dmatex_id,
+                size: size.clone(),
+                fourcc: format.fourcc(),
+                drm_modifier: modifier,
+                srgb,
+                array_layers,
+                planes,
+                timeline: timeline.export().map_err(DmatexImportError::Timeline)?,
+                color_space,
+            })
+        })?;
+
+        Ok(Dmatex {
+            image,
+            timeline,
+            dmatex_id,
+            name,
+            color_space,
+            fourcc: format.fourcc(),
+            drm_modifier: modifier,
+            opaque: format.drm_fourcc().is_some_and(is_opaque),
+            srgb,
+            views: Mutex::default(),
+            memory_types,
+            client,
+        })
+    }
+}
+
+/// how many ids an import tries before a collision is returned as error
+const IMPORT_ATTEMPTS: usize = 4;
+
+/// Imports into the server with a fresh id, and again with another one whenever the server
+/// already uses the id. `import` builds the request for an id, returns the accepted id
+fn import_with_retry(
+    client: &Arc<dyn Backend>,
+    mut import: impl FnMut(u64) -> Result<DmatexImport, DmatexImportError>,
+) -> Result<u64, DmatexImportError> {
+    for _ in 1..IMPORT_ATTEMPTS {
+        let dmatex_id = client.generate_id();
+        match client.clone().import_dmatex(import(dmatex_id)?) {
+            Err(ImportError::IdCollision(dmatex_id)) => {
+                report(DiagnosticEvent::IdCollision { dmatex_id })
+            }
+            result => return result.map(|()| dmatex_id).map_err(Into::into),
+        }
+    }
+    let dmatex_id = client.generate_id();
+    client.clone().import_dmatex(import(dmatex_id)?)?;
+    Ok(dmatex_id)
+}
+
+/// One exported allocation the images of a swapchain are suballocated from, so a resize
+/// allocates once instead of once per image. The allocation is made with the first image,
+/// sized for `images` of its memory requirements
+pub(crate) struct SharedMemory {
+    images: u64,
+    allocator: Mutex<Option<Arc<StandardMemoryAllocator>>>,
+}
+impl SharedMemory {
+    pub(crate) fn new(images: usize) -> Arc<Self> {
+        Arc::new(Self {
+            images: images as u64,
+            allocator: Mutex::default(),
+        })
+    }
+    fn allocate(&self, dev: &Arc<Device>, req: &MemoryRequirements) -> Option<ResourceMemory> {
+        let Some(type_index) = find_memory_type(dev.physical_device(), req.memory_type_bits) else {
+            report(DiagnosticEvent::NoMemoryType {
+                memory_type_bits: req.memory_type_bits,
+            });
+            return None;
+        };
+        let allocator = self
+            .allocator
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| {
+                let memory_types = dev.physical_device().memory_properties().memory_types.len();
+                // padded so every image can start at an aligned offset
+                let block_size = req.layout.pad_to_alignment().size() * self.images;
+                Arc::new(StandardMemoryAllocator::new(
+                    dev.clone(),
+                    GenericMemoryAllocatorCreateInfo {
+                        block_sizes: &vec![block_size; memory_types],
+                        memory_type_bits: 1 << type_index,
+                        dedicated_allocation: false,
+                        export_handle_types: &vec![
+                            ExternalMemoryHandleTypes::DMA_BUF;
+                            memory_types
+                        ],
+                        ..Default::default()
+                    },
+                ))
+            })
+            .clone();
+        let allocation = allocator
+            .allocate_from_type(type_index, req.layout, AllocationType::NonLinear, false)
+            .inspect_err(|err| {
+                report(DiagnosticEvent::AllocationFailed {
+                    error: err.to_string(),
+                })
+            })
+            .ok()?;
+        Some(unsafe { ResourceMemory::from_allocation(allocator, allocation) })
+    }
+}
+
+/// How the planes of a dmatex map to the dmabufs of its memories
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaneExport {
+    /// a single memory for the whole image, every plane is exported with its fd at the
+    /// plane's offset
+    SingleMemory,
+    /// disjoint images, every plane has its own memory and fd
+    PerPlaneMemory,
+}
+impl PlaneExport {
+    pub fn for_image(image: &Image) -> Self {
+        Self::for_flags(image.flags())
+    }
+    /// for an image created with `flags`
+    pub fn for_flags(flags: ImageCreateFlags) -> Self {
+        if flags.intersects(ImageCreateFlags::DISJOINT) {
+            Self::PerPlaneMemory
+        } else {
+            Self::SingleMemory
+        }
+    }
+    /// the memory each of the `planes` memory planes is exported from, in plane order.
+    /// `planes` has to be the modifier's plane count the server advertised
+    pub fn memory_indices(self, planes: u32) -> impl Iterator<Item = usize> {
+        (0..planes as usize).map(move |plane| match self {
+            Self::SingleMemory => 0,
+            Self::PerPlaneMemory => plane,
+        })
+    }
+}
+
+/// whether every layer of every memory plane is `array_pitch` bytes after the previous one
+fn has_uniform_layer_stride(raw_image: &RawImage, planes: u32) -> bool {
+    let layers = raw_image.array_layers();
+    if layers == 1 {
+        return true;
+    }
+    (0..planes).all(|plane| {
+        let aspect = memory_plane_aspect(plane);
+        let Ok(first) = raw_image.subresource_layout(aspect, 0, 0) else {
+            return false;
+        };
+        let Some(array_pitch) = first.array_pitch.filter(|v| *v != 0) else {
+            return false;
+        };
+        (1..layers).all(|layer| {
+            raw_image
+                .subresource_layout(aspect, 0, layer)
+                .is_ok_and(|v| {
+                    v.offset == first.offset + layer as u64 * array_pitch
+                        && v.row_pitch == first.row_pitch
+                })
+        })
+    })
+}
+
+/// the first row pitch of a memory plane that isn't a multiple of `alignment`
+fn unaligned_row_pitch(raw_image: &RawImage, planes: u32, alignment: u64) -> Option<u64> {
+    (0..planes)
+        .filter_map(|plane| {
+            raw_image
+                .subresource_layout(memory_plane_aspect(plane), 0, 0)
+                .ok()
+        })
+        .map(|layout| layout.row_pitch)
+        .find(|row_pitch| row_pitch % alignment != 0)
+}
+
+/// A linear image with the driver's layout, but the rows of every plane padded to a
+/// multiple of `alignment`. `create_info` has to ask for the linear modifier
+fn aligned_linear_image(
+    dev: &Arc<Device>,
+    format: &DmatexFormat,
+    create_info: impl Fn() -> ImageCreateInfo,
+    alignment: u64,
+) -> Result<(RawImage, u64, u32), DmatexImportError> {
+    let planes = format
+        .variants()
+        .iter()
+        .find(|v| v.modifier == u64::from(DrmModifier::Linear))
+        .ok_or(DmatexImportError::NoLinearLayout(format.vk_format()))?
+        .planes;
+    let implicit = RawImage::new(dev.clone(), create_info())?;
+    let info = create_info();
+    let (layered, three_d) = (info.array_layers > 1, info.extent[2] > 1);
+    let mut offset = 0;
+    let layouts = (0..planes)
+        .map(|plane| {
+            let layout = implicit
+                .subresource_layout(memory_plane_aspect(plane), 0, 0)
+                .map_err(DmatexImportError::Validation)?;
+            let row_pitch = layout.row_pitch.next_multiple_of(alignment);
+            // the same number of rows, each one longer
+            let pad = |pitch: u64| pitch.div_ceil(layout.row_pitch) * row_pitch;
+            let array_pitch = layered.then(|| pad(layout.array_pitch.unwrap()));
+            let size = array_pitch.map_or(pad(layout.size), |v| v * info.array_layers as u64);
+            let plane_offset = offset;
+            offset = (offset + size).next_multiple_of(alignment);
+            Ok(SubresourceLayout {
+                offset: plane_offset,
+                size: 0,
+                row_pitch,
+                array_pitch,
+                depth_pitch: three_d.then(|| pad(layout.depth_pitch.unwrap())),
+            })
+        })
+        .collect::<Result<_, DmatexImportError>>()?;
+    let raw_image = RawImage::new(
+        dev.clone(),
+        ImageCreateInfo {
+            drm_format_modifier_plane_layouts: layouts,
+            ..info
+        },
+    )?;
+    Ok((raw_image, DrmModifier::Linear.into(), planes))
+}
+
+fn memory_plane_aspect(plane: u32) -> ImageAspect {
+    match plane {
+        0 => ImageAspect::MemoryPlane0,
+        1 => ImageAspect::MemoryPlane1,
+        2 => ImageAspect::MemoryPlane2,
+        _ => ImageAspect::MemoryPlane3,
+    }
+}
+
+/// Clears `image` to transparent and attaches the completion to timeline point 0. Without
+/// `sync_fd_export` the clear is waited for and the point signalled on the cpu
+fn clear_image(
+    queue: &Arc<Queue>,
+    image: &Arc<Image>,
+    timeline: &dyn SyncProvider,
+    sync_fd_export: bool,
+) -> Result<(), DmatexImportError> {
+    let dev = queue.device();
+    let allocator = Arc::new(StandardCommandBufferAllocator::new(
+        dev.clone(),
+        Default::default(),
+    ));
+    let mut builder = AutoCommandBufferBuilder::primary(
+        allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+    builder
+        .clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Float([0.0; 4]),
+            ..ClearColorImageInfo::image(image.clone())
+        })
+        .map_err(DmatexImportError::Validation)?;
+    let command_buffer = builder.build()?;
+    if !sync_fd_export {
+        let fence = Arc::new(Fence::new(dev.clone(), FenceCreateInfo::default())?);
+        queue.with(|mut guard| unsafe {
+            guard.submit(
+                &[SubmitInfo {
+                    command_buffers: vec![CommandBufferSubmitInfo::new(command_buffer)],
+                    ..Default::default()
+                }],
+                Some(&fence),
+            )
+        })?;
+        fence.wait(None)?;
+        unsafe { timeline.signal(0) }.map_err(DmatexImportError::Timeline)?;
+        return Ok(());
+    }
+    let cleared = exportable_semaphore(dev);
+    queue.with(|mut guard| unsafe {
+        guard.submit(
+            &[SubmitInfo {
+                command_buffers: vec![CommandBufferSubmitInfo::new(command_buffer)],
+                signal_semaphores: vec![SemaphoreSubmitInfo::new(cleared.clone())],
+                ..Default::default()
+            }],
+            None,
+        )
+    })?;
+    attach_to_timeline(timeline, &cleared, 0);
+    Ok(())
+}
+
+/// a semaphore that can be signalled by a submission and then passed to [`attach_to_timeline`]
+pub(crate) fn exportable_semaphore(dev: &Arc<Device>) -> Arc<Semaphore> {
+    // TODO: custom pool?
+    Arc::new(
+        Semaphore::new(
+            dev.clone(),
+            SemaphoreCreateInfo {
+                export_handle_types: ExternalSemaphoreHandleTypes::SYNC_FD,
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    )
+}
+
+/// makes `point` signal once the already submitted signal operation of `semaphore` completes
+pub(crate) fn attach_to_timeline(timeline: &dyn SyncProvider, semaphore: &Semaphore, point: u64) {
+    let fd = unsafe { semaphore.export_fd(ExternalSemaphoreHandleType::SyncFd) }.unwrap();
+    timeline.import_sync_file_point(fd.as_fd(), point).unwrap();
+}
+
+/// a binary semaphore that is signalled once `point` is, for use as a submission's wait
+pub(crate) fn timeline_wait_semaphore(
+    dev: &Arc<Device>,
+    timeline: &dyn SyncProvider,
+    point: u64,
+) -> Arc<Semaphore> {
+    let semaphore = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+    unsafe {
+        semaphore
+            .import_fd(ImportSemaphoreFdInfo {
+                file: Some(timeline.export_sync_file_point(point).unwrap().into()),
+                flags: SemaphoreImportFlags::TEMPORARY,
+                ..ImportSemaphoreFdInfo::handle_type(ExternalSemaphoreHandleType::SyncFd)
+            })
+            .unwrap()
+    }
+    semaphore
+}
+
+/// A dmabuf plane allocated outside of cme
+#[derive(Debug)]
+pub struct ExternalPlane {
+    pub fd: OwnedFd,
+    pub offset: u64,
+    pub row_pitch: u64,
+}
+
+/// A dmabuf allocated outside of cme, e.g. by a video decoder or another process
+#[derive(Debug)]
+pub struct ExternalDmabuf {
+    pub size: DmatexSize,
+    pub fourcc: DrmFourcc,
+    pub modifier: u64,
+    pub srgb: bool,
+    pub planes: Vec<ExternalPlane>,
+}
+
+/// An imported dmabuf a producer outside of cme keeps writing frames into, like the buffers
+/// of a video stream or a wayland client. Every frame is acquired at the point after the
+/// previous frame's release
+pub struct BridgedDmatex {
+    pub dmatex: Dmatex,
+    latest_release: AtomicU64,
+}
+impl BridgedDmatex {
+    pub fn new(dmatex: Dmatex) -> Self {
+        Self {
+            dmatex,
+            latest_release: AtomicU64::new(0),
+        }
+    }
+    /// A frame the producer is already done with, its acquire point is signalled right away
+    pub fn signal_frame(&self) -> Result<DmatexSubmitInfo, Errno> {
+        self.frame(|timeline, acquire_point| unsafe { timeline.signal(acquire_point) })
+    }
+    /// A frame that is done once `sync_file` is signalled
+    pub fn fenced_frame(&self, sync_file: BorrowedFd<'_>) -> Result<DmatexSubmitInfo, Errno> {
+        self.frame(|timeline, acquire_point| {
+            timeline.import_sync_file_point(sync_file, acquire_point)
+        })
+    }
+    /// the points only advance once the acquire point will be signalled, so a failed frame
+    /// doesn't leave the next one waiting for it
+    fn frame(
+        &self,
+        acquire: impl FnOnce(&dyn SyncProvider, u64) -> Result<(), Errno>,
+    ) -> Result<DmatexSubmitInfo, Errno> {
+        let acquire_point = self.latest_release.load(Ordering::Relaxed) + 1;
+        acquire(&*self.dmatex.timeline, acquire_point)?;
+        let release_point = acquire_point + 1;
+        self.latest_release.store(release_point, Ordering::Relaxed);
+        Ok(DmatexSubmitInfo {
+            dmatex_id: self.dmatex.dmatex_id,
+            acquire_point,
+            release_point,
+        })
+    }
+    /// the release point of the latest frame
+    pub fn latest_release(&self) -> u64 {
+        self.latest_release.load(Ordering::Relaxed)
+    }
+    /// whether the server stopped reading the latest frame, so the producer can reuse the buffer
+    pub fn is_released(&self) -> bool {
+        self.dmatex.is_signalled(self.latest_release())
+    }
+    /// waits until the server stopped reading the latest frame, `deadline` None waits forever
+    pub fn wait_release(&self, deadline: Option<Instant>) -> Result<(), Errno> {
+        self.dmatex.wait(self.latest_release(), deadline)
+    }
+}
+
+impl Dmatex {
+    /// Imports a dmabuf that was allocated elsewhere into vulkan and shares it with the server,
+    /// the server receives the planes exactly as they were passed in. Panics on failure, use
+    /// [`Self::try_import`] for dmabufs from untrusted sources
+    pub fn import<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        dmabuf: ExternalDmabuf,
+        usage: ImageUsage,
+    ) -> Self {
+        Self::try_import(client, dev, render_dev, dmabuf, usage).unwrap()
+    }
+    /// Like [`Self::import`], but returns the error if the dmabuf can't be imported into
+    /// vulkan, e.g. because its planes don't match the modifier, or the server doesn't accept it
+    pub fn try_import<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        dmabuf: ExternalDmabuf,
+        usage: ImageUsage,
+    ) -> Result<Self, DmatexImportError> {
+        Self::import_dyn(client.clone(), dev, render_dev, dmabuf, None, usage)
+    }
+    /// a new drm timeline syncobj is created if `timeline` is None
+    pub(crate) fn import_dyn(
+        client: Arc<dyn Backend>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        dmabuf: ExternalDmabuf,
+        timeline: Option<Box<dyn SyncProvider>>,
+        usage: ImageUsage,
+    ) -> Result<Self, DmatexImportError> {
+        Self::import_inner(client, dev, render_dev, dmabuf, None, timeline, usage)
+    }
+    /// `layers` is the array layer count and the array pitch of every plane,
+    /// a new timeline is created if `timeline` is None
+    fn import_inner(
+        client: Arc<dyn Backend>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        dmabuf: ExternalDmabuf,
+        layers: Option<(u32, Vec<u64>)>,
+        timeline: Option<Box<dyn SyncProvider>>,
+        usage: ImageUsage,
+    ) -> Result<Self, DmatexImportError> {
+        // the planes of a dmabuf can only be passed to vulkan together with its modifier
+        if !DeviceCaps::check(
+            DeviceCaps::new(dev).modifier_query,
+            DeviceCapability::ModifierQuery,
+        ) {
+            return Err(DmatexImportError::NoModifierSupport);
+        }
+        let format = Format::from_drm_fourcc(dmabuf.fourcc)
+            .ok_or(DmatexImportError::UnknownFourcc(dmabuf.fourcc as u32))?;
+        let format = if dmabuf.srgb {
+            format
+                .to_srgb()
+                .ok_or(DmatexImportError::NoSrgbVariant(format))?
+        } else {
+            format
+        };
+        // the planes come from outside, vulkan only checks them against the modifier in
+        // validation layers
+        let modifier_planes = dev
+            .physical_device()
+            .format_properties(format)
+            .map_err(DmatexImportError::Validation)?
+            .drm_format_modifier_properties
+            .iter()
+            .find(|props| props.drm_format_modifier == dmabuf.modifier)
+            .map(|props| props.drm_format_modifier_plane_count)
+            .ok_or(DmatexImportError::UnsupportedModifier {
+                format,
+                modifier: dmabuf.modifier,
+            })?;
+        if dmabuf.planes.len() != modifier_planes as usize {
+            return Err(DmatexImportError::PlaneCount {
+                expected: modifier_planes as usize,
+                got: dmabuf.planes.len(),
+            });
+        }
+        if let Some((_, pitches)) = &layers
+            && pitches.len() != dmabuf.planes.len()
+        {
+            return Err(DmatexImportError::PlaneCount {
+                expected: dmabuf.planes.len(),
+                got: pitches.len(),
+            });
+        }
+        // planes living in different dmabufs need their own memory
+        let disjoint = dmabuf
+            .planes
+            .iter()
+            .skip(1)
+            .any(|p| !same_file(&p.fd, &dmabuf.planes[0].fd));
         let raw_image = RawImage::new(
             dev.clone(),
             ImageCreateInfo {
-                flags: ImageCreateFlags::empty(),
-                image_type: match &size {
-                    DmatexSize::Dim1D(_) => ImageType::Dim1d,
-                    DmatexSize::Dim2D(_) => ImageType::Dim2d,
-                    DmatexSize::Dim3D(_) => ImageType::Dim3d,
-                },
-                format: format.vk_format(),
-                view_formats: vec![],
-                extent: match &size {
-                    DmatexSize::Dim1D(v) => [*v, 1, 1],
-                    DmatexSize::Dim2D(v) => [v.x, v.y, 1],
-                    DmatexSize::Dim3D(v) => (*v).into(),
+                flags: if disjoint {
+                    ImageCreateFlags::DISJOINT
+                } else {
+                    ImageCreateFlags::empty()
                 },
-                array_layers: array_layers.unwrap_or(1),
+                image_type: image_type(&dmabuf.size),
+                format,
+                extent: image_extent(&dmabuf.size),
+                array_layers: layers.as_ref().map_or(1, |(layers, _)| *layers),
                 tiling: ImageTiling::DrmFormatModifier,
                 usage,
-                drm_format_modifiers: modifiers,
+                drm_format_modifiers: vec![dmabuf.modifier],
+                drm_format_modifier_plane_layouts: dmabuf
+                    .planes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| SubresourceLayout {
+                        offset: p.offset,
+                        size: 0,
+                        row_pitch: p.row_pitch,
+                        array_pitch: layers.as_ref().map(|(_, pitches)| pitches[i]),
+                        depth_pitch: None,
+                    })
+                    .collect(),
                 external_memory_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
                 ..Default::default()
             },
-        )
-        .unwrap();
-        let (modifier, planes) = raw_image.drm_format_modifier().unwrap();
+        )?;
+        let import_fds = if disjoint {
+            dmabuf.planes.iter().map(|p| &p.fd).collect::<Vec<_>>()
+        } else {
+            vec![&dmabuf.planes[0].fd]
+        };
         let mem_reqs = raw_image.memory_requirements();
-        info!("modifier {modifier} needs {planes} planes");
+        if mem_reqs.len() != import_fds.len() {
+            return Err(DmatexImportError::PlaneCount {
+                expected: mem_reqs.len(),
+                got: import_fds.len(),
+            });
+        }
         let mems = mem_reqs
             .iter()
-            .map(|v| {
-                let wants_decicated =
-                    v.prefers_dedicated_allocation || v.requires_dedicated_allocation;
-                if !wants_decicated {
-                    warn!("dmatex image doesn't want a dedicated alloc, too bad");
-                }
-                let Some((type_index, _)) = dev
-                    .physical_device()
-                    .memory_properties()
-                    .memory_types
-                    .iter()
-                    .enumerate()
-                    .filter(|(i, _)| v.memory_type_bits & (1 << i) != 0)
-                    .find(|(_, p)| {
-                        // nvidia doesn't put the device local mem first
-                        p.property_flags.contains(MemoryPropertyFlags::DEVICE_LOCAL)
-                        // not sure if this is even needed, just in case
-                        && !p.property_flags.contains(MemoryPropertyFlags::PROTECTED)
-                    })
-                else {
-                    warn!("unable to find memory type for dmatex plane");
-                    return None;
-                };
-                vulkano::memory::DeviceMemory::allocate(
-                    dev.clone(),
-                    MemoryAllocateInfo {
-                        allocation_size: v.layout.size(),
-                        memory_type_index: type_index as u32,
-                        dedicated_allocation: Some(DedicatedAllocation::Image(&raw_image)),
-                        export_handle_types: ExternalMemoryHandleTypes::DMA_BUF,
-                        ..MemoryAllocateInfo::default()
-                    },
-                )
-                .inspect_err(|err| error!("failed to allocate mem for dmatex plane: {err}"))
-                .ok()
+            .zip(import_fds)
+            .map(|(req, fd)| {
+                let type_index = find_memory_type(dev.physical_device(), req.memory_type_bits)
+                    .ok_or(DmatexImportError::NoMemoryType(req.memory_type_bits))?;
+                // vulkan takes ownership of the fd, the original still goes to the server
+                let file = File::from(fd.try_clone().map_err(DmatexImportError::Dup)?);
+                let memory = unsafe {
+                    DeviceMemory::import(
+                        dev.clone(),
+                        MemoryAllocateInfo {
+                            allocation_size: req.layout.size(),
+                            memory_type_index: type_index,
+                            dedicated_allocation: (!disjoint)
+                                .then_some(DedicatedAllocation::Image(&raw_image)),
+                            ..MemoryAllocateInfo::default()
+                        },
+                        MemoryImportInfo::Fd {
+                            handle_type: ExternalMemoryHandleType::DmaBuf,
+                            file,
+                        },
+                    )
+                }?;
+                Ok(memory)
             })
-            .collect::<Option<Vec<DeviceMemory>>>();
-        let mems = mems.unwrap();
-        let fds = mems
-            .iter()
-            .map(|v| v.export_fd(ExternalMemoryHandleType::DmaBuf))
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
-        let image = match raw_image.bind_memory(mems.into_iter().map(ResourceMemory::new_dedicated))
-        {
-            Ok(v) => v,
-            Err((err, _, _)) => panic!("failed to bind image mem: {err}"),
+            .collect::<Result<Vec<_>, DmatexImportError>>()?;
+        let memory_types = mems.iter().map(|v| v.memory_type_index()).collect();
+        let image = raw_image
+            .bind_memory(mems.into_iter().map(ResourceMemory::new_dedicated))
+            .map_err(|(err, _, _)| err)?;
+        let timeline = match timeline {
+            Some(timeline) => timeline,
+            None => timeline_syncobj(render_dev).map_err(DmatexImportError::Timeline)?,
         };
-        let timeline = TimelineSyncObj::create(render_dev.drm_node()).unwrap();
-        let dmatex_id = client.generate_id();
-        let first_fd = fds[0].try_clone().unwrap();
-        let planes = fds
-            .into_iter()
-            .chain([first_fd])
-            .enumerate()
-            .map(|(i, v)| {
-                let aspect = match i {
-                    0 => vulkano::image::ImageAspect::MemoryPlane0,
-                    1 => vulkano::image::ImageAspect::MemoryPlane1,
-                    2 => vulkano::image::ImageAspect::MemoryPlane2,
-                    3 => vulkano::image::ImageAspect::MemoryPlane3,
-                    _ => vulkano::image::ImageAspect::Color,
-                };
-                let layout = image.subresource_layout(aspect, 0, 0).unwrap();
-                DmatexPlane {
-                    dmabuf_fd: OwnedFd::from(v).into(),
-                    offset: layout.offset as u32,
-                    row_size: layout.row_pitch as u32,
-                    array_element_size: layout.array_pitch.unwrap_or(0) as u32,
-                    depth_slice_size: layout.depth_pitch.unwrap_or(0) as u32,
-                }
+        let dmatex_id = import_with_retry(&client, |dmatex_id| {
+            let planes = dmabuf
+                .planes
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    Ok(DmatexPlane {
+                        dmabuf_fd: p.fd.try_clone().map_err(DmatexImportError::Dup)?.into(),
+                        offset: p.offset as u32,
+                        row_size: p.row_pitch as u32,
+                        array_element_size: layers
+                            .as_ref()
+                            .map_or(0, |(_, pitches)| pitches[i] as u32),
+                        depth_slice_size: 0,
+                    })
+                })
+                .collect::<Result<Vec<_>, DmatexImportError>>()?;
+            Ok(DmatexImport {
+                dmatex_id,
+                size: dmabuf.size.clone(),
+                fourcc: dmabuf.fourcc as u32,
+                drm_modifier: dmabuf.modifier,
+                srgb: dmabuf.srgb,
+                array_layers: layers.as_ref().map(|(layers, _)| *layers),
+                planes,
+                timeline: timeline.export().map_err(DmatexImportError::Timeline)?,
+                color_space: None,
             })
-            .collect::<Vec<_>>();
-        import_dmatex(
-            client,
-            dmatex_id,
-            size,
-            format.drm_fourcc() as u32,
-            modifier,
-            format!("{:?}", format.vk_format()).contains("SRGB"),
-            array_layers,
-            &planes,
-            timeline.export().unwrap().into(),
-        )
-        .unwrap();
+        })?;
 
-        Self {
+        Ok(Dmatex {
             image: Arc::new(image),
             timeline,
             dmatex_id,
-            _client: client.clone(),
+            name: None,
+            color_space: None,
+            fourcc: dmabuf.fourcc as u32,
+            drm_modifier: dmabuf.modifier,
+            opaque: is_opaque(dmabuf.fourcc),
+            srgb: dmabuf.srgb,
+            views: Mutex::default(),
+            memory_types,
+            client,
+        })
+    }
+}
+
+impl Dmatex {
+    /// Exports the dmabufs, layout and timeline of this dmatex, e.g. to pass it to a process
+    /// that doesn't use vulkan. The fds are duplicates, this dmatex stays usable
+    pub fn to_handle(&self) -> Result<DmatexHandle, DmatexHandleError> {
+        let ImageMemory::Normal(memory) = self.image.memory() else {
+            unreachable!("dmatexes are always bound to normal memory");
+        };
+        let descriptor = self.descriptor();
+        let planes = PlaneExport::for_image(&self.image)
+            .memory_indices(descriptor.planes.len() as u32)
+            .zip(&descriptor.planes)
+            .map(|(memory_index, layout)| {
+                let fd = memory[memory_index]
+                    .device_memory()
+                    .export_fd(ExternalMemoryHandleType::DmaBuf)
+                    .map_err(|err| DmatexHandleError::MemoryExport(err.to_string()))?;
+                Ok(PlaneFd {
+                    fd: fd.into(),
+                    offset: layout.offset,
+                    row_pitch: layout.row_pitch,
+                    array_pitch: layout.array_pitch.unwrap_or(0),
+                })
+            })
+            .collect::<Result<Vec<_>, DmatexHandleError>>()?;
+        Ok(DmatexHandle {
+            size: descriptor.size,
+            fourcc: self.fourcc,
+            modifier: self.drm_modifier,
+            srgb: self.srgb,
+            array_layers: descriptor.array_layers,
+            planes,
+            timeline_fd: self
+                .timeline
+                .export()
+                .map_err(DmatexHandleError::Timeline)?,
+        })
+    }
+    /// Imports a dmatex exported with [`Self::to_handle`], possibly by another process.
+    /// The handle's timeline is kept, so both sides synchronize on the same points
+    pub fn from_handle<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        handle: DmatexHandle,
+        usage: ImageUsage,
+    ) -> Result<Self, DmatexHandleError> {
+        if handle.array_layers > 1 && handle.planes.iter().any(|p| p.array_pitch == 0) {
+            return Err(DmatexHandleError::MissingArrayPitch);
+        }
+        let fourcc = DrmFourcc::try_from(handle.fourcc)
+            .map_err(|_| DmatexHandleError::UnknownFourcc(handle.fourcc))?;
+        if Format::from_drm_fourcc(fourcc).is_none() {
+            return Err(DmatexHandleError::UnknownFourcc(handle.fourcc));
+        }
+        let timeline = TimelineSyncObj::import(render_dev.drm_node(), handle.timeline_fd)
+            .map_err(DmatexHandleError::Timeline)?;
+        let layers = (handle.array_layers > 1).then(|| {
+            (
+                handle.array_layers,
+                handle.planes.iter().map(|p| p.array_pitch).collect(),
+            )
+        });
+        let dmabuf = ExternalDmabuf {
+            size: handle.size,
+            fourcc,
+            modifier: handle.modifier,
+            srgb: handle.srgb,
+            planes: handle
+                .planes
+                .into_iter()
+                .map(|p| ExternalPlane {
+                    fd: p.fd,
+                    offset: p.offset,
+                    row_pitch: p.row_pitch,
+                })
+                .collect(),
+        };
+        Ok(Self::import_inner(
+            client.clone(),
+            dev,
+            render_dev,
+            dmabuf,
+            layers,
+            Some(Box::new(timeline)),
+            usage,
+        )?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DmatexHandleError {
+    #[error("failed to export dmatex memory: {0}")]
+    MemoryExport(String),
+    #[error("timeline syncobj error: {0}")]
+    Timeline(Errno),
+    #[error("fourcc {0:#010x} has no vulkan format")]
+    UnknownFourcc(u32),
+    #[error("layered dmatexes need a non zero array pitch for every plane")]
+    MissingArrayPitch,
+    #[error(transparent)]
+    Import(#[from] DmatexImportError),
+}
+
+#[derive(Debug, Error)]
+pub enum DmatexImportError {
+    #[error("the server didn't import the dmatex: {0}")]
+    Server(#[from] ImportError),
+    #[error("failed to create or export the timeline: {0}")]
+    Timeline(Errno),
+    #[error("failed to duplicate a dmabuf fd: {0}")]
+    Dup(std::io::Error),
+    #[error("failed to allocate the dmatex's memory")]
+    OutOfMemory,
+    #[error("failed to allocate a udmabuf: {0}")]
+    Udmabuf(Errno),
+    #[error("fourcc {0:#010x} has no vulkan format")]
+    UnknownFourcc(u32),
+    #[error("{0:?} has no srgb variant the server could sample it as")]
+    NoSrgbVariant(Format),
+    #[error("the device can't use {format:?} with modifier {modifier:#x}")]
+    UnsupportedModifier { format: Format, modifier: u64 },
+    #[error("expected {expected} planes, got {got}")]
+    PlaneCount { expected: usize, got: usize },
+    #[error("cube compatible dmatexes have to be square and 2D")]
+    NonSquareCube,
+    #[error("only multi-planar formats can be disjoint, got {0:?}")]
+    NotMultiPlanar(Format),
+    #[error("3D dmatexes can't have array layers")]
+    LayeredVolume,
+    #[error("the server can't import linear {0:?} images")]
+    NoLinearLayout(Format),
+    #[error("no modifier with a plane count matching the server for {0:?}")]
+    NoModifier(Format),
+    #[error("no memory type can hold the dmabuf, allowed types are {0:#b}")]
+    NoMemoryType(u32),
+    #[error("importing dmabufs needs VK_EXT_image_drm_format_modifier")]
+    NoModifierSupport,
+    #[error("the vulkan device was lost")]
+    DeviceLost,
+    #[error("vulkan error: {0}")]
+    Vulkan(VulkanError),
+    #[error("validation error: {0}")]
+    Validation(Box<ValidationError>),
+}
+impl From<VulkanError> for DmatexImportError {
+    fn from(err: VulkanError) -> Self {
+        match err {
+            VulkanError::DeviceLost => Self::DeviceLost,
+            err => Self::Vulkan(err),
+        }
+    }
+}
+impl From<Validated<VulkanError>> for DmatexImportError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        match err {
+            Validated::Error(err) => err.into(),
+            Validated::ValidationError(err) => Self::Validation(err),
         }
     }
 }
@@ -178,7 +1617,6 @@ impl Dmatex {
     }
     pub const fn required_device_exts() -> DeviceExtensions {
         DeviceExtensions {
-            ext_image_drm_format_modifier: true,
             ext_external_memory_dma_buf: true,
             khr_external_memory: true,
             khr_external_memory_fd: true,
@@ -188,8 +1626,119 @@ impl Dmatex {
             ..DeviceExtensions::empty()
         }
     }
+    /// Extensions cme has faster paths for, see [`DeviceCaps`]. Without
+    /// `ext_image_drm_format_modifier` dmatexes are linear and dmabufs can't be imported
+    pub const fn optional_device_exts() -> DeviceExtensions {
+        DeviceExtensions {
+            ext_image_drm_format_modifier: true,
+
+            ..DeviceExtensions::empty()
+        }
+    }
+    /// the required extensions and the optional ones `phys_dev` supports, to create the
+    /// device with
+    pub fn device_exts(phys_dev: &PhysicalDevice) -> DeviceExtensions {
+        Self::required_device_exts()
+            .union(&Self::optional_device_exts().intersection(phys_dev.supported_extensions()))
+    }
     /// empty, exists just incase any device features are required in the future
     pub const fn required_device_features() -> DeviceFeatures {
         DeviceFeatures::empty()
     }
 }
+
+fn image_type(size: &DmatexSize) -> ImageType {
+    match size {
+        DmatexSize::Dim1D(_) => ImageType::Dim1d,
+        DmatexSize::Dim2D(_) => ImageType::Dim2d,
+        DmatexSize::Dim3D(_) => ImageType::Dim3d,
+    }
+}
+
+fn image_extent(size: &DmatexSize) -> [u32; 3] {
+    match size {
+        DmatexSize::Dim1D(v) => [*v, 1, 1],
+        DmatexSize::Dim2D(v) => [v.x, v.y, 1],
+        DmatexSize::Dim3D(v) => (*v).into(),
+    }
+}
+
+/// Picks the memory type used for exported/imported dmabuf memory. Device local memory is
+/// preferred, some integrated gpus only expose exportable memory that's also host visible,
+/// plain host visible memory is the last resort
+pub(crate) fn find_memory_type(phys_dev: &PhysicalDevice, memory_type_bits: u32) -> Option<u32> {
+    let mut best: Option<(u32, u8)> = None;
+    for (i, memory_type) in phys_dev.memory_properties().memory_types.iter().enumerate() {
+        if memory_type_bits & (1 << i) == 0 {
+            continue;
+        }
+        let flags = memory_type.property_flags;
+        // not sure if this is even needed, just in case
+        if flags.contains(MemoryPropertyFlags::PROTECTED) {
+            continue;
+        }
+        let device_local = flags.contains(MemoryPropertyFlags::DEVICE_LOCAL);
+        let host_visible = flags.contains(MemoryPropertyFlags::HOST_VISIBLE);
+        // nvidia doesn't put the device local mem first, so look at all of them
+        let score = match (device_local, host_visible) {
+            (true, false) => 3,
+            (true, true) => 2,
+            (false, true) => 1,
+            (false, false) => continue,
+        };
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((i as u32, score));
+        }
+    }
+    if let Some((i, score)) = best
+        && score < 3
+    {
+        report(DiagnosticEvent::NotDeviceLocal { memory_type: i });
+    }
+    best.map(|(i, _)| i)
+}
+
+/// whether both fds refer to the same dmabuf
+fn same_file(a: &OwnedFd, b: &OwnedFd) -> bool {
+    match (rustix::fs::fstat(a), rustix::fs::fstat(b)) {
+        (Ok(a), Ok(b)) => a.st_dev == b.st_dev && a.st_ino == b.st_ino,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_memory_planes() {
+        let export = PlaneExport::for_flags(ImageCreateFlags::empty());
+        assert_eq!(export, PlaneExport::SingleMemory);
+        assert_eq!(export.memory_indices(1).collect::<Vec<_>>(), [0]);
+        // e.g. nv12 or a modifier with a separate compression plane, all from one dmabuf
+        assert_eq!(export.memory_indices(3).collect::<Vec<_>>(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn per_plane_memory() {
+        let export = PlaneExport::PerPlaneMemory;
+        assert_eq!(export.memory_indices(1).collect::<Vec<_>>(), [0]);
+        assert_eq!(export.memory_indices(3).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn disjoint_images_export_per_plane() {
+        assert_eq!(
+            PlaneExport::for_flags(ImageCreateFlags::DISJOINT),
+            PlaneExport::PerPlaneMemory
+        );
+        assert_eq!(
+            PlaneExport::for_flags(ImageCreateFlags::DISJOINT | ImageCreateFlags::MUTABLE_FORMAT),
+            PlaneExport::PerPlaneMemory
+        );
+        assert_eq!(
+            PlaneExport::for_flags(ImageCreateFlags::MUTABLE_FORMAT),
+            PlaneExport::SingleMemory
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! A dmatex as plain data and file descriptors, for handing shared textures to helper processes
+//! that don't link vulkano, see [`crate::dmatex::Dmatex::to_handle`]
+
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+
+use stardust_xr_fusion::drawable::DmatexSize;
+
+/// Everything needed to import a dmatex elsewhere. Send [`Self::fds`] over a unix socket
+/// with SCM_RIGHTS and the rest of the fields through any serialization
+#[derive(Debug)]
+pub struct DmatexHandle {
+    pub size: DmatexSize,
+    pub fourcc: u32,
+    pub modifier: u64,
+    pub srgb: bool,
+    pub array_layers: u32,
+    pub planes: Vec<PlaneFd>,
+    /// the drm timeline syncobj the dmatex is synchronized with
+    pub timeline_fd: OwnedFd,
+}
+
+/// A dmabuf plane, planes of non disjoint images share the same dmabuf
+#[derive(Debug)]
+pub struct PlaneFd {
+    pub fd: OwnedFd,
+    pub offset: u64,
+    pub row_pitch: u64,
+    /// bytes between array layers, 0 for single layer dmatexes
+    pub array_pitch: u64,
+}
+
+impl DmatexHandle {
+    /// the plane fds in order followed by the timeline fd
+    pub fn fds(&self) -> impl Iterator<Item = BorrowedFd<'_>> {
+        self.planes
+            .iter()
+            .map(|p| p.fd.as_fd())
+            .chain([self.timeline_fd.as_fd()])
+    }
+}
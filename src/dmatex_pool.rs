@@ -0,0 +1,209 @@
+//! Recycling dmatexes of one size and format, for clients that go through many short lived
+//! textures like thumbnail grids or video scrubbing.
+//!
+//! Creating a dmatex allocates memory and imports it on the server, a [`DmatexPool`] keeps
+//! released ones around instead and hands them out again once the server is done with them.
+//! Every texture is used for one frame at a time: [`DmatexPool::acquire`] it, render into it
+//! and signal [`PooledDmatex::acquire_point`], submit it with
+//! [`PooledDmatex::submit_info`], then give it back with [`DmatexPool::release`].
+
+use std::{collections::VecDeque, sync::Arc, time::Instant};
+
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use tracing::warn;
+use vulkano::{
+    device::{Device, Queue},
+    image::ImageUsage,
+};
+
+use crate::{
+    backend::Backend,
+    dmatex::{Dmatex, DmatexBuilder},
+    format::DmatexFormat,
+    render_device::RenderDevice,
+};
+
+/// Same spec dmatexes handed out and taken back, idle ones beyond
+/// [`DmatexPool::max_idle`] get unregistered least recently used first
+pub struct DmatexPool {
+    client: Arc<dyn Backend>,
+    dev: Arc<Device>,
+    size: DmatexSize,
+    format: DmatexFormat,
+    array_layers: Option<u32>,
+    usage: ImageUsage,
+    name: Option<String>,
+    clear_queue: Option<Arc<Queue>>,
+    max_idle: usize,
+    /// released textures, least recently used first
+    idle: VecDeque<IdleDmatex>,
+    /// how many textures were created, for their names
+    created: usize,
+}
+
+struct IdleDmatex {
+    dmatex: Arc<Dmatex>,
+    /// the point after which the server doesn't read it anymore
+    release_point: u64,
+}
+
+impl IdleDmatex {
+    fn is_released(&self) -> bool {
+        self.dmatex
+            .timeline
+            .blocking_wait(self.release_point, Some(Instant::now()))
+            .is_ok()
+    }
+}
+
+/// A texture of a [`DmatexPool`] for one frame
+pub struct PooledDmatex {
+    dmatex: Arc<Dmatex>,
+    acquire_point: u64,
+}
+
+impl PooledDmatex {
+    pub fn dmatex(&self) -> &Arc<Dmatex> {
+        &self.dmatex
+    }
+    /// the point to signal once rendering is done
+    pub fn acquire_point(&self) -> u64 {
+        self.acquire_point
+    }
+    /// the point the server signals once it doesn't read the texture anymore
+    pub fn release_point(&self) -> u64 {
+        self.acquire_point + 1
+    }
+    /// what to submit to the server for this frame
+    pub fn submit_info(&self) -> DmatexSubmitInfo {
+        DmatexSubmitInfo {
+            dmatex_id: self.dmatex.dmatex_id,
+            acquire_point: self.acquire_point,
+            release_point: self.release_point(),
+        }
+    }
+}
+
+impl DmatexPool {
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        size: DmatexSize,
+        format: &DmatexFormat,
+        usage: ImageUsage,
+    ) -> Self {
+        Self {
+            client: client.clone(),
+            dev: dev.clone(),
+            size,
+            format: format.clone(),
+            array_layers: None,
+            usage,
+            name: None,
+            clear_queue: None,
+            max_idle: 8,
+            idle: VecDeque::new(),
+            created: 0,
+        }
+    }
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = Some(array_layers);
+        self
+    }
+    /// debug label for the textures, see [`DmatexBuilder::name`]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    /// clears new textures and signals point 0 on the gpu, see [`DmatexBuilder::clear_queue`]
+    pub fn clear_queue(mut self, queue: &Arc<Queue>) -> Self {
+        self.clear_queue = Some(queue.clone());
+        self
+    }
+    /// how many released textures are kept for reuse, 8 by default
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+    /// Hands out the most recently released texture the server is done with, or creates a
+    /// new one if there is none
+    pub fn acquire(&mut self, render_dev: &RenderDevice) -> PooledDmatex {
+        if let Some(index) = self.idle.iter().rposition(IdleDmatex::is_released) {
+            let idle = self.idle.remove(index).unwrap();
+            return PooledDmatex {
+                dmatex: idle.dmatex,
+                acquire_point: idle.release_point + 1,
+            };
+        }
+        PooledDmatex {
+            dmatex: self.create(render_dev),
+            acquire_point: 1,
+        }
+    }
+    /// Takes a texture back after it was submitted, it's handed out again once the server
+    /// signals its release point
+    pub fn release(&mut self, dmatex: PooledDmatex) {
+        let release_point = dmatex.release_point();
+        self.push_idle(dmatex.dmatex, release_point);
+    }
+    /// Takes a texture back that was never submitted, its acquire point must not have been
+    /// signalled either
+    pub fn release_unsubmitted(&mut self, dmatex: PooledDmatex) {
+        self.push_idle(dmatex.dmatex, dmatex.acquire_point - 1);
+    }
+    /// Unregisters idle textures least recently used first until at most `keep` are left.
+    /// Ones the server didn't release yet are kept until a later trim
+    pub fn trim(&mut self, keep: usize) {
+        let mut excess = self.idle.len().saturating_sub(keep);
+        self.idle.retain(|idle| {
+            if excess == 0 || !idle.is_released() {
+                return true;
+            }
+            excess -= 1;
+            if let Err(err) = idle.dmatex.unregister() {
+                warn!("failed to unregister a pooled dmatex: {err}");
+            }
+            false
+        });
+    }
+    /// how many released textures wait for reuse
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+    fn push_idle(&mut self, dmatex: Arc<Dmatex>, release_point: u64) {
+        self.idle.push_back(IdleDmatex {
+            dmatex,
+            release_point,
+        });
+        self.trim(self.max_idle);
+    }
+    fn create(&mut self, render_dev: &RenderDevice) -> Arc<Dmatex> {
+        let mut builder = DmatexBuilder::new(
+            self.client.clone(),
+            &self.dev,
+            render_dev,
+            self.size.clone(),
+            &self.format,
+            self.usage,
+        );
+        if let Some(array_layers) = self.array_layers {
+            builder = builder.array_layers(array_layers);
+        }
+        if let Some(name) = &self.name {
+            builder = builder.name(format!("{name} [{}]", self.created));
+        }
+        if let Some(queue) = &self.clear_queue {
+            builder = builder.clear_queue(queue);
+        }
+        self.created += 1;
+        let cleared = builder.clears();
+        let dmatex = Arc::new(builder.build());
+        // a cleared dmatex gets point 0 signalled by the clear
+        if !cleared {
+            unsafe {
+                dmatex.timeline.signal(0).unwrap();
+            }
+        }
+        dmatex
+    }
+}
@@ -0,0 +1,527 @@
+//! Paints egui output into swapchain frames using the caller's device and queue
+
+use std::{collections::HashMap, sync::Arc};
+
+use ::egui::{
+    ClippedPrimitive, ImageData, TextureFilter, TextureId, TexturesDelta,
+    epaint::{Primitive, Vertex as EpaintVertex},
+};
+use stardust_xr_fusion::drawable::DmatexSubmitInfo;
+use vulkano::{
+    buffer::{
+        Buffer, BufferContents, BufferCreateInfo, BufferUsage,
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+    },
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CommandBufferUsage, CopyBufferToImageInfo,
+        PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+        allocator::StandardCommandBufferAllocator,
+    },
+    descriptor_set::{
+        DescriptorSet, WriteDescriptorSet, allocator::StandardDescriptorSetAllocator,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::{
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::{
+                AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+            },
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Scissor, Viewport, ViewportState},
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+
+use crate::{
+    format::{DmatexFormat, VulkanoFormatExtension},
+    swapchain::{SwapchainError, SwapchainFrameHandle},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+            #version 450
+
+            layout(location = 0) in vec2 position;
+            layout(location = 1) in vec2 tex_coords;
+            layout(location = 2) in vec4 color;
+
+            layout(location = 0) out vec4 v_color;
+            layout(location = 1) out vec2 v_tex_coords;
+
+            layout(push_constant) uniform PushConstants {
+                vec2 screen_size;
+                int output_srgb;
+            } push_constants;
+
+            vec3 srgb_to_linear(vec3 srgb) {
+                bvec3 cutoff = lessThan(srgb, vec3(0.04045));
+                vec3 lower = srgb / vec3(12.92);
+                vec3 higher = pow((srgb + vec3(0.055)) / vec3(1.055), vec3(2.4));
+                return mix(higher, lower, cutoff);
+            }
+
+            void main() {
+                gl_Position = vec4(
+                    2.0 * position.x / push_constants.screen_size.x - 1.0,
+                    2.0 * position.y / push_constants.screen_size.y - 1.0,
+                    0.0,
+                    1.0
+                );
+                v_color = vec4(srgb_to_linear(color.rgb), color.a);
+                v_tex_coords = tex_coords;
+            }
+        ",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+            #version 450
+
+            layout(location = 0) in vec4 v_color;
+            layout(location = 1) in vec2 v_tex_coords;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D tex;
+
+            layout(push_constant) uniform PushConstants {
+                vec2 screen_size;
+                int output_srgb;
+            } push_constants;
+
+            vec3 linear_to_srgb(vec3 linear) {
+                bvec3 cutoff = lessThan(linear, vec3(0.0031308));
+                vec3 lower = linear * vec3(12.92);
+                vec3 higher = vec3(1.055) * pow(linear, vec3(1.0 / 2.4)) - vec3(0.055);
+                return mix(higher, lower, cutoff);
+            }
+
+            void main() {
+                vec4 color = v_color * texture(tex, v_tex_coords);
+                if (push_constants.output_srgb == 0) {
+                    color = vec4(linear_to_srgb(color.rgb), color.a);
+                }
+                f_color = color;
+            }
+        ",
+    }
+}
+
+#[derive(BufferContents, Vertex)]
+#[repr(C)]
+struct EguiVertex {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    tex_coords: [f32; 2],
+    #[format(R8G8B8A8_UNORM)]
+    color: [u8; 4],
+}
+impl From<&EpaintVertex> for EguiVertex {
+    fn from(v: &EpaintVertex) -> Self {
+        Self {
+            position: [v.pos.x, v.pos.y],
+            tex_coords: [v.uv.x, v.uv.y],
+            color: v.color.to_array(),
+        }
+    }
+}
+
+struct Texture {
+    image: Arc<Image>,
+    descriptor_set: Arc<DescriptorSet>,
+}
+
+/// Owns the pipeline, textures and buffers needed to draw egui into a swapchain
+pub struct EguiRenderer {
+    dev: Arc<Device>,
+    format: Format,
+    render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    buffer_allocator: SubbufferAllocator,
+    textures: HashMap<TextureId, Texture>,
+    next_user_texture: u64,
+    clear_color: [f32; 4],
+}
+
+impl EguiRenderer {
+    /// `format` is the format of the swapchain the renderer will draw into
+    pub fn new(dev: &Arc<Device>, format: &DmatexFormat) -> Self {
+        let format = format.vk_format();
+        let render_pass = vulkano::single_pass_renderpass!(
+            dev.clone(),
+            attachments: {
+                color: {
+                    format: format,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {},
+            },
+        )
+        .unwrap();
+
+        let vs = vs::load(dev.clone()).unwrap().entry_point("main").unwrap();
+        let fs = fs::load(dev.clone()).unwrap().entry_point("main").unwrap();
+        let vertex_input_state = EguiVertex::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            dev.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(dev.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipeline = GraphicsPipeline::new(
+            dev.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                // egui outputs premultiplied alpha
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend {
+                            src_color_blend_factor: BlendFactor::One,
+                            dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                            color_blend_op: BlendOp::Add,
+                            src_alpha_blend_factor: BlendFactor::OneMinusDstAlpha,
+                            dst_alpha_blend_factor: BlendFactor::One,
+                            alpha_blend_op: BlendOp::Add,
+                        }),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                    .into_iter()
+                    .collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap();
+
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+        let buffer_allocator = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::INDEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+        Self {
+            dev: dev.clone(),
+            format,
+            render_pass,
+            pipeline,
+            memory_allocator,
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            buffer_allocator,
+            textures: HashMap::new(),
+            next_user_texture: 0,
+            clear_color: [0.0; 4],
+        }
+    }
+    /// color the frame is cleared to before painting, transparent by default
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+    /// makes a caller owned image usable with [`::egui::Image`]
+    pub fn register_texture(&mut self, view: Arc<ImageView>, sampler: Arc<Sampler>) -> TextureId {
+        let id = TextureId::User(self.next_user_texture);
+        self.next_user_texture += 1;
+        let texture = Texture {
+            image: view.image().clone(),
+            descriptor_set: self.descriptor_set(view, sampler),
+        };
+        self.textures.insert(id, texture);
+        id
+    }
+    pub fn unregister_texture(&mut self, id: TextureId) {
+        self.textures.remove(&id);
+    }
+
+    /// Applies `textures_delta`, paints `primitives` into the frame and submits it
+    pub fn paint<T>(
+        &mut self,
+        frame: SwapchainFrameHandle<'_, T>,
+        queue: &Arc<Queue>,
+        primitives: &[ClippedPrimitive],
+        textures_delta: &TexturesDelta,
+        pixels_per_point: f32,
+    ) -> Result<DmatexSubmitInfo, SwapchainError> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        for (id, delta) in &textures_delta.set {
+            self.update_texture(&mut builder, *id, delta);
+        }
+        self.record_draw(&mut builder, frame.view(), primitives, pixels_per_point);
+        let command_buffer = builder.build().unwrap();
+        // the swapchain keeps the command buffer and with it the freed textures alive until
+        // the frame is rendered
+        for id in &textures_delta.free {
+            self.textures.remove(id);
+        }
+
+        frame.submit_command_buffer(&self.dev, queue, command_buffer)
+    }
+
+    fn record_draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view: Arc<ImageView>,
+        primitives: &[ClippedPrimitive],
+        pixels_per_point: f32,
+    ) {
+        let [width, height, _] = view.image().extent();
+        let output_srgb = self.format.is_srgb();
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![view],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(self.clear_color.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [width as f32, height as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    screen_size: [
+                        width as f32 / pixels_per_point,
+                        height as f32 / pixels_per_point,
+                    ],
+                    output_srgb: output_srgb as i32,
+                },
+            )
+            .unwrap();
+
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else {
+                // paint callbacks need access to the render pass, not supported
+                continue;
+            };
+            if mesh.indices.is_empty() || mesh.vertices.is_empty() {
+                continue;
+            }
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let min_x = (clip_rect.min.x * pixels_per_point).clamp(0.0, width as f32) as u32;
+            let min_y = (clip_rect.min.y * pixels_per_point).clamp(0.0, height as f32) as u32;
+            let max_x = (clip_rect.max.x * pixels_per_point).clamp(0.0, width as f32) as u32;
+            let max_y = (clip_rect.max.y * pixels_per_point).clamp(0.0, height as f32) as u32;
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+
+            let vertices = self
+                .buffer_allocator
+                .allocate_slice::<EguiVertex>(mesh.vertices.len() as u64)
+                .unwrap();
+            for (dst, src) in vertices.write().unwrap().iter_mut().zip(&mesh.vertices) {
+                *dst = src.into();
+            }
+            let indices = self
+                .buffer_allocator
+                .allocate_slice::<u32>(mesh.indices.len() as u64)
+                .unwrap();
+            indices.write().unwrap().copy_from_slice(&mesh.indices);
+
+            builder
+                .set_scissor(
+                    0,
+                    [Scissor {
+                        offset: [min_x, min_y],
+                        extent: [max_x - min_x, max_y - min_y],
+                    }]
+                    .into_iter()
+                    .collect(),
+                )
+                .unwrap()
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline.layout().clone(),
+                    0,
+                    texture.descriptor_set.clone(),
+                )
+                .unwrap()
+                .bind_vertex_buffers(0, vertices)
+                .unwrap()
+                .bind_index_buffer(indices)
+                .unwrap();
+            unsafe { builder.draw_indexed(mesh.indices.len() as u32, 1, 0, 0, 0) }.unwrap();
+        }
+        builder.end_render_pass(Default::default()).unwrap();
+    }
+
+    fn update_texture(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        id: TextureId,
+        delta: &::egui::epaint::ImageDelta,
+    ) {
+        let pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+            ImageData::Font(image) => image
+                .srgba_pixels(None)
+                .flat_map(|c| c.to_array())
+                .collect(),
+        };
+        let [width, height] = delta.image.size().map(|v| v as u32);
+        let staging = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            pixels,
+        )
+        .unwrap();
+
+        let (image, offset) = match (delta.pos, self.textures.get(&id)) {
+            (Some([x, y]), Some(texture)) => (texture.image.clone(), [x as u32, y as u32, 0]),
+            _ => {
+                let image = Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format: Format::R8G8B8A8_SRGB,
+                        extent: [width, height, 1],
+                        usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .unwrap();
+                let filter = |filter: TextureFilter| match filter {
+                    TextureFilter::Nearest => Filter::Nearest,
+                    TextureFilter::Linear => Filter::Linear,
+                };
+                let sampler = Sampler::new(
+                    self.dev.clone(),
+                    SamplerCreateInfo {
+                        mag_filter: filter(delta.options.magnification),
+                        min_filter: filter(delta.options.minification),
+                        address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                let view = ImageView::new_default(image.clone()).unwrap();
+                let texture = Texture {
+                    image: image.clone(),
+                    descriptor_set: self.descriptor_set(view, sampler),
+                };
+                self.textures.insert(id, texture);
+                (image, [0; 3])
+            }
+        };
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    image_subresource: image.subresource_layers(),
+                    image_offset: offset,
+                    image_extent: [width, height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(staging, image)
+            })
+            .unwrap();
+    }
+
+    fn descriptor_set(&self, view: Arc<ImageView>, sampler: Arc<Sampler>) -> Arc<DescriptorSet> {
+        DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view_sampler(0, view, sampler)],
+            [],
+        )
+        .unwrap()
+    }
+}
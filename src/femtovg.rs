@@ -0,0 +1,291 @@
+//! Vector graphics with femtovg for lightweight HUDs and widgets, rendered through opengl es
+//!
+//! Femtovg has no vulkan renderer, so the swapchain images are imported into a surfaceless
+//! EGL context as dmabufs. Mesa has to pick the same gpu for EGL as the vulkan device uses.
+//! Every frame waits for the server's release and for the gl work on the cpu, like
+//! [`host_sync`](crate::swapchain::SwapchainBuilder::host_sync).
+
+use std::{ffi::c_void, mem::ManuallyDrop, os::fd::AsRawFd, sync::Arc};
+
+use ::femtovg::{
+    Canvas, ErrorKind, ImageFlags, ImageId, ImageInfo, RenderTarget, renderer::OpenGl,
+};
+use glow::HasContext;
+use khronos_egl as egl;
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use vulkano::{
+    command_buffer::{SemaphoreSubmitInfo, SubmitInfo},
+    device::{Device, Queue},
+    image::ImageUsage,
+    sync::PipelineStages,
+};
+
+use crate::{
+    backend::Backend,
+    dmatex::{Dmatex, DmatexHandleError},
+    format::DmatexFormat,
+    render_device::RenderDevice,
+    swapchain::{Swapchain, SwapchainError},
+};
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+const LINUX_DMA_BUF_EXT: egl::Enum = 0x3270;
+const LINUX_DRM_FOURCC_EXT: egl::Attrib = 0x3271;
+const DMA_BUF_PLANE0_FD_EXT: egl::Attrib = 0x3272;
+const DMA_BUF_PLANE0_OFFSET_EXT: egl::Attrib = 0x3273;
+const DMA_BUF_PLANE0_PITCH_EXT: egl::Attrib = 0x3274;
+const DMA_BUF_PLANE0_MODIFIER_LO_EXT: egl::Attrib = 0x3443;
+const DMA_BUF_PLANE0_MODIFIER_HI_EXT: egl::Attrib = 0x3444;
+
+type ImageTargetTexture2d = unsafe extern "system" fn(target: u32, image: *const c_void);
+
+/// A swapchain femtovg draws into
+pub struct VectorSurface {
+    /// dropped by hand while the gl context is still current
+    canvas: ManuallyDrop<Canvas<OpenGl>>,
+    gl: glow::Context,
+    images: Vec<ImportedImage>,
+    egl: egl::Instance<egl::Static>,
+    display: egl::Display,
+    context: egl::Context,
+    swapchain: Swapchain,
+    dev: Arc<Device>,
+}
+
+/// a swapchain image as gl texture and femtovg render target
+struct ImportedImage {
+    egl_image: egl::Image,
+    texture: glow::Texture,
+    id: ImageId,
+}
+
+impl VectorSurface {
+    /// `format` has to be a single plane format EGL can render to, usually rgba8
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        width: u32,
+        height: u32,
+        format: &DmatexFormat,
+    ) -> Result<Self, VectorError> {
+        let egl = egl::Instance::new(egl::Static);
+        let display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+        }?;
+        egl.initialize(display)?;
+        egl.bind_api(egl::OPENGL_ES_API)?;
+        let config = egl
+            .choose_first_config(
+                display,
+                &[egl::RENDERABLE_TYPE, egl::OPENGL_ES3_BIT, egl::NONE],
+            )?
+            .ok_or(VectorError::NoConfig)?;
+        let context = egl.create_context(
+            display,
+            config,
+            None,
+            &[egl::CONTEXT_MAJOR_VERSION, 3, egl::NONE],
+        )?;
+        egl.make_current(display, None, None, Some(context))?;
+        let load = |name: &str| {
+            egl.get_proc_address(name)
+                .map_or(std::ptr::null(), |f| f as *const c_void)
+        };
+        let image_target_texture = load("glEGLImageTargetTexture2DOES");
+        if image_target_texture.is_null() {
+            return Err(VectorError::NoDmabufImport);
+        }
+        let image_target_texture: ImageTargetTexture2d =
+            unsafe { std::mem::transmute(image_target_texture) };
+        // one for femtovg and one for importing, both call into the same current context
+        let gl = unsafe { glow::Context::from_loader_function(load) };
+        let renderer =
+            OpenGl::new_from_glow_context(unsafe { glow::Context::from_loader_function(load) })?;
+        let mut canvas = Canvas::new(renderer)?;
+        canvas.set_size(width, height, 1.0);
+
+        let swapchain = Swapchain::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim2D([width, height].into()),
+            format,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+        )
+        .build()?;
+        let images = swapchain
+            .images()
+            .iter()
+            .map(|dmatex| {
+                import_image(
+                    &egl,
+                    display,
+                    &gl,
+                    &mut canvas,
+                    image_target_texture,
+                    dmatex,
+                    width,
+                    height,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            canvas: ManuallyDrop::new(canvas),
+            gl,
+            images,
+            egl,
+            display,
+            context,
+            swapchain,
+            dev: dev.clone(),
+        })
+    }
+    pub fn canvas(&mut self) -> &mut Canvas<OpenGl> {
+        &mut self.canvas
+    }
+    /// Draws the next image with `draw` and submits it, the whole image is redrawn every frame
+    pub fn draw(
+        &mut self,
+        queue: &Arc<Queue>,
+        draw: impl FnOnce(&mut Canvas<OpenGl>),
+    ) -> Result<DmatexSubmitInfo, VectorError> {
+        self.egl
+            .make_current(self.display, None, None, Some(self.context))?;
+        let frame = self.swapchain.prepare_next_image();
+        frame.blocking_release_wait();
+        self.canvas
+            .set_render_target(RenderTarget::Image(self.images[frame.index()].id));
+        draw(&mut self.canvas);
+        self.canvas.flush();
+        unsafe { self.gl.finish() };
+        // gl is done, this just hands the image to the server
+        let info = frame.submit(&self.dev, queue, |wait, mut guard, signal| unsafe {
+            guard.submit(
+                &[SubmitInfo {
+                    wait_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(wait)
+                    }],
+                    signal_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(signal)
+                    }],
+                    ..Default::default()
+                }],
+                None,
+            )
+        })?;
+        Ok(info)
+    }
+}
+
+impl Drop for VectorSurface {
+    fn drop(&mut self) {
+        let _ = self
+            .egl
+            .make_current(self.display, None, None, Some(self.context));
+        for image in self.images.drain(..) {
+            self.canvas.delete_image(image.id);
+            unsafe { self.gl.delete_texture(image.texture) };
+            let _ = self.egl.destroy_image(self.display, image.egl_image);
+        }
+        unsafe { ManuallyDrop::drop(&mut self.canvas) };
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn import_image(
+    egl: &egl::Instance<egl::Static>,
+    display: egl::Display,
+    gl: &glow::Context,
+    canvas: &mut Canvas<OpenGl>,
+    image_target_texture: ImageTargetTexture2d,
+    dmatex: &Dmatex,
+    width: u32,
+    height: u32,
+) -> Result<ImportedImage, VectorError> {
+    let handle = dmatex.to_handle()?;
+    let [plane] = handle.planes.as_slice() else {
+        return Err(VectorError::MultiPlane);
+    };
+    // egl dups the fd, the handle can be dropped afterwards
+    let egl_image = egl.create_image(
+        display,
+        unsafe { egl::Context::from_ptr(egl::NO_CONTEXT) },
+        LINUX_DMA_BUF_EXT,
+        unsafe { egl::ClientBuffer::from_ptr(std::ptr::null_mut()) },
+        &[
+            egl::WIDTH as egl::Attrib,
+            width as egl::Attrib,
+            egl::HEIGHT as egl::Attrib,
+            height as egl::Attrib,
+            LINUX_DRM_FOURCC_EXT,
+            handle.fourcc as egl::Attrib,
+            DMA_BUF_PLANE0_FD_EXT,
+            plane.fd.as_raw_fd() as egl::Attrib,
+            DMA_BUF_PLANE0_OFFSET_EXT,
+            plane.offset as egl::Attrib,
+            DMA_BUF_PLANE0_PITCH_EXT,
+            plane.row_pitch as egl::Attrib,
+            DMA_BUF_PLANE0_MODIFIER_LO_EXT,
+            (handle.modifier & 0xffff_ffff) as egl::Attrib,
+            DMA_BUF_PLANE0_MODIFIER_HI_EXT,
+            (handle.modifier >> 32) as egl::Attrib,
+            egl::ATTRIB_NONE,
+        ],
+    )?;
+    let texture = unsafe {
+        let texture = gl.create_texture().map_err(VectorError::Gl)?;
+        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+        image_target_texture(glow::TEXTURE_2D, egl_image.as_ptr());
+        gl.bind_texture(glow::TEXTURE_2D, None);
+        texture
+    };
+    let id = canvas.create_image_from_native_texture(
+        texture,
+        ImageInfo::new(
+            ImageFlags::empty(),
+            width as usize,
+            height as usize,
+            ::femtovg::PixelFormat::Rgba8,
+        ),
+    )?;
+    Ok(ImportedImage {
+        egl_image,
+        texture,
+        id,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum VectorError {
+    #[error("egl error: {0}")]
+    Egl(#[from] egl::Error),
+    #[error("gl error: {0}")]
+    Gl(String),
+    #[error("femtovg error: {0:?}")]
+    Femtovg(ErrorKind),
+    #[error("no egl config for opengl es 3")]
+    NoConfig,
+    #[error("egl can't import dmabufs as textures")]
+    NoDmabufImport,
+    #[error("only single plane formats can be rendered with femtovg")]
+    MultiPlane,
+    #[error(transparent)]
+    Handle(#[from] DmatexHandleError),
+    #[error(transparent)]
+    Swapchain(#[from] SwapchainError),
+}
+impl From<ErrorKind> for VectorError {
+    fn from(err: ErrorKind) -> Self {
+        Self::Femtovg(err)
+    }
+}
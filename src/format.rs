@@ -1,166 +1,319 @@
 use std::{collections::HashMap, sync::Arc};
 
 use drm_fourcc::DrmFourcc;
-use stardust_xr_fusion::{ClientHandle, drawable::enumerate_dmatex_formats, node::NodeResult};
-use tracing::{error, warn};
+use stardust_xr_fusion::node::NodeError;
+use thiserror::Error;
 use vulkano::format::Format;
 
-use crate::render_device::RenderDevice;
+pub use crate::fourcc::{
+    AlphaMode, VulkanoFormatExtension, compressed_format_from_fourcc, compressed_format_to_fourcc,
+    compressed_fourcc, to_drm_fourcc,
+};
+use crate::{
+    backend::{Backend, ServerFormat},
+    diagnostics::{DiagnosticEvent, report},
+    render_device::RenderDevice,
+};
 
 // TODO: Docs
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SerializedFormat", try_from = "SerializedFormat")
+)]
 pub struct DmatexFormat {
     format: Format,
-    fourcc: DrmFourcc,
+    fourcc: u32,
     variants: Vec<DmatexFormatVariant>,
+    alternate_fourccs: Vec<u32>,
 }
 impl DmatexFormat {
     pub fn vk_format(&self) -> Format {
         self.format
     }
-    pub fn drm_fourcc(&self) -> DrmFourcc {
+    /// the raw fourcc the server advertised
+    pub fn fourcc(&self) -> u32 {
         self.fourcc
     }
+    /// None for the [compressed formats](compressed_format_from_fourcc), drm has no fourccs
+    /// for those
+    pub fn drm_fourcc(&self) -> Option<DrmFourcc> {
+        DrmFourcc::try_from(self.fourcc).ok()
+    }
+    /// block compressed formats can only be uploaded to, not rendered to
+    pub fn is_compressed(&self) -> bool {
+        self.format.compression().is_some()
+    }
     pub fn variants(&self) -> &[DmatexFormatVariant] {
         &self.variants
     }
+    /// Other fourccs the server advertised for the same vulkan format, e.g. `Xrgb8888` next
+    /// to `Argb8888`. Dmatexes always use [`Self::fourcc`], the one with alpha
+    pub fn alternate_fourccs(&self) -> &[u32] {
+        &self.alternate_fourccs
+    }
 }
 impl DmatexFormat {
-    pub async fn enumerate(
-        client: &Arc<ClientHandle>,
+    /// The formats the server can import for `render_device`, formats without a vulkan
+    /// equivalent are logged and skipped
+    pub async fn enumerate<B: Backend>(
+        client: &Arc<B>,
+        render_device: &RenderDevice,
+    ) -> Result<HashMap<Format, DmatexFormat>, EnumerateError> {
+        let (formats, unmapped) = Self::enumerate_with_unmapped(client, render_device).await?;
+        for v in unmapped {
+            report(DiagnosticEvent::FormatSkipped(v));
+        }
+        Ok(formats)
+    }
+    /// Like [`Self::enumerate`], but fails with [`EnumerateError::Unmapped`] if any format
+    /// couldn't be mapped
+    pub async fn enumerate_strict<B: Backend>(
+        client: &Arc<B>,
         render_device: &RenderDevice,
-    ) -> NodeResult<HashMap<Format, DmatexFormat>> {
-        let formats = enumerate_dmatex_formats(client, render_device.drm_node_id()).await?;
-        let mut out = HashMap::new();
-        for v in formats {
-            let Ok(fourcc) = drm_fourcc::DrmFourcc::try_from(v.format) else {
-                error!("unable to parse drm_fourcc: {:X}", v.format);
+    ) -> Result<HashMap<Format, DmatexFormat>, EnumerateError> {
+        let (formats, unmapped) = Self::enumerate_with_unmapped(client, render_device).await?;
+        if !unmapped.is_empty() {
+            return Err(EnumerateError::Unmapped(unmapped));
+        }
+        Ok(formats)
+    }
+    /// the mapped formats together with every server format that couldn't be mapped
+    pub async fn enumerate_with_unmapped<B: Backend>(
+        client: &Arc<B>,
+        render_device: &RenderDevice,
+    ) -> Result<(HashMap<Format, DmatexFormat>, Vec<UnmappedFormat>), EnumerateError> {
+        let formats = client
+            .clone()
+            .enumerate_dmatex_formats(render_device.drm_node_id())
+            .await?;
+        Ok(map_formats(formats))
+    }
+}
+
+fn map_formats(formats: Vec<ServerFormat>) -> (HashMap<Format, DmatexFormat>, Vec<UnmappedFormat>) {
+    let mut out = HashMap::new();
+    let mut unmapped = Vec::new();
+    for v in formats {
+        let format = match map_format(&v) {
+            Ok(format) => format,
+            Err(reason) => {
+                unmapped.push(UnmappedFormat {
+                    fourcc: v.fourcc,
+                    is_srgb: v.is_srgb,
+                    drm_modifier: v.drm_modifier,
+                    reason,
+                });
                 continue;
-            };
-            let Some(format) = Format::from_drm_fourcc(fourcc) else {
-                warn!("failed to get vulkan format for drm_fourcc: {fourcc}");
+            }
+        };
+        let entry = out.entry(format).or_insert_with(|| DmatexFormat {
+            format,
+            fourcc: v.fourcc,
+            variants: vec![],
+            alternate_fourccs: vec![],
+        });
+        if entry.fourcc != v.fourcc {
+            // e.g. Argb8888 and Xrgb8888 are both B8G8R8A8, a format can only have
+            // one fourcc so the one with alpha wins and the other is kept as an alternate
+            let preferred = to_drm_fourcc(format, AlphaMode::Alpha).map(|v| v as u32);
+            if preferred != Some(v.fourcc) {
+                if !entry.alternate_fourccs.contains(&v.fourcc) {
+                    entry.alternate_fourccs.push(v.fourcc);
+                }
                 continue;
-            };
-            let format = if v.is_srgb {
-                let Some(format) = format.to_srgb() else {
-                    warn!("failed to do srgb conversion for: {format:?}");
-                    continue;
-                };
-                format
-            } else {
-                format
-            };
-            out.entry(format)
-                .or_insert_with(|| DmatexFormat {
-                    format,
-                    fourcc,
-                    variants: vec![],
-                })
-                .variants
-                .push(DmatexFormatVariant {
-                    modifier: v.drm_modifier,
-                    planes: v.planes,
-                });
+            }
+            let replaced = std::mem::replace(&mut entry.fourcc, v.fourcc);
+            entry.variants.clear();
+            entry.alternate_fourccs.retain(|v| *v != entry.fourcc);
+            if !entry.alternate_fourccs.contains(&replaced) {
+                entry.alternate_fourccs.push(replaced);
+            }
         }
+        entry.variants.push(DmatexFormatVariant {
+            modifier: v.drm_modifier,
+            planes: v.planes,
+        });
+    }
+    (out, unmapped)
+}
 
-        Ok(out)
+fn map_format(v: &ServerFormat) -> Result<Format, UnmappedReason> {
+    let format = match DrmFourcc::try_from(v.fourcc) {
+        Ok(fourcc) => Format::from_drm_fourcc(fourcc).ok_or(UnmappedReason::NoVulkanFormat)?,
+        Err(_) => compressed_format_from_fourcc(v.fourcc).ok_or(UnmappedReason::UnknownFourcc)?,
+    };
+    if v.is_srgb {
+        format.to_srgb().ok_or(UnmappedReason::NoSrgbVariant)
+    } else {
+        Ok(format)
     }
 }
-#[derive(Debug, Clone, Copy)]
-pub struct DmatexFormatVariant {
-    pub modifier: u64,
-    pub planes: u32,
+
+/// A format the server advertised that has no vulkan equivalent here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappedFormat {
+    pub fourcc: u32,
+    pub is_srgb: bool,
+    pub drm_modifier: u64,
+    pub reason: UnmappedReason,
+}
+impl std::fmt::Display for UnmappedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fourcc = match DrmFourcc::try_from(self.fourcc) {
+            Ok(fourcc) => fourcc.to_string(),
+            Err(_) => format!("{:X}", self.fourcc),
+        };
+        let srgb = if self.is_srgb { " srgb" } else { "" };
+        write!(
+            f,
+            "{fourcc}{srgb} with modifier {:X}: {}",
+            self.drm_modifier, self.reason
+        )
+    }
 }
 
-pub trait VulkanoFormatExtension: Sized {
-    fn from_drm_fourcc(drm_format: drm_fourcc::DrmFourcc) -> Option<Self>;
-    fn to_drm_fourcc(&self) -> Option<&'static [drm_fourcc::DrmFourcc]>;
-    fn to_srgb(&self) -> Option<Self>;
-}
-impl VulkanoFormatExtension for Format {
-    fn from_drm_fourcc(drm_format: drm_fourcc::DrmFourcc) -> Option<Self> {
-        use Format as F;
-        use drm_fourcc::DrmFourcc as D;
-        Some(match drm_format {
-            D::Abgr1555 | D::Xbgr1555 => F::R5G5B5A1_UNORM_PACK16,
-            D::Abgr2101010 | D::Xbgr2101010 => F::A2B10G10R10_UNORM_PACK32,
-            D::Abgr4444 | D::Xbgr4444 => F::A4B4G4R4_UNORM_PACK16,
-            D::Abgr8888 | D::Xbgr8888 => F::R8G8B8A8_UNORM,
-            D::Argb1555 | D::Xrgb1555 => F::A1R5G5B5_UNORM_PACK16,
-            D::Argb2101010 | D::Xrgb2101010 => F::A2R10G10B10_UNORM_PACK32,
-            D::Argb4444 | D::Xrgb4444 => F::B4G4R4A4_UNORM_PACK16,
-            D::Argb8888 | D::Xrgb8888 => F::B8G8R8A8_UNORM,
-            D::Bgr565 => F::B5G6R5_UNORM_PACK16,
-            D::Bgr888 => F::B8G8R8_UNORM,
-            // D::Bgr888_a8 => F::B8G8R8A8_UNORM,
-            D::Bgra4444 | D::Bgrx4444 => F::B4G4R4A4_UNORM_PACK16,
-            D::Bgra5551 | D::Bgrx5551 => F::B5G5R5A1_UNORM_PACK16,
-            D::Bgra8888 | D::Bgrx8888 => F::B8G8R8A8_UNORM,
-            D::R16 => F::R16_UNORM,
-            D::R8 => F::R8_UNORM,
-            D::Rg1616 => F::R16G16_UNORM,
-            D::Rg88 => F::R8G8_UNORM,
-            D::Rgb565 => F::R5G6B5_UNORM_PACK16,
-            D::Rgb888 => F::R8G8B8_UNORM,
-            // D::Rgb888_a8 => F::R8G8B8A8_UNORM,
-            D::Rgba4444 | D::Rgbx4444 => F::R4G4B4A4_UNORM_PACK16,
-            D::Rgba5551 | D::Rgbx5551 => F::R5G5B5A1_UNORM_PACK16,
-            D::Rgba8888 | D::Rgbx8888 => F::R8G8B8A8_UNORM,
-            D::Abgr16161616f => F::R16G16B16A16_SFLOAT,
-            _ => return None,
-        })
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum UnmappedReason {
+    #[error("not a known drm fourcc")]
+    UnknownFourcc,
+    #[error("no matching vulkan format")]
+    NoVulkanFormat,
+    #[error("no srgb variant of the vulkan format")]
+    NoSrgbVariant,
+}
+
+#[derive(Debug, Error)]
+pub enum EnumerateError {
+    #[error("failed to get the formats from the server: {0}")]
+    Node(#[from] NodeError),
+    #[error("{} server formats couldn't be mapped", .0.len())]
+    Unmapped(Vec<UnmappedFormat>),
+}
+
+/// The negotiated formats, cached so they can be enumerated again with [`Self::refresh`]
+/// after the server switched gpus
+pub struct FormatTable {
+    client: Arc<dyn Backend>,
+    strict: bool,
+    formats: HashMap<Format, DmatexFormat>,
+    unmapped: Vec<UnmappedFormat>,
+}
+impl FormatTable {
+    /// Enumerates the formats for `render_device`, `strict` turns unmapped formats into
+    /// an error like [`DmatexFormat::enumerate_strict`]
+    pub async fn new<B: Backend>(
+        client: &Arc<B>,
+        render_device: &RenderDevice,
+        strict: bool,
+    ) -> Result<Self, EnumerateError> {
+        let mut table = Self {
+            client: client.clone(),
+            strict,
+            formats: HashMap::new(),
+            unmapped: Vec::new(),
+        };
+        table.refresh(render_device).await?;
+        Ok(table)
     }
-    fn to_drm_fourcc(&self) -> Option<&'static [drm_fourcc::DrmFourcc]> {
-        use Format as F;
-        use drm_fourcc::DrmFourcc as D;
-        Some(match self {
-            F::R5G5B5A1_UNORM_PACK16 => &[D::Abgr1555, D::Xbgr1555, D::Rgba5551, D::Rgbx5551],
-            F::A2B10G10R10_UNORM_PACK32 => &[D::Abgr2101010, D::Xbgr2101010],
-            F::A4B4G4R4_UNORM_PACK16 => &[D::Abgr4444, D::Xbgr4444],
-            F::R8G8B8A8_UNORM | F::R8G8B8A8_SRGB => {
-                &[D::Abgr8888, D::Xbgr8888, D::Rgba8888, D::Rgbx8888]
-            }
-            F::A1R5G5B5_UNORM_PACK16 => &[D::Argb1555, D::Xrgb1555],
-            F::A2R10G10B10_UNORM_PACK32 => &[D::Argb2101010, D::Xrgb2101010],
-            F::B4G4R4A4_UNORM_PACK16 => &[D::Argb4444, D::Xrgb4444, D::Bgra4444, D::Bgrx4444],
-            F::B8G8R8A8_UNORM | F::B8G8R8A8_SRGB => {
-                &[D::Argb8888, D::Xrgb8888, D::Bgra8888, D::Bgrx8888]
-            }
-            F::B5G6R5_UNORM_PACK16 => &[D::Bgr565],
-            F::B8G8R8_UNORM | F::B8G8R8_SRGB => &[D::Bgr888],
-            // D::Bgr888_a8 => F::B8G8R8A8_UNORM,
-            F::B5G5R5A1_UNORM_PACK16 => &[D::Bgra5551, D::Bgrx5551],
-            F::R16_UNORM => &[D::R16],
-            F::R8_UNORM | F::R8_SRGB => &[D::R8],
-            F::R16G16_UNORM => &[D::Rg1616],
-            F::R8G8_UNORM | F::R8G8_SRGB => &[D::Rg88],
-            F::R5G6B5_UNORM_PACK16 => &[D::Rgb565],
-            F::R8G8B8_UNORM | F::R8G8B8_SRGB => &[D::Rgb888],
-            // D::Rgb888_a8 => F::R8G8B8A8_UNORM,
-            F::R4G4B4A4_UNORM_PACK16 => &[D::Rgba4444, D::Rgbx4444],
-            F::R16G16B16A16_SFLOAT => &[D::Abgr16161616f],
-            _ => return None,
-        })
+    /// Enumerates the formats again, e.g. for a new [`RenderDevice`]. The old table is
+    /// kept if this fails, so it can simply be retried
+    pub async fn refresh(&mut self, render_device: &RenderDevice) -> Result<(), EnumerateError> {
+        let formats = self
+            .client
+            .clone()
+            .enumerate_dmatex_formats(render_device.drm_node_id())
+            .await?;
+        let (formats, unmapped) = map_formats(formats);
+        if self.strict && !unmapped.is_empty() {
+            return Err(EnumerateError::Unmapped(unmapped));
+        }
+        for v in &unmapped {
+            report(DiagnosticEvent::FormatSkipped(*v));
+        }
+        self.formats = formats;
+        self.unmapped = unmapped;
+        Ok(())
+    }
+    pub fn get(&self, format: Format) -> Option<&DmatexFormat> {
+        self.formats.get(&format)
+    }
+    pub fn formats(&self) -> &HashMap<Format, DmatexFormat> {
+        &self.formats
+    }
+    /// the server formats skipped by the last refresh
+    pub fn unmapped(&self) -> &[UnmappedFormat] {
+        &self.unmapped
+    }
+}
+/// The vulkan format is derived from the fourcc again when deserializing,
+/// vulkano's [`Format`] can't be serialized itself
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedFormat {
+    fourcc: u32,
+    srgb: bool,
+    /// only for humans reading the output
+    #[serde(default)]
+    vk_format: String,
+    variants: Vec<DmatexFormatVariant>,
+    #[serde(default)]
+    alternate_fourccs: Vec<u32>,
+}
+#[cfg(feature = "serde")]
+impl From<DmatexFormat> for SerializedFormat {
+    fn from(format: DmatexFormat) -> Self {
+        Self {
+            fourcc: format.fourcc,
+            srgb: format.format.is_srgb(),
+            vk_format: format!("{:?}", format.format),
+            variants: format.variants,
+            alternate_fourccs: format.alternate_fourccs,
+        }
     }
-    fn to_srgb(&self) -> Option<Self> {
-        use Format as F;
-        Some(match self {
-            F::R8_UNORM => F::R8_SRGB,
-            F::R8G8_UNORM => F::R8G8_SRGB,
-            F::R8G8B8_UNORM => F::R8G8B8_SRGB,
-            F::B8G8R8_UNORM => F::B8G8R8_SRGB,
-            F::R8G8B8A8_UNORM => F::R8G8B8A8_SRGB,
-            F::B8G8R8A8_UNORM => F::B8G8R8A8_SRGB,
-            F::A8B8G8R8_UNORM_PACK32 => F::A8B8G8R8_SRGB_PACK32,
-            F::BC1_RGB_UNORM_BLOCK => F::BC1_RGB_SRGB_BLOCK,
-            F::BC1_RGBA_UNORM_BLOCK => F::BC1_RGBA_SRGB_BLOCK,
-            F::BC2_UNORM_BLOCK => F::BC2_SRGB_BLOCK,
-            F::BC3_UNORM_BLOCK => F::BC3_SRGB_BLOCK,
-            F::BC7_UNORM_BLOCK => F::BC7_SRGB_BLOCK,
-            F::ETC2_R8G8B8_UNORM_BLOCK => F::ETC2_R8G8B8_SRGB_BLOCK,
-            F::ETC2_R8G8B8A1_UNORM_BLOCK => F::ETC2_R8G8B8A1_SRGB_BLOCK,
-            F::ETC2_R8G8B8A8_UNORM_BLOCK => F::ETC2_R8G8B8A8_SRGB_BLOCK,
-            _ => return None,
+}
+#[cfg(feature = "serde")]
+impl TryFrom<SerializedFormat> for DmatexFormat {
+    type Error = String;
+
+    fn try_from(v: SerializedFormat) -> Result<Self, Self::Error> {
+        let format = match DrmFourcc::try_from(v.fourcc) {
+            Ok(fourcc) => Format::from_drm_fourcc(fourcc),
+            Err(_) => compressed_format_from_fourcc(v.fourcc),
+        }
+        .ok_or_else(|| format!("no vulkan format for fourcc {:X}", v.fourcc))?;
+        let format = if v.srgb {
+            format
+                .to_srgb()
+                .ok_or_else(|| format!("no srgb variant of {format:?}"))?
+        } else {
+            format
+        };
+        Ok(Self {
+            format,
+            fourcc: v.fourcc,
+            variants: v.variants,
+            alternate_fourccs: v.alternate_fourccs,
         })
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmatexFormatVariant {
+    pub modifier: u64,
+    pub planes: u32,
+}
+
+/// How the server should interpret the values in a dmatex
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+    /// bt.709 primaries with the srgb transfer function, what servers assume without a colorspace
+    Srgb,
+    /// bt.709 primaries, linear and extended past 0..1, usually with an fp16 format
+    ScRgb,
+    /// bt.2020 primaries with the pq transfer function, usually with a 10 or 16 bit format
+    Bt2020Pq,
+}
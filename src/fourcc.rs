@@ -0,0 +1,279 @@
+//! Mappings between drm fourccs and vulkan formats, usable with just the `formats` feature
+
+use drm_fourcc::DrmFourcc;
+use vulkano::format::{Format, NumericFormat};
+
+const fn fourcc_code(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// Fourccs for block compressed formats, drm doesn't define any so these are specific to
+/// stardust. They can only be used with the linear modifier.
+pub mod compressed_fourcc {
+    use super::fourcc_code;
+
+    pub const BC1: u32 = fourcc_code(b"BC1 ");
+    pub const BC3: u32 = fourcc_code(b"BC3 ");
+    pub const BC4: u32 = fourcc_code(b"BC4 ");
+    pub const BC5: u32 = fourcc_code(b"BC5 ");
+    pub const BC7: u32 = fourcc_code(b"BC7 ");
+    pub const ETC2_RGB8: u32 = fourcc_code(b"ET2 ");
+    pub const ETC2_RGB8A1: u32 = fourcc_code(b"ET21");
+    pub const ETC2_RGBA8: u32 = fourcc_code(b"ET2A");
+    pub const ASTC_4X4: u32 = fourcc_code(b"AS44");
+    pub const ASTC_8X8: u32 = fourcc_code(b"AS88");
+}
+
+const COMPRESSED_FORMATS: &[(u32, Format)] = {
+    use Format as F;
+    use compressed_fourcc as C;
+    &[
+        (C::BC1, F::BC1_RGBA_UNORM_BLOCK),
+        (C::BC3, F::BC3_UNORM_BLOCK),
+        (C::BC4, F::BC4_UNORM_BLOCK),
+        (C::BC5, F::BC5_UNORM_BLOCK),
+        (C::BC7, F::BC7_UNORM_BLOCK),
+        (C::ETC2_RGB8, F::ETC2_R8G8B8_UNORM_BLOCK),
+        (C::ETC2_RGB8A1, F::ETC2_R8G8B8A1_UNORM_BLOCK),
+        (C::ETC2_RGBA8, F::ETC2_R8G8B8A8_UNORM_BLOCK),
+        (C::ASTC_4X4, F::ASTC_4x4_UNORM_BLOCK),
+        (C::ASTC_8X8, F::ASTC_8x8_UNORM_BLOCK),
+    ]
+};
+
+/// the vulkan format for one of the [`compressed_fourcc`]s, always the unorm variant
+pub fn compressed_format_from_fourcc(fourcc: u32) -> Option<Format> {
+    COMPRESSED_FORMATS
+        .iter()
+        .find(|(v, _)| *v == fourcc)
+        .map(|(_, format)| *format)
+}
+
+/// the [`compressed_fourcc`] of a block compressed format, srgb and unorm map to the same one
+pub fn compressed_format_to_fourcc(format: Format) -> Option<u32> {
+    COMPRESSED_FORMATS
+        .iter()
+        .find(|(_, v)| *v == format || v.to_srgb() == Some(format))
+        .map(|(fourcc, _)| *fourcc)
+}
+
+/// Every vulkan format with the fourccs that describe the same pixels, fourccs with alpha come
+/// before their x channel variant. srgb formats use the fourccs of their unorm variant.
+///
+/// Drm fourccs name their channels from the most significant bit of a little endian word,
+/// so `Abgr8888` is r, g, b, a in memory like `R8G8B8A8_UNORM` and `Gr88` is `R8G8_UNORM`.
+/// Fourccs whose channel order no vulkan format has, like `Rgba8888`, `Bgra8888`, `Rg88`,
+/// `Rg1616` or `Abgr1555`, stay unmapped. So do `Bgr888_a8`/`Rgb888_a8` and the other
+/// `_a8` fourccs, they keep alpha in a separate plane
+const FOURCC_FORMATS: &[(Format, &[DrmFourcc])] = {
+    use DrmFourcc as D;
+    use Format as F;
+    &[
+        (F::R5G5B5A1_UNORM_PACK16, &[D::Rgba5551, D::Rgbx5551]),
+        (
+            F::A2B10G10R10_UNORM_PACK32,
+            &[D::Abgr2101010, D::Xbgr2101010],
+        ),
+        (F::A4B4G4R4_UNORM_PACK16, &[D::Abgr4444, D::Xbgr4444]),
+        (F::R8G8B8A8_UNORM, &[D::Abgr8888, D::Xbgr8888]),
+        (F::A1R5G5B5_UNORM_PACK16, &[D::Argb1555, D::Xrgb1555]),
+        (
+            F::A2R10G10B10_UNORM_PACK32,
+            &[D::Argb2101010, D::Xrgb2101010],
+        ),
+        (F::A4R4G4B4_UNORM_PACK16, &[D::Argb4444, D::Xrgb4444]),
+        (F::B4G4R4A4_UNORM_PACK16, &[D::Bgra4444, D::Bgrx4444]),
+        (F::B8G8R8A8_UNORM, &[D::Argb8888, D::Xrgb8888]),
+        (F::B5G6R5_UNORM_PACK16, &[D::Bgr565]),
+        (F::B8G8R8_UNORM, &[D::Rgb888]),
+        (F::B5G5R5A1_UNORM_PACK16, &[D::Bgra5551, D::Bgrx5551]),
+        (F::R16_UNORM, &[D::R16]),
+        (F::R8_UNORM, &[D::R8]),
+        (F::R16G16_UNORM, &[D::Gr1616]),
+        (F::R8G8_UNORM, &[D::Gr88]),
+        (F::R5G6B5_UNORM_PACK16, &[D::Rgb565]),
+        (F::R8G8B8_UNORM, &[D::Bgr888]),
+        (F::R4G4B4A4_UNORM_PACK16, &[D::Rgba4444, D::Rgbx4444]),
+        (
+            F::R16G16B16A16_SFLOAT,
+            &[D::Abgr16161616f, D::Xbgr16161616f],
+        ),
+        (F::R16G16B16A16_UNORM, &[D::Abgr16161616, D::Xbgr16161616]),
+        (F::G8_B8R8_2PLANE_420_UNORM, &[D::Nv12]),
+        (F::G8_B8R8_2PLANE_422_UNORM, &[D::Nv16]),
+        (F::G8_B8_R8_3PLANE_420_UNORM, &[D::Yuv420]),
+        (F::G8B8G8R8_422_UNORM, &[D::Yuyv]),
+        (F::B8G8R8G8_422_UNORM, &[D::Uyvy]),
+        (F::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16, &[D::P010]),
+        (F::G12X4_B12X4R12X4_2PLANE_420_UNORM_3PACK16, &[D::P012]),
+        (F::G16_B16R16_2PLANE_420_UNORM, &[D::P016]),
+    ]
+};
+
+/// Which of the fourccs sharing a vulkan format's layout [`to_drm_fourcc`] picks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// the alpha channel is used, e.g. `Argb8888`
+    Alpha,
+    /// the alpha channel is ignored, e.g. `Xrgb8888`
+    Opaque,
+}
+
+/// The fourcc to request for `format`, srgb formats use the fourcc of their unorm variant.
+/// Formats without an alpha or x channel return their only fourcc regardless of `alpha`
+pub fn to_drm_fourcc(format: Format, alpha: AlphaMode) -> Option<DrmFourcc> {
+    let fourccs = format.to_drm_fourcc()?;
+    fourccs
+        .iter()
+        .find(|v| is_opaque(**v) == (alpha == AlphaMode::Opaque))
+        .or(fourccs.first())
+        .copied()
+}
+
+pub trait VulkanoFormatExtension: Sized {
+    fn from_drm_fourcc(drm_format: DrmFourcc) -> Option<Self>;
+    fn to_drm_fourcc(&self) -> Option<&'static [DrmFourcc]>;
+    fn to_srgb(&self) -> Option<Self>;
+    /// whether the format's color channels use the srgb transfer function
+    fn is_srgb(&self) -> bool;
+}
+impl VulkanoFormatExtension for Format {
+    fn from_drm_fourcc(drm_format: DrmFourcc) -> Option<Self> {
+        FOURCC_FORMATS
+            .iter()
+            .find(|(_, fourccs)| fourccs.contains(&drm_format))
+            .map(|(format, _)| *format)
+    }
+    fn to_drm_fourcc(&self) -> Option<&'static [DrmFourcc]> {
+        FOURCC_FORMATS
+            .iter()
+            .find(|(format, _)| format == self || format.to_srgb().as_ref() == Some(self))
+            .map(|(_, fourccs)| *fourccs)
+    }
+    fn to_srgb(&self) -> Option<Self> {
+        use Format as F;
+        Some(match self {
+            F::R8_UNORM => F::R8_SRGB,
+            F::R8G8_UNORM => F::R8G8_SRGB,
+            F::R8G8B8_UNORM => F::R8G8B8_SRGB,
+            F::B8G8R8_UNORM => F::B8G8R8_SRGB,
+            F::R8G8B8A8_UNORM => F::R8G8B8A8_SRGB,
+            F::B8G8R8A8_UNORM => F::B8G8R8A8_SRGB,
+            F::A8B8G8R8_UNORM_PACK32 => F::A8B8G8R8_SRGB_PACK32,
+            F::BC1_RGB_UNORM_BLOCK => F::BC1_RGB_SRGB_BLOCK,
+            F::BC1_RGBA_UNORM_BLOCK => F::BC1_RGBA_SRGB_BLOCK,
+            F::BC2_UNORM_BLOCK => F::BC2_SRGB_BLOCK,
+            F::BC3_UNORM_BLOCK => F::BC3_SRGB_BLOCK,
+            F::BC7_UNORM_BLOCK => F::BC7_SRGB_BLOCK,
+            F::ETC2_R8G8B8_UNORM_BLOCK => F::ETC2_R8G8B8_SRGB_BLOCK,
+            F::ETC2_R8G8B8A1_UNORM_BLOCK => F::ETC2_R8G8B8A1_SRGB_BLOCK,
+            F::ETC2_R8G8B8A8_UNORM_BLOCK => F::ETC2_R8G8B8A8_SRGB_BLOCK,
+            F::ASTC_4x4_UNORM_BLOCK => F::ASTC_4x4_SRGB_BLOCK,
+            F::ASTC_8x8_UNORM_BLOCK => F::ASTC_8x8_SRGB_BLOCK,
+            _ => return None,
+        })
+    }
+    fn is_srgb(&self) -> bool {
+        self.numeric_format_color() == Some(NumericFormat::SRGB)
+    }
+}
+
+/// whether `fourcc` has an ignored x channel in place of alpha
+pub fn is_opaque(fourcc: DrmFourcc) -> bool {
+    use DrmFourcc as D;
+    matches!(
+        fourcc,
+        D::Xbgr1555
+            | D::Xbgr2101010
+            | D::Xbgr4444
+            | D::Xbgr8888
+            | D::Xbgr16161616
+            | D::Xbgr16161616f
+            | D::Xrgb1555
+            | D::Xrgb2101010
+            | D::Xrgb4444
+            | D::Xrgb8888
+            | D::Bgrx4444
+            | D::Bgrx5551
+            | D::Bgrx8888
+            | D::Rgbx4444
+            | D::Rgbx5551
+            | D::Rgbx8888
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DrmFourcc as D;
+    use Format as F;
+
+    #[test]
+    fn fourcc_round_trip() {
+        for (format, fourccs) in FOURCC_FORMATS {
+            assert_eq!(format.to_drm_fourcc(), Some(*fourccs), "{format:?}");
+            for fourcc in *fourccs {
+                assert_eq!(
+                    Format::from_drm_fourcc(*fourcc),
+                    Some(*format),
+                    "{fourcc:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn memory_order() {
+        for (fourcc, format) in [
+            (D::Abgr8888, F::R8G8B8A8_UNORM),
+            (D::Argb8888, F::B8G8R8A8_UNORM),
+            (D::Bgr888, F::R8G8B8_UNORM),
+            (D::Rgb888, F::B8G8R8_UNORM),
+            (D::Gr88, F::R8G8_UNORM),
+            (D::Gr1616, F::R16G16_UNORM),
+            (D::Rgba5551, F::R5G5B5A1_UNORM_PACK16),
+            (D::Argb4444, F::A4R4G4B4_UNORM_PACK16),
+            (D::Bgra4444, F::B4G4R4A4_UNORM_PACK16),
+        ] {
+            assert_eq!(Format::from_drm_fourcc(fourcc), Some(format), "{fourcc:?}");
+        }
+        for fourcc in [
+            D::Rgba8888,
+            D::Rgbx8888,
+            D::Bgra8888,
+            D::Bgrx8888,
+            D::Rg88,
+            D::Rg1616,
+            D::Abgr1555,
+        ] {
+            assert_eq!(Format::from_drm_fourcc(fourcc), None, "{fourcc:?}");
+        }
+    }
+
+    #[test]
+    fn alpha_mode() {
+        for (format, fourccs) in FOURCC_FORMATS {
+            let alpha = to_drm_fourcc(*format, AlphaMode::Alpha).unwrap();
+            let opaque = to_drm_fourcc(*format, AlphaMode::Opaque).unwrap();
+            assert!(fourccs.contains(&alpha) && fourccs.contains(&opaque));
+            if fourccs.len() > 1 {
+                assert!(!is_opaque(alpha) && is_opaque(opaque), "{format:?}");
+            }
+        }
+        assert_eq!(
+            to_drm_fourcc(F::R8G8B8A8_SRGB, AlphaMode::Opaque),
+            Some(D::Xbgr8888)
+        );
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        for (fourcc, format) in COMPRESSED_FORMATS {
+            assert_eq!(compressed_format_from_fourcc(*fourcc), Some(*format));
+            assert_eq!(compressed_format_to_fourcc(*format), Some(*fourcc));
+            if let Some(srgb) = format.to_srgb() {
+                assert_eq!(compressed_format_to_fourcc(srgb), Some(*fourcc));
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Appsink adapter that imports DMABuf backed GStreamer buffers as dmatexes
+
+use std::{
+    collections::VecDeque,
+    os::fd::{BorrowedFd, RawFd},
+    sync::Arc,
+};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use gstreamer::{self as gst, ClockTime};
+use gstreamer_allocators::DmaBufMemory;
+use gstreamer_video::{self as gst_video, VideoFormat, VideoMeta};
+use rustix::io::Errno;
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use vulkano::{device::Device, format::Format, image::ImageUsage};
+
+use crate::{
+    backend::Backend,
+    dmatex::{BridgedDmatex, Dmatex, DmatexImportError, ExternalDmabuf, ExternalPlane},
+    fourcc::VulkanoFormatExtension,
+    render_device::RenderDevice,
+};
+
+/// how many distinct buffers stay imported, decoder pools are usually smaller than this
+const IMPORT_CACHE_SIZE: usize = 8;
+
+/// gstreamer formats are named by byte order while drm formats are little endian words.
+/// Formats with alpha first in memory like `Argb` have no vulkan format, so they're None as well
+pub fn video_format_to_fourcc(format: VideoFormat) -> Option<DrmFourcc> {
+    Some(match format {
+        VideoFormat::Bgra => DrmFourcc::Argb8888,
+        VideoFormat::Bgrx => DrmFourcc::Xrgb8888,
+        VideoFormat::Rgba => DrmFourcc::Abgr8888,
+        VideoFormat::Rgbx => DrmFourcc::Xbgr8888,
+        _ => return None,
+    })
+}
+
+/// Feed it samples from an appsink and ask it which frame to show at the current running time
+pub struct GstDmatexSink<B: Backend> {
+    client: Arc<B>,
+    dev: Arc<Device>,
+    usage: ImageUsage,
+    format: Option<(DrmFourcc, u64)>,
+    /// least recently used at the front
+    imports: VecDeque<Arc<CachedImport>>,
+    pending: VecDeque<PendingFrame>,
+    in_flight: Vec<InFlightFrame>,
+}
+/// shared with the frames using it, so it outlives its cache entry until they're done
+struct CachedImport {
+    fd: RawFd,
+    bridged: BridgedDmatex,
+}
+struct PendingFrame {
+    import: Arc<CachedImport>,
+    pts: Option<ClockTime>,
+    buffer: gst::Buffer,
+}
+/// keeps the buffer out of the pool and its import alive until the server is done with it
+struct InFlightFrame {
+    import: Arc<CachedImport>,
+    release_point: u64,
+    _buffer: gst::Buffer,
+}
+
+impl<B: Backend> GstDmatexSink<B> {
+    pub fn new(client: &Arc<B>, dev: &Arc<Device>, usage: ImageUsage) -> Self {
+        Self {
+            client: client.clone(),
+            dev: dev.clone(),
+            usage,
+            format: None,
+            imports: VecDeque::new(),
+            pending: VecDeque::new(),
+            in_flight: Vec::new(),
+        }
+    }
+    /// Call this whenever the negotiated caps change, drops all cached imports and queued
+    /// frames. Frames the server still shows keep their imports until they're released
+    pub fn set_caps(&mut self, caps: &gst::CapsRef) -> Result<(), GstImportError> {
+        let format = if let Ok(info) = gst_video::VideoInfoDmaDrm::from_caps(caps) {
+            let fourcc = DrmFourcc::try_from(info.fourcc())
+                .map_err(|_| GstImportError::UnsupportedFormat)?;
+            (fourcc, info.modifier())
+        } else {
+            let info =
+                gst_video::VideoInfo::from_caps(caps).map_err(|_| GstImportError::InvalidCaps)?;
+            let fourcc =
+                video_format_to_fourcc(info.format()).ok_or(GstImportError::UnsupportedFormat)?;
+            (fourcc, DrmModifier::Linear.into())
+        };
+        if Format::from_drm_fourcc(format.0).is_none() {
+            return Err(GstImportError::UnsupportedFormat);
+        }
+        self.format = Some(format);
+        self.imports.clear();
+        self.pending.clear();
+        Ok(())
+    }
+    /// imports the buffer of `sample` (if it isn't already) and queues it by its PTS
+    pub fn push_sample(
+        &mut self,
+        render_dev: &RenderDevice,
+        sample: &gst::Sample,
+    ) -> Result<(), GstImportError> {
+        if let Some(caps) = sample.caps()
+            && self.format.is_none()
+        {
+            self.set_caps(caps)?;
+        }
+        let (fourcc, modifier) = self.format.ok_or(GstImportError::InvalidCaps)?;
+        let buffer = sample.buffer_owned().ok_or(GstImportError::NoBuffer)?;
+        let meta = buffer
+            .meta::<VideoMeta>()
+            .ok_or(GstImportError::NoVideoMeta)?;
+        let planes = (0..meta.n_planes() as usize)
+            .map(|i| {
+                let offset = meta.offset()[i];
+                let (memories, skip) = buffer
+                    .find_memory(offset..offset + 1)
+                    .ok_or(GstImportError::NotDmabuf)?;
+                let memory = buffer.peek_memory(memories.start);
+                let dmabuf = memory
+                    .downcast_memory_ref::<DmaBufMemory>()
+                    .ok_or(GstImportError::NotDmabuf)?;
+                Ok((
+                    dmabuf.fd(),
+                    (memory.offset() + skip) as u64,
+                    meta.stride()[i] as u64,
+                ))
+            })
+            .collect::<Result<Vec<_>, GstImportError>>()?;
+        let fd = planes[0].0;
+
+        let import = if let Some(i) = self.imports.iter().position(|v| v.fd == fd) {
+            self.imports.remove(i).unwrap()
+        } else {
+            let planes = planes
+                .iter()
+                .map(|(fd, offset, row_pitch)| {
+                    Ok(ExternalPlane {
+                        fd: unsafe { BorrowedFd::borrow_raw(*fd) }
+                            .try_clone_to_owned()
+                            .map_err(DmatexImportError::Dup)?,
+                        offset: *offset,
+                        row_pitch: *row_pitch,
+                    })
+                })
+                .collect::<Result<Vec<_>, GstImportError>>()?;
+            let dmatex = Dmatex::try_import(
+                &self.client,
+                &self.dev,
+                render_dev,
+                ExternalDmabuf {
+                    size: DmatexSize::Dim2D([meta.width(), meta.height()].into()),
+                    fourcc,
+                    modifier,
+                    srgb: false,
+                    planes,
+                },
+                self.usage,
+            )?;
+            if self.imports.len() >= IMPORT_CACHE_SIZE {
+                // prefer imports no frame uses, busy ones live on in their frames
+                let i = self
+                    .imports
+                    .iter()
+                    .position(|import| Arc::strong_count(import) == 1)
+                    .unwrap_or(0);
+                self.imports.remove(i);
+            }
+            Arc::new(CachedImport {
+                fd,
+                bridged: BridgedDmatex::new(dmatex),
+            })
+        };
+        self.imports.push_back(import.clone());
+
+        let pts = buffer.pts();
+        self.pending.push_back(PendingFrame {
+            import,
+            pts,
+            buffer,
+        });
+        Ok(())
+    }
+    /// Returns the newest frame due at `running_time`, frames that were overtaken are skipped
+    /// without ever reaching the server
+    pub fn frame_for(
+        &mut self,
+        running_time: ClockTime,
+    ) -> Result<Option<DmatexSubmitInfo>, GstImportError> {
+        self.collect_released();
+        let mut due = None;
+        while let Some(frame) = self.pending.front() {
+            if frame.pts.is_some_and(|pts| pts > running_time) {
+                break;
+            }
+            due = self.pending.pop_front();
+        }
+        let Some(frame) = due else {
+            return Ok(None);
+        };
+        let import = frame.import;
+        // the buffer was handed to the appsink, so decoding has finished
+        let submit = import
+            .bridged
+            .signal_frame()
+            .map_err(GstImportError::Syncobj)?;
+        self.in_flight.push(InFlightFrame {
+            import,
+            release_point: submit.release_point,
+            _buffer: frame.buffer,
+        });
+        Ok(Some(submit))
+    }
+    /// returns buffers the server released to their pool
+    fn collect_released(&mut self) {
+        self.in_flight.retain(|frame| {
+            !frame
+                .import
+                .bridged
+                .dmatex
+                .is_signalled(frame.release_point)
+        });
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum GstImportError {
+    #[error("caps are not raw or DMA_DRM video")]
+    InvalidCaps,
+    #[error("video format has no drm fourcc or vulkan equivalent")]
+    UnsupportedFormat,
+    #[error("sample has no buffer")]
+    NoBuffer,
+    #[error("buffer has no VideoMeta")]
+    NoVideoMeta,
+    #[error("buffer memory is not a dmabuf")]
+    NotDmabuf,
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(Errno),
+    #[error(transparent)]
+    Import(#[from] DmatexImportError),
+}
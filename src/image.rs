@@ -0,0 +1,83 @@
+//! Dmatexes from decoded images using the `image` crate, e.g. for picture frames or wallpapers
+
+use std::sync::Arc;
+
+use ::image::DynamicImage;
+use stardust_xr_fusion::drawable::DmatexSize;
+use thiserror::Error;
+use vulkano::{
+    device::{Device, Queue},
+    format::Format,
+    image::ImageUsage,
+};
+
+use crate::{
+    backend::Backend,
+    dmatex::Dmatex,
+    format::FormatTable,
+    render_device::RenderDevice,
+    uploader::{UploadError, UploadPoints, Uploader},
+};
+
+impl Dmatex {
+    /// Creates a sampled srgb dmatex with the pixels of `image`, uploaded through `queue`.
+    /// Blocks until the upload finished, timeline point 0 is signalled once it's done
+    pub fn from_image<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        queue: &Arc<Queue>,
+        formats: &FormatTable,
+        image: &DynamicImage,
+    ) -> Result<Self, FromImageError> {
+        let (width, height) = (image.width(), image.height());
+        if width == 0 || height == 0 {
+            return Err(FromImageError::Empty);
+        }
+        let (format, bgra) = if let Some(format) = formats.get(Format::R8G8B8A8_SRGB) {
+            (format, false)
+        } else if let Some(format) = formats.get(Format::B8G8R8A8_SRGB) {
+            (format, true)
+        } else {
+            return Err(FromImageError::NoSrgbFormat);
+        };
+        let mut pixels = image.to_rgba8().into_raw();
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        let dmatex = Dmatex::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim2D([width, height].into()),
+            format,
+            ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+        )
+        // the upload overwrites everything anyway
+        .initial_clear(false)
+        .build();
+        let mut uploader = Uploader::with_staging_size(queue, pixels.len() as u64);
+        uploader.upload_image(
+            &dmatex,
+            &pixels,
+            UploadPoints {
+                wait: None,
+                signal: 0,
+            },
+        )?;
+        uploader.wait_idle()?;
+        Ok(dmatex)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FromImageError {
+    #[error("the image has no pixels")]
+    Empty,
+    #[error("the server supports neither R8G8B8A8_SRGB nor B8G8R8A8_SRGB")]
+    NoSrgbFormat,
+    #[error("failed to upload the pixels: {0}")]
+    Upload(#[from] UploadError),
+}
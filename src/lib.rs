@@ -1,12 +1,92 @@
+#[cfg(feature = "vulkan")]
 use std::sync::Arc;
 
+#[cfg(feature = "vulkan")]
 use vulkano::device::physical::PhysicalDevice;
 
+pub mod atlas;
+pub mod render_gate;
+#[cfg(feature = "formats")]
+pub mod fourcc;
+#[cfg(feature = "descriptors")]
+pub mod dmatex_handle;
+#[cfg(feature = "vulkan")]
+pub mod backend;
+#[cfg(feature = "vulkan")]
+pub mod caps;
+#[cfg(feature = "vulkan")]
+pub mod debug;
+#[cfg(feature = "vulkan")]
+pub mod diagnostics;
+#[cfg(feature = "vulkan")]
 pub mod dmatex;
-pub mod swapchain;
+#[cfg(feature = "vulkan")]
+pub mod dma_buffer;
+#[cfg(feature = "vulkan")]
+pub mod dmatex_pool;
+#[cfg(feature = "vulkan")]
+pub mod uploader;
+#[cfg(feature = "vulkan")]
 pub mod format;
+#[cfg(feature = "vulkan")]
+pub mod profiler;
+#[cfg(feature = "vulkan")]
+pub mod render_context;
+#[cfg(feature = "vulkan")]
 pub mod render_device;
+#[cfg(feature = "vulkan")]
+pub mod device_watcher;
+#[cfg(feature = "vulkan")]
+pub mod sync;
+#[cfg(feature = "vulkan")]
+pub mod syncobj_eventfd;
+#[cfg(feature = "swapchain")]
+pub mod swapchain;
+#[cfg(feature = "swapchain")]
+pub mod surface;
+#[cfg(feature = "swapchain")]
+pub mod surface_manager;
+#[cfg(feature = "swapchain")]
+pub mod resizable_swapchain;
+#[cfg(feature = "swapchain")]
+pub mod streaming_texture;
+#[cfg(feature = "swapchain")]
+pub mod pixel_surface;
+#[cfg(feature = "swapchain")]
+pub mod post_process;
+#[cfg(feature = "color")]
+pub mod color;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "testing")]
+pub mod mock;
+#[cfg(feature = "pipewire")]
+pub mod pipewire;
+#[cfg(feature = "gst")]
+pub mod gst;
+#[cfg(feature = "vaapi")]
+pub mod vaapi;
+#[cfg(feature = "canvas")]
+pub mod canvas;
+#[cfg(feature = "egui")]
+pub mod egui;
+#[cfg(feature = "skia")]
+pub mod skia;
+#[cfg(feature = "femtovg")]
+pub mod femtovg;
+#[cfg(feature = "udmabuf")]
+pub mod udmabuf;
+#[cfg(feature = "wayland")]
+pub mod wayland;
+#[cfg(feature = "v4l2")]
+pub mod v4l2;
+#[cfg(feature = "image")]
+pub mod image;
+
+#[cfg(feature = "vulkan")]
+pub use diagnostics::diagnose;
 
+#[cfg(feature = "vulkan")]
 pub fn get_phys_dev_node_id(phys_dev: &Arc<PhysicalDevice>) -> u64 {
     let props = phys_dev.properties();
     // Create dev_t from the primary node major/minor numbers
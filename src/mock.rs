@@ -0,0 +1,296 @@
+//! In-process stand-in for a Stardust server, for running renderers in CI without a compositor
+
+use std::{
+    collections::HashMap,
+    pin::pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Sender, channel},
+    },
+    task::{Context, Poll, Waker},
+};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use stardust_xr_fusion::{
+    drawable::{DmatexSize, DmatexSubmitInfo},
+    node::{NodeError, NodeResult},
+};
+use thiserror::Error;
+use timeline_syncobj::{render_node::DrmRenderNode, timeline_syncobj::TimelineSyncObj};
+use vulkano::{
+    Validated, VulkanError, VulkanLibrary,
+    device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags},
+    format::Format,
+    instance::{Instance, InstanceCreateInfo},
+};
+
+use crate::{
+    backend::{
+        Backend, BackendFuture, DmatexImport, ImportError, ServerFormat, SurfaceEvent,
+        SurfaceEvents,
+    },
+    dmatex::Dmatex,
+    format::{ColorSpace, DmatexFormat, EnumerateError},
+    get_phys_dev_node_id,
+    render_device::{RenderDevice, RenderDeviceCreationError},
+};
+
+/// Accepts every import and lets tests play the compositor side of the timeline
+pub struct MockServer {
+    render_node_id: u64,
+    drm_node: DrmRenderNode,
+    formats: Vec<ServerFormat>,
+    next_id: AtomicU64,
+    dmatexes: Mutex<HashMap<u64, MockDmatex>>,
+    surface_events: Mutex<Vec<Sender<SurfaceEvent>>>,
+}
+
+struct MockDmatex {
+    info: MockDmatexInfo,
+    /// shared so presenting can wait on it without holding the lock
+    timeline: Arc<TimelineSyncObj>,
+}
+
+/// What the client sent in `import_dmatex`
+#[derive(Debug, Clone)]
+pub struct MockDmatexInfo {
+    pub size: DmatexSize,
+    pub fourcc: u32,
+    pub drm_modifier: u64,
+    pub srgb: bool,
+    pub array_layers: Option<u32>,
+    pub plane_count: usize,
+    pub color_space: Option<ColorSpace>,
+}
+
+impl MockServer {
+    /// advertises linear 8bpc rgba/bgra formats on the given render node
+    pub fn new(render_node_id: u64) -> Result<Self, rustix::io::Errno> {
+        let formats = [
+            DrmFourcc::Abgr8888,
+            DrmFourcc::Xbgr8888,
+            DrmFourcc::Argb8888,
+            DrmFourcc::Xrgb8888,
+        ]
+        .into_iter()
+        .flat_map(|fourcc| {
+            [false, true].map(|is_srgb| ServerFormat {
+                fourcc: fourcc as u32,
+                is_srgb,
+                drm_modifier: DrmModifier::Linear.into(),
+                planes: 1,
+            })
+        })
+        .collect();
+        Ok(Self {
+            render_node_id,
+            drm_node: DrmRenderNode::new(render_node_id)?,
+            formats,
+            next_id: AtomicU64::new(1),
+            dmatexes: Mutex::default(),
+            surface_events: Mutex::default(),
+        })
+    }
+    /// replaces the advertised formats
+    pub fn with_formats(mut self, formats: Vec<ServerFormat>) -> Self {
+        self.formats = formats;
+        self
+    }
+    pub fn dmatex_ids(&self) -> Vec<u64> {
+        self.dmatexes.lock().unwrap().keys().copied().collect()
+    }
+    pub fn dmatex_info(&self, dmatex_id: u64) -> Option<MockDmatexInfo> {
+        self.dmatexes
+            .lock()
+            .unwrap()
+            .get(&dmatex_id)
+            .map(|v| v.info.clone())
+    }
+    /// hands `event` to every [surface event](Backend::surface_events) subscription
+    pub fn send_surface_event(&self, event: SurfaceEvent) {
+        self.surface_events
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+    /// Does what the compositor would do with a submitted frame: waits for the acquire point
+    /// and then signals the release point
+    pub fn present(&self, submit: &DmatexSubmitInfo) -> Result<(), MockPresentError> {
+        let timeline = self
+            .dmatexes
+            .lock()
+            .unwrap()
+            .get(&submit.dmatex_id)
+            .map(|v| v.timeline.clone())
+            .ok_or(MockPresentError::UnknownDmatex(submit.dmatex_id))?;
+        // other threads keep importing and presenting while this one waits
+        timeline
+            .blocking_wait(submit.acquire_point, None)
+            .map_err(MockPresentError::Syncobj)?;
+        unsafe { timeline.signal(submit.release_point) }.map_err(MockPresentError::Syncobj)
+    }
+}
+
+impl Backend for MockServer {
+    fn generate_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+    fn primary_render_device_id(self: Arc<Self>) -> BackendFuture<u64> {
+        Box::pin(async move { Ok(self.render_node_id) })
+    }
+    fn enumerate_dmatex_formats(
+        self: Arc<Self>,
+        _render_node_id: u64,
+    ) -> BackendFuture<Vec<ServerFormat>> {
+        Box::pin(async move { Ok(self.formats.clone()) })
+    }
+    fn import_dmatex(self: Arc<Self>, import: DmatexImport) -> Result<(), ImportError> {
+        let mut dmatexes = self.dmatexes.lock().unwrap();
+        if dmatexes.contains_key(&import.dmatex_id) {
+            return Err(ImportError::IdCollision(import.dmatex_id));
+        }
+        // a real server answers a bad timeline with an error too
+        let timeline = TimelineSyncObj::import(&self.drm_node, import.timeline).map_err(|err| {
+            NodeError::ReturnedError {
+                e: format!("failed to import timeline: {err}"),
+            }
+        })?;
+        let info = MockDmatexInfo {
+            size: import.size,
+            fourcc: import.fourcc,
+            drm_modifier: import.drm_modifier,
+            srgb: import.srgb,
+            array_layers: import.array_layers,
+            plane_count: import.planes.len(),
+            color_space: import.color_space,
+        };
+        dmatexes.insert(
+            import.dmatex_id,
+            MockDmatex {
+                info,
+                timeline: Arc::new(timeline),
+            },
+        );
+        Ok(())
+    }
+    fn confirm_dmatex(self: Arc<Self>, dmatex_id: u64) -> BackendFuture<(), ImportError> {
+        // imports are handled right away, a dmatex that exists was accepted
+        let accepted = self.dmatexes.lock().unwrap().contains_key(&dmatex_id);
+        Box::pin(async move {
+            accepted
+                .then_some(())
+                .ok_or(ImportError::Rejected(dmatex_id))
+        })
+    }
+    fn unregister_dmatex(self: Arc<Self>, dmatex_id: u64) -> NodeResult<()> {
+        self.dmatexes.lock().unwrap().remove(&dmatex_id);
+        Ok(())
+    }
+    fn surface_events(self: Arc<Self>) -> SurfaceEvents {
+        let (sender, events) = channel();
+        self.surface_events.lock().unwrap().push(sender);
+        events
+    }
+}
+
+/// A device with a render queue and a [`MockServer`] on the first gpu that can share dmatexes,
+/// the boilerplate examples, benches and CI renderers start with
+pub struct MockSetup {
+    pub server: Arc<MockServer>,
+    pub dev: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub render_dev: RenderDevice,
+    pub formats: HashMap<Format, DmatexFormat>,
+}
+
+impl MockSetup {
+    pub fn new() -> Result<Self, MockSetupError> {
+        let library =
+            VulkanLibrary::new().map_err(|err| MockSetupError::Vulkan(err.to_string()))?;
+        let instance = Instance::new(
+            library,
+            InstanceCreateInfo {
+                enabled_extensions: Dmatex::required_instance_exts(),
+                ..Default::default()
+            },
+        )
+        .map_err(vulkan_error)?;
+        let (phys_dev, queue_family_index) = instance
+            .enumerate_physical_devices()
+            .map_err(|err| MockSetupError::Vulkan(err.to_string()))?
+            .filter(|p| {
+                p.supported_extensions()
+                    .contains(&Dmatex::required_device_exts())
+                    && p.properties().render_major.is_some()
+            })
+            .find_map(|p| {
+                let index = p
+                    .queue_family_properties()
+                    .iter()
+                    .position(|q| q.queue_flags.intersects(QueueFlags::GRAPHICS))?;
+                Some((p, index as u32))
+            })
+            .ok_or(MockSetupError::NoDevice)?;
+        let server = Arc::new(
+            MockServer::new(get_phys_dev_node_id(&phys_dev)).map_err(MockSetupError::Syncobj)?,
+        );
+        let enabled_extensions = Dmatex::device_exts(&phys_dev);
+        let (dev, mut queues) = Device::new(
+            phys_dev,
+            DeviceCreateInfo {
+                enabled_extensions,
+                enabled_features: Dmatex::required_device_features(),
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .map_err(vulkan_error)?;
+        let render_dev = ready(RenderDevice::primary_server_device(&server))?;
+        let formats = ready(DmatexFormat::enumerate(&server, &render_dev))?;
+        Ok(Self {
+            server,
+            dev,
+            queue: queues.next().unwrap(),
+            render_dev,
+            formats,
+        })
+    }
+}
+
+fn vulkan_error(err: Validated<VulkanError>) -> MockSetupError {
+    MockSetupError::Vulkan(err.to_string())
+}
+
+/// the mock server answers right away, so its futures are ready on the first poll
+fn ready<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(v) => v,
+        Poll::Pending => unreachable!("mock server futures never wait"),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MockSetupError {
+    #[error("vulkan error: {0}")]
+    Vulkan(String),
+    #[error("no gpu that can share dmatexes")]
+    NoDevice,
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(rustix::io::Errno),
+    #[error(transparent)]
+    RenderDevice(#[from] RenderDeviceCreationError),
+    #[error(transparent)]
+    Enumerate(#[from] EnumerateError),
+}
+
+#[derive(Debug, Error)]
+pub enum MockPresentError {
+    #[error("no dmatex with id {0} was imported")]
+    UnknownDmatex(u64),
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(rustix::io::Errno),
+}
@@ -0,0 +1,177 @@
+//! Zero-copy bridge from PipeWire dmabuf video buffers (e.g. screencasts) to dmatexes
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    os::fd::{BorrowedFd, RawFd},
+    sync::Arc,
+};
+
+use ::pipewire::{
+    buffer::Buffer,
+    spa::{
+        buffer::DataType,
+        param::video::{VideoFormat, VideoInfoRaw},
+    },
+};
+use drm_fourcc::DrmFourcc;
+use rustix::io::Errno;
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use vulkano::{device::Device, image::ImageUsage};
+
+use crate::{
+    backend::Backend,
+    dmatex::{BridgedDmatex, Dmatex, DmatexImportError, ExternalDmabuf, ExternalPlane},
+    render_device::RenderDevice,
+};
+
+/// spa formats are named by byte order while drm formats are little endian words. Formats
+/// with alpha first in memory like `ARGB` have no vulkan format, so they're None as well
+pub fn video_format_to_fourcc(format: VideoFormat) -> Option<DrmFourcc> {
+    Some(match format {
+        VideoFormat::BGRA => DrmFourcc::Argb8888,
+        VideoFormat::BGRx => DrmFourcc::Xrgb8888,
+        VideoFormat::RGBA => DrmFourcc::Abgr8888,
+        VideoFormat::RGBx => DrmFourcc::Xbgr8888,
+        VideoFormat::RGB => DrmFourcc::Bgr888,
+        VideoFormat::BGR => DrmFourcc::Rgb888,
+        _ => return None,
+    })
+}
+
+/// The dmabuf part of a PipeWire buffer, the fds are still owned by PipeWire
+#[derive(Debug, Clone)]
+pub struct SpaDmabufFrame {
+    pub width: u32,
+    pub height: u32,
+    pub fourcc: DrmFourcc,
+    pub modifier: u64,
+    pub planes: Vec<SpaDmabufPlane>,
+}
+#[derive(Debug, Clone, Copy)]
+pub struct SpaDmabufPlane {
+    pub fd: RawFd,
+    pub offset: u32,
+    pub stride: u32,
+}
+impl SpaDmabufFrame {
+    /// None if the buffer isn't a dmabuf or the format has no drm equivalent
+    pub fn from_buffer(info: &VideoInfoRaw, buffer: &mut Buffer) -> Option<Self> {
+        let fourcc = video_format_to_fourcc(info.format())?;
+        let planes = buffer
+            .datas_mut()
+            .iter()
+            .map(|data| {
+                if data.type_() != DataType::DmaBuf {
+                    return None;
+                }
+                Some(SpaDmabufPlane {
+                    fd: data.as_raw().fd as RawFd,
+                    offset: data.chunk().offset(),
+                    stride: data.chunk().stride() as u32,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if planes.is_empty() {
+            return None;
+        }
+        Some(Self {
+            width: info.size().width,
+            height: info.size().height,
+            fourcc,
+            modifier: info.modifier(),
+            planes,
+        })
+    }
+}
+
+/// Imports each PipeWire buffer once and turns every new frame into a timeline point
+pub struct PipewireBridge<B: Backend> {
+    client: Arc<B>,
+    dev: Arc<Device>,
+    usage: ImageUsage,
+    /// PipeWire keeps reusing the same buffers, so the first fd identifies one
+    buffers: HashMap<RawFd, BridgedDmatex>,
+}
+
+impl<B: Backend> PipewireBridge<B> {
+    pub fn new(client: &Arc<B>, dev: &Arc<Device>, usage: ImageUsage) -> Self {
+        Self {
+            client: client.clone(),
+            dev: dev.clone(),
+            usage,
+            buffers: HashMap::new(),
+        }
+    }
+    /// Marks the frame as ready for the server, the PipeWire buffer must not be requeued
+    /// before [`Self::wait_release`] returns for the returned submit info
+    pub fn import_frame(
+        &mut self,
+        render_dev: &RenderDevice,
+        frame: &SpaDmabufFrame,
+    ) -> Result<DmatexSubmitInfo, PipewireImportError> {
+        let first = frame.planes.first().ok_or(PipewireImportError::NoPlanes)?;
+        let buffer = match self.buffers.entry(first.fd) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let planes = frame
+                    .planes
+                    .iter()
+                    .map(|p| {
+                        Ok(ExternalPlane {
+                            fd: unsafe { BorrowedFd::borrow_raw(p.fd) }
+                                .try_clone_to_owned()
+                                .map_err(DmatexImportError::Dup)?,
+                            offset: p.offset as u64,
+                            row_pitch: p.stride as u64,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, DmatexImportError>>()?;
+                let dmatex = Dmatex::try_import(
+                    &self.client,
+                    &self.dev,
+                    render_dev,
+                    ExternalDmabuf {
+                        size: DmatexSize::Dim2D([frame.width, frame.height].into()),
+                        fourcc: frame.fourcc,
+                        modifier: frame.modifier,
+                        srgb: false,
+                        planes,
+                    },
+                    self.usage,
+                )?;
+                entry.insert(BridgedDmatex::new(dmatex))
+            }
+        };
+        // PipeWire only hands us buffers the producer is done with
+        buffer.signal_frame().map_err(PipewireImportError::Syncobj)
+    }
+    /// blocks until the server stopped reading from the frame
+    pub fn wait_release(&self, submit: &DmatexSubmitInfo) -> Result<(), PipewireImportError> {
+        let Some(buffer) = self
+            .buffers
+            .values()
+            .find(|v| v.dmatex.dmatex_id == submit.dmatex_id)
+        else {
+            return Ok(());
+        };
+        buffer
+            .dmatex
+            .wait(submit.release_point, None)
+            .map_err(PipewireImportError::Syncobj)
+    }
+    /// drops all imported buffers, call this when the stream renegotiates its buffers
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PipewireImportError {
+    #[error("the frame has no planes")]
+    NoPlanes,
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(Errno),
+    #[error(transparent)]
+    Import(#[from] DmatexImportError),
+}
@@ -0,0 +1,253 @@
+//! Row-major pixel access for software renderers, written once against [`PixelSurface`] and
+//! used with whichever backing the device supports: the staging uploads of a
+//! [`StreamingTexture`] or, with the `udmabuf` feature, a [`MappedTexture`] the cpu writes
+//! into directly.
+//!
+//! [`StreamingTexture`]: crate::streaming_texture::StreamingTexture
+
+use std::{ops::Range, sync::Arc};
+
+use stardust_xr_fusion::drawable::DmatexSubmitInfo;
+use vulkano::device::Queue;
+
+use crate::swapchain::SwapchainError;
+
+/// Tightly packed rows of pixels in the surface's format, top to bottom
+pub trait PixelSurface {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    fn bytes_per_pixel(&self) -> usize;
+    /// Every row, `width * bytes_per_pixel` long. Changes have to be reported to
+    /// [`Self::flush`]
+    fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]>;
+    /// writes one pixel of `bytes_per_pixel` bytes, out of bounds pixels are ignored
+    fn put_pixel(&mut self, x: u32, y: u32, pixel: &[u8]) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        if x >= self.width() {
+            return;
+        }
+        if let Some(row) = self.rows_mut().nth(y as usize) {
+            let start = x as usize * bytes_per_pixel;
+            row[start..start + bytes_per_pixel].copy_from_slice(pixel);
+        }
+    }
+    /// Hands the rows in `damage` to the server, `queue` is only used by surfaces that
+    /// upload. Returns None if `damage` is empty and nothing changed since the last flush
+    fn flush(
+        &mut self,
+        queue: &Arc<Queue>,
+        damage: Range<u32>,
+    ) -> Result<Option<DmatexSubmitInfo>, SwapchainError>;
+}
+
+#[cfg(feature = "udmabuf")]
+pub use mapped::MappedTexture;
+
+#[cfg(feature = "udmabuf")]
+mod mapped {
+    use std::{ffi::c_void, ops::Range, ptr, sync::Arc};
+
+    use rustix::mm::{MapFlags, ProtFlags, mmap, munmap};
+    use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+    use vulkano::{
+        device::{Device, Queue},
+        image::ImageUsage,
+    };
+
+    use super::PixelSurface;
+    use crate::{
+        backend::Backend,
+        dmatex::{Dmatex, Srgbness},
+        format::DmatexFormat,
+        render_device::RenderDevice,
+        swapchain::SwapchainError,
+        udmabuf::UdmabufAllocator,
+    };
+
+    /// A shared mapping of a udmabuf's memfd
+    struct Mapping {
+        ptr: *mut c_void,
+        len: usize,
+    }
+    // the mapping is only accessed through `&mut MappedTexture`
+    unsafe impl Send for Mapping {}
+    impl Mapping {
+        fn bytes(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) }
+        }
+        fn bytes_mut(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.cast(), self.len) }
+        }
+    }
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = munmap(self.ptr, self.len);
+            }
+        }
+    }
+
+    struct MappedImage {
+        dmatex: Arc<Dmatex>,
+        mapping: Mapping,
+        /// the point after which the server doesn't read the image anymore
+        release: u64,
+    }
+
+    /// Three linear udmabuf dmatexes the cpu writes into directly, no staging copy or gpu
+    /// work involved. Rows are written into the image that's flushed next, the others catch
+    /// up on the rows they missed once it's their turn
+    pub struct MappedTexture {
+        images: [MappedImage; 3],
+        width: u32,
+        height: u32,
+        bytes_per_pixel: usize,
+        row_pitch: usize,
+        /// rows each image still has to copy from the last flushed one
+        image_damage: [Option<Range<u32>>; 3],
+        current: usize,
+    }
+
+    impl MappedTexture {
+        /// `format` can be any uncompressed single plane format with a drm fourcc
+        pub fn new<B: Backend>(
+            client: &Arc<B>,
+            dev: &Arc<Device>,
+            render_dev: &RenderDevice,
+            allocator: &UdmabufAllocator,
+            width: u32,
+            height: u32,
+            format: &DmatexFormat,
+        ) -> Self {
+            assert!(
+                !format.is_compressed() && format.vk_format().planes().is_empty(),
+                "mapped textures need an uncompressed single plane format, got {:?}",
+                format.vk_format()
+            );
+            assert!(width > 0 && height > 0, "mapped texture size can't be 0");
+            let fourcc = format
+                .drm_fourcc()
+                .expect("mapped textures need a drm fourcc");
+            let srgb = Srgbness::Auto
+                .resolve(format.vk_format())
+                .expect("the format has no srgb variant");
+            let size = DmatexSize::Dim2D([width, height].into());
+            let mut row_pitch = 0;
+            let images = [(); 3].map(|_| {
+                // TODO: error handling
+                let (dmabuf, memfd) = allocator
+                    .allocate_mappable_image(&size, fourcc, srgb)
+                    .unwrap()
+                    .expect("failed to allocate udmabuf");
+                row_pitch = dmabuf.planes[0].row_pitch as usize;
+                let len = row_pitch * height as usize;
+                let ptr = unsafe {
+                    mmap(
+                        ptr::null_mut(),
+                        len,
+                        ProtFlags::READ | ProtFlags::WRITE,
+                        MapFlags::SHARED,
+                        &memfd,
+                        0,
+                    )
+                }
+                .expect("failed to map udmabuf");
+                let dmatex = Arc::new(Dmatex::import(
+                    client,
+                    dev,
+                    render_dev,
+                    dmabuf,
+                    ImageUsage::TRANSFER_SRC,
+                ));
+                unsafe {
+                    dmatex.timeline.signal(0).unwrap();
+                }
+                MappedImage {
+                    dmatex,
+                    mapping: Mapping { ptr, len },
+                    release: 0,
+                }
+            });
+            Self {
+                images,
+                width,
+                height,
+                bytes_per_pixel: format.vk_format().block_size() as usize,
+                row_pitch,
+                image_damage: [None, None, None],
+                current: 0,
+            }
+        }
+        pub fn images(&self) -> impl Iterator<Item = &Arc<Dmatex>> {
+            self.images.iter().map(|v| &v.dmatex)
+        }
+        /// bytes from one row to the next in the images
+        pub fn row_pitch(&self) -> usize {
+            self.row_pitch
+        }
+    }
+
+    impl PixelSurface for MappedTexture {
+        fn width(&self) -> u32 {
+            self.width
+        }
+        fn height(&self) -> u32 {
+            self.height
+        }
+        fn bytes_per_pixel(&self) -> usize {
+            self.bytes_per_pixel
+        }
+        fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+            let row_len = self.width as usize * self.bytes_per_pixel;
+            self.images[self.current]
+                .mapping
+                .bytes_mut()
+                .chunks_exact_mut(self.row_pitch)
+                .map(move |row| &mut row[..row_len])
+        }
+        fn flush(
+            &mut self,
+            _queue: &Arc<Queue>,
+            damage: Range<u32>,
+        ) -> Result<Option<DmatexSubmitInfo>, SwapchainError> {
+            let damage = damage.start.min(self.height)..damage.end.min(self.height);
+            if damage.is_empty() {
+                return Ok(None);
+            }
+            let index = self.current;
+            let image = &mut self.images[index];
+            let acquire_point = image.release + 1;
+            image.release = acquire_point + 1;
+            // the cpu writes are visible to the server once the point is signalled
+            unsafe { image.dmatex.timeline.signal(acquire_point) }
+                .map_err(SwapchainError::Syncobj)?;
+            let submit = DmatexSubmitInfo {
+                dmatex_id: image.dmatex.dmatex_id,
+                acquire_point,
+                release_point: image.release,
+            };
+            for (i, rows) in self.image_damage.iter_mut().enumerate() {
+                if i != index {
+                    *rows = Some(match rows {
+                        Some(v) => v.start.min(damage.start)..v.end.max(damage.end),
+                        None => damage.clone(),
+                    });
+                }
+            }
+
+            self.current = (index + 1) % self.images.len();
+            let [prev, next] = self.images.get_disjoint_mut([index, self.current]).unwrap();
+            next.dmatex
+                .timeline
+                .blocking_wait(next.release, None)
+                .map_err(SwapchainError::Syncobj)?;
+            if let Some(rows) = self.image_damage[self.current].take() {
+                let bytes =
+                    rows.start as usize * self.row_pitch..rows.end as usize * self.row_pitch;
+                next.mapping.bytes_mut()[bytes.clone()]
+                    .copy_from_slice(&prev.mapping.bytes()[bytes]);
+            }
+            Ok(Some(submit))
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Compute passes over swapchain images between the caller's rendering and the server's
+//! acquire, e.g. color management, gamma or dimming applied to everything a client shows
+//!
+//! The shader gets the image as storage image at set 0 binding 0, an `image2DArray` for
+//! layered images and an `image2D` otherwise, with the layer or depth slice in
+//! `gl_GlobalInvocationID.z`. The swapchain has to be created with [`ImageUsage::STORAGE`]
+//! in a format that supports storage, usually a unorm one.
+//!
+//! [`ImageUsage::STORAGE`]: vulkano::image::ImageUsage::STORAGE
+
+use std::sync::{Arc, Mutex, Weak};
+
+use thiserror::Error;
+use vulkano::{
+    command_buffer::{
+        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        allocator::StandardCommandBufferAllocator,
+        sys::{CommandBuffer, RecordingCommandBuffer},
+    },
+    descriptor_set::{
+        DescriptorSet, WriteDescriptorSet, allocator::StandardDescriptorSetAllocator,
+    },
+    device::Device,
+    image::{Image, ImageLayout},
+    pipeline::{
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo, compute::ComputePipelineCreateInfo,
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+    shader::{EntryPoint, ShaderModule, ShaderModuleCreateInfo},
+    sync::{AccessFlags, DependencyInfo, ImageMemoryBarrier, PipelineStages},
+};
+
+use crate::dmatex::Dmatex;
+
+/// A compute shader run over every pixel of a frame, see
+/// [`SwapchainBuilder::post_process`](crate::swapchain::SwapchainBuilder::post_process)
+pub struct PostProcess {
+    pipeline: Arc<ComputePipeline>,
+    local_size: [u32; 2],
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    /// a set per image, the raw command buffers don't keep them alive
+    sets: Mutex<Vec<(Weak<Image>, Arc<DescriptorSet>)>>,
+    push_constants: Mutex<Vec<u8>>,
+}
+
+impl PostProcess {
+    /// `spirv` has to contain a compute shader named `entry_point` with a workgroup size of
+    /// `local_size` in x and y and 1 in z
+    pub fn new(
+        dev: &Arc<Device>,
+        spirv: &[u32],
+        entry_point: &str,
+        local_size: [u32; 2],
+    ) -> Result<Self, PostProcessError> {
+        let module = unsafe { ShaderModule::new(dev.clone(), ShaderModuleCreateInfo::new(spirv)) }
+            .map_err(|err| PostProcessError::Shader(err.to_string()))?;
+        let entry_point = module
+            .entry_point(entry_point)
+            .ok_or_else(|| PostProcessError::EntryPoint(entry_point.to_string()))?;
+        Self::from_entry_point(dev, entry_point, local_size)
+    }
+    /// like [`Self::new`], for shaders loaded elsewhere, e.g. with `vulkano_shaders`
+    pub fn from_entry_point(
+        dev: &Arc<Device>,
+        entry_point: EntryPoint,
+        local_size: [u32; 2],
+    ) -> Result<Self, PostProcessError> {
+        let stage = PipelineShaderStageCreateInfo::new(entry_point);
+        let layout = PipelineLayout::new(
+            dev.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(dev.clone())
+                .map_err(|err| PostProcessError::Pipeline(err.to_string()))?,
+        )
+        .map_err(|err| PostProcessError::Pipeline(err.to_string()))?;
+        let pipeline = ComputePipeline::new(
+            dev.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(|err| PostProcessError::Pipeline(err.to_string()))?;
+        Ok(Self {
+            pipeline,
+            local_size: local_size.map(|v| v.max(1)),
+            descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            sets: Mutex::default(),
+            push_constants: Mutex::default(),
+        })
+    }
+    /// Push constants for the following frames at offset 0, e.g. a brightness factor.
+    /// Nothing is pushed while empty
+    pub fn set_push_constants(&self, data: &[u8]) {
+        *self.push_constants.lock().unwrap() = data.to_vec();
+    }
+    /// The pass over one frame. `layout` is the layout the caller's work left the image in,
+    /// it's back in it afterwards
+    pub(crate) fn record(
+        &self,
+        allocator: &Arc<StandardCommandBufferAllocator>,
+        queue_family_index: u32,
+        dmatex: &Dmatex,
+        layout: ImageLayout,
+    ) -> Arc<CommandBuffer> {
+        let image = &dmatex.image;
+        let set = self.descriptor_set(dmatex);
+        let pipeline_layout = self.pipeline.layout();
+        let [width, height, depth] = image.extent();
+        let group_counts = [
+            width.div_ceil(self.local_size[0]),
+            height.div_ceil(self.local_size[1]),
+            depth * image.array_layers(),
+        ];
+        let push_constants = self.push_constants.lock().unwrap().clone();
+        let mut recording = unsafe {
+            RecordingCommandBuffer::new(
+                allocator.clone(),
+                queue_family_index,
+                CommandBufferLevel::Primary,
+                CommandBufferBeginInfo {
+                    usage: CommandBufferUsage::OneTimeSubmit,
+                    ..Default::default()
+                },
+            )
+        }
+        .unwrap();
+        unsafe {
+            recording
+                .pipeline_barrier(&DependencyInfo {
+                    image_memory_barriers: [ImageMemoryBarrier {
+                        src_stages: PipelineStages::ALL_COMMANDS,
+                        src_access: AccessFlags::MEMORY_WRITE,
+                        dst_stages: PipelineStages::COMPUTE_SHADER,
+                        dst_access: AccessFlags::SHADER_STORAGE_READ
+                            | AccessFlags::SHADER_STORAGE_WRITE,
+                        old_layout: layout,
+                        new_layout: ImageLayout::General,
+                        subresource_range: image.subresource_range(),
+                        ..ImageMemoryBarrier::image(image.clone())
+                    }]
+                    .into_iter()
+                    .collect(),
+                    ..Default::default()
+                })
+                .unwrap();
+            recording.bind_pipeline_compute(&self.pipeline).unwrap();
+            recording
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    pipeline_layout,
+                    0,
+                    &[&*set],
+                    &[],
+                )
+                .unwrap();
+            if !push_constants.is_empty() {
+                recording
+                    .push_constants(pipeline_layout, 0, push_constants.as_slice())
+                    .unwrap();
+            }
+            recording.dispatch(group_counts).unwrap();
+            recording
+                .pipeline_barrier(&DependencyInfo {
+                    image_memory_barriers: [ImageMemoryBarrier {
+                        src_stages: PipelineStages::COMPUTE_SHADER,
+                        src_access: AccessFlags::SHADER_STORAGE_WRITE,
+                        dst_stages: PipelineStages::ALL_COMMANDS,
+                        dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+                        old_layout: ImageLayout::General,
+                        new_layout: layout,
+                        subresource_range: image.subresource_range(),
+                        ..ImageMemoryBarrier::image(image.clone())
+                    }]
+                    .into_iter()
+                    .collect(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        Arc::new(unsafe { recording.end() }.unwrap())
+    }
+    fn descriptor_set(&self, dmatex: &Dmatex) -> Arc<DescriptorSet> {
+        let mut sets = self.sets.lock().unwrap();
+        // images that are gone aren't used by any pending work either
+        sets.retain(|(image, _)| image.strong_count() > 0);
+        if let Some((_, set)) = sets
+            .iter()
+            .find(|(image, _)| image.as_ptr() == Arc::as_ptr(&dmatex.image))
+        {
+            return set.clone();
+        }
+        let set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view(0, dmatex.create_view())],
+            [],
+        )
+        .unwrap();
+        sets.push((Arc::downgrade(&dmatex.image), set.clone()));
+        set
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PostProcessError {
+    #[error("invalid spir-v: {0}")]
+    Shader(String),
+    #[error("the shader has no entry point {0:?}")]
+    EntryPoint(String),
+    #[error("failed to create the compute pipeline: {0}")]
+    Pipeline(String),
+}
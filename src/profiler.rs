@@ -0,0 +1,147 @@
+//! GPU frame times from timestamp queries around a swapchain's submissions
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use vulkano::{
+    command_buffer::{
+        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        allocator::StandardCommandBufferAllocator,
+        sys::{CommandBuffer, RecordingCommandBuffer},
+    },
+    device::{Device, DeviceOwned},
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+/// frames that can be in flight before profiling skips frames
+const SLOTS: u32 = 8;
+/// weight of the newest frame in the moving average
+const SMOOTHING: f64 = 0.1;
+
+/// Measures how long the gpu took for each frame, enabled with
+/// [`SwapchainBuilder::gpu_profiling`](crate::swapchain::SwapchainBuilder::gpu_profiling).
+/// Results show up a few frames late, once the gpu is done with them
+pub struct GpuProfiler {
+    query_pool: Arc<QueryPool>,
+    /// nanoseconds per timestamp tick
+    timestamp_period: f64,
+    /// slots with submitted queries and their valid timestamp bits, oldest first
+    pending: VecDeque<(u32, u64)>,
+    next_slot: u32,
+    average: Option<Duration>,
+    last: Option<Duration>,
+}
+
+impl GpuProfiler {
+    pub fn new(dev: &Arc<Device>) -> Self {
+        let query_pool = QueryPool::new(
+            dev.clone(),
+            QueryPoolCreateInfo {
+                query_count: SLOTS * 2,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )
+        .unwrap();
+        Self {
+            query_pool,
+            timestamp_period: dev.physical_device().properties().timestamp_period as f64,
+            pending: VecDeque::new(),
+            next_slot: 0,
+            average: None,
+            last: None,
+        }
+    }
+    /// exponential moving average of the gpu frame time
+    pub fn average_frame_time(&self) -> Option<Duration> {
+        self.average
+    }
+    /// gpu time of the most recent frame that finished
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.last
+    }
+
+    /// reads the results of every finished frame, oldest first
+    pub(crate) fn collect(&mut self) {
+        while let Some((slot, mask)) = self.pending.front().copied() {
+            let mut timestamps = [0u64; 2];
+            let ready = self
+                .query_pool
+                .get_results(
+                    slot * 2..slot * 2 + 2,
+                    &mut timestamps,
+                    QueryResultFlags::empty(),
+                )
+                .unwrap_or(false);
+            if !ready {
+                break;
+            }
+            self.pending.pop_front();
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]) & mask;
+            let frame_time = Duration::from_nanos((ticks as f64 * self.timestamp_period) as u64);
+            self.last = Some(frame_time);
+            self.average = Some(match self.average {
+                Some(average) => average.mul_f64(1.0 - SMOOTHING) + frame_time.mul_f64(SMOOTHING),
+                None => frame_time,
+            });
+        }
+    }
+
+    /// Command buffers writing the start and end timestamp of a frame, None if every slot
+    /// is still waiting for its results or the queue family can't write timestamps
+    pub(crate) fn begin_frame(
+        &mut self,
+        allocator: &Arc<StandardCommandBufferAllocator>,
+        queue_family_index: u32,
+    ) -> Option<(Arc<CommandBuffer>, Arc<CommandBuffer>)> {
+        self.collect();
+        let valid_bits = self
+            .query_pool
+            .device()
+            .physical_device()
+            .queue_family_properties()[queue_family_index as usize]
+            .timestamp_valid_bits?;
+        if self.pending.len() == SLOTS as usize {
+            return None;
+        }
+        let slot = self.next_slot;
+        self.next_slot = (self.next_slot + 1) % SLOTS;
+        self.pending
+            .push_back((slot, u64::MAX >> (64 - valid_bits)));
+        let query_pool = &self.query_pool;
+        let start = record(allocator, queue_family_index, |recording| unsafe {
+            recording
+                .reset_query_pool(query_pool, slot * 2..slot * 2 + 2)
+                .unwrap();
+            recording
+                .write_timestamp(query_pool, slot * 2, PipelineStage::TopOfPipe)
+                .unwrap();
+        });
+        let end = record(allocator, queue_family_index, |recording| unsafe {
+            recording
+                .write_timestamp(query_pool, slot * 2 + 1, PipelineStage::BottomOfPipe)
+                .unwrap();
+        });
+        Some((start, end))
+    }
+}
+
+fn record(
+    allocator: &Arc<StandardCommandBufferAllocator>,
+    queue_family_index: u32,
+    record: impl FnOnce(&mut RecordingCommandBuffer),
+) -> Arc<CommandBuffer> {
+    let mut recording = unsafe {
+        RecordingCommandBuffer::new(
+            allocator.clone(),
+            queue_family_index,
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+    }
+    .unwrap();
+    record(&mut recording);
+    Arc::new(unsafe { recording.end() }.unwrap())
+}
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    descriptor_set::allocator::StandardDescriptorSetAllocator, device::Device,
+    memory::allocator::StandardMemoryAllocator,
+};
+
+/// The allocators a client needs next to its dmatexes, created once per device and cheap to
+/// clone. Pass [`Self::command_buffer_allocator`] to
+/// [`SwapchainBuilder::command_buffer_allocator`](crate::swapchain::SwapchainBuilder::command_buffer_allocator)
+/// so swapchains share it too
+#[derive(Clone)]
+pub struct RenderContext {
+    dev: Arc<Device>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+}
+
+impl RenderContext {
+    /// creates all allocators with vulkano's defaults
+    pub fn new(dev: &Arc<Device>) -> Self {
+        Self {
+            dev: dev.clone(),
+            memory_allocator: Arc::new(StandardMemoryAllocator::new_default(dev.clone())),
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+        }
+    }
+    pub fn device(&self) -> &Arc<Device> {
+        &self.dev
+    }
+    pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
+        &self.memory_allocator
+    }
+    pub fn command_buffer_allocator(&self) -> &Arc<StandardCommandBufferAllocator> {
+        &self.command_buffer_allocator
+    }
+    pub fn descriptor_set_allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.descriptor_set_allocator
+    }
+}
@@ -1,11 +1,11 @@
 use std::sync::Arc;
 
-use stardust_xr_fusion::{ClientHandle, drawable::get_primary_render_device_id, node::NodeError};
+use stardust_xr_fusion::node::NodeError;
 use thiserror::Error;
 use timeline_syncobj::render_node::DrmRenderNode;
 use vulkano::{VulkanError, device::physical::PhysicalDevice, instance::Instance};
 
-use crate::get_phys_dev_node_id;
+use crate::{backend::Backend, get_phys_dev_node_id};
 
 /// Roughly corresponds to a GPU
 pub struct RenderDevice {
@@ -15,21 +15,34 @@ pub struct RenderDevice {
 
 impl RenderDevice {
     /// initializes Self with the preferred [`RenderDevice`] of the server
-    pub async fn primary_server_device(
-        client: &Arc<ClientHandle>,
+    pub async fn primary_server_device<B: Backend>(
+        client: &Arc<B>,
     ) -> Result<Self, RenderDeviceCreationError> {
-        let id = get_primary_render_device_id(client)
+        let id = client
+            .clone()
+            .primary_render_device_id()
             .await
             .map_err(RenderDeviceCreationError::FailedToGetDeviceId)?;
-        let drm_node =
-            DrmRenderNode::new(id).map_err(RenderDeviceCreationError::FailedToOpenDrmNode)?;
+        Self::from_node_id(id)
+    }
 
+    /// opens the drm node with the given id, e.g. one the server reported
+    pub fn from_node_id(render_node_id: u64) -> Result<Self, RenderDeviceCreationError> {
+        let drm_node = DrmRenderNode::new(render_node_id)
+            .map_err(RenderDeviceCreationError::FailedToOpenDrmNode)?;
         Ok(Self {
             drm_node,
-            render_node_id: id,
+            render_node_id,
         })
     }
 
+    /// Opens the drm node again, e.g. after a gpu reset left the old fd unusable.
+    /// Everything created from the old device has to be recreated, see
+    /// [`Swapchain::recreate_on`](crate::swapchain::Swapchain::recreate_on)
+    pub fn reopen(&self) -> Result<Self, RenderDeviceCreationError> {
+        Self::from_node_id(self.render_node_id)
+    }
+
     pub fn get_physical_device(
         &self,
         instance: &Arc<Instance>,
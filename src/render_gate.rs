@@ -0,0 +1,194 @@
+//! Skipping frames for surfaces nobody can see
+//!
+//! With the `vulkan` feature a gate `follow`ing a client picks up its visibility and focus
+//! from the client's `SurfaceEvent`s, which a `ClientHandle` gets from
+//! `backend::publish_surface_event`. The app asks the gate every frame whether to render, and
+//! can override what the events said with [`RenderGate::set_visible`].
+
+#[cfg(feature = "vulkan")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "vulkan")]
+use crate::backend::{Backend, SurfaceEvent, SurfaceEvents};
+
+/// What to do this frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateDecision {
+    /// don't prepare or submit a frame
+    Skip,
+    /// render normally, after a pause `full_damage` is set since the content shown before
+    /// the pause might be stale, e.g. call `Canvas::mark_all_dirty`
+    Render { full_damage: bool },
+}
+
+/// Tracks whether a surface is visible and turns that into per frame render decisions
+pub struct RenderGate {
+    visible: bool,
+    focused: bool,
+    /// whether losing focus pauses rendering like being hidden does
+    pause_unfocused: bool,
+    #[cfg(feature = "vulkan")]
+    events: Option<SurfaceEvents>,
+    /// a frame was skipped since the last rendered one
+    paused: bool,
+    /// while hidden, still render one frame this often so the server never shows very old
+    /// content when the surface comes back before the app noticed
+    keepalive: Option<Duration>,
+    last_render: Option<Instant>,
+}
+
+impl Default for RenderGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderGate {
+    /// starts out visible
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            focused: true,
+            pause_unfocused: false,
+            #[cfg(feature = "vulkan")]
+            events: None,
+            paused: false,
+            keepalive: None,
+            last_render: None,
+        }
+    }
+    /// render a frame at least this often even while hidden
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+    /// follows the visibility and focus of `client`, see [`Backend::surface_events`]
+    #[cfg(feature = "vulkan")]
+    pub fn follow<B: Backend>(self, client: &Arc<B>) -> Self {
+        self.with_events(client.clone().surface_events())
+    }
+    /// like [`Self::follow`] with an existing subscription
+    #[cfg(feature = "vulkan")]
+    pub fn with_events(mut self, events: SurfaceEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+    /// skip frames while the client doesn't have focus as well, not just while it's hidden
+    pub fn pause_unfocused(mut self, pause: bool) -> Self {
+        self.pause_unfocused = pause;
+        self
+    }
+    /// whether the surface is seen, until the next visibility event
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+    /// whether frames were skipped since the last rendered one
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    /// Decides whether to render this frame, call once per frame
+    pub fn begin_frame(&mut self) -> GateDecision {
+        #[cfg(feature = "vulkan")]
+        for event in self.events.iter().flat_map(|v| v.try_iter()) {
+            match event {
+                SurfaceEvent::Visible(visible) => self.visible = visible,
+                SurfaceEvent::Focused(focused) => self.focused = focused,
+                SurfaceEvent::PreferredSize(_) => {}
+            }
+        }
+        let shown = self.visible && (self.focused || !self.pause_unfocused);
+        let keepalive_due = self.keepalive.is_some_and(|interval| {
+            self.last_render
+                .is_none_or(|last| last.elapsed() >= interval)
+        });
+        if !shown && !keepalive_due {
+            self.paused = true;
+            return GateDecision::Skip;
+        }
+        self.last_render = Some(Instant::now());
+        GateDecision::Render {
+            full_damage: std::mem::take(&mut self.paused),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "vulkan")]
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    const RENDER: GateDecision = GateDecision::Render { full_damage: false };
+    const RESUME: GateDecision = GateDecision::Render { full_damage: true };
+
+    #[test]
+    fn hidden_frames_are_skipped() {
+        let mut gate = RenderGate::new();
+        assert_eq!(gate.begin_frame(), RENDER);
+        gate.set_visible(false);
+        assert_eq!(gate.begin_frame(), GateDecision::Skip);
+        assert_eq!(gate.begin_frame(), GateDecision::Skip);
+        assert!(gate.is_paused());
+        gate.set_visible(true);
+        // the first frame after the pause redraws everything, the next one doesn't
+        assert_eq!(gate.begin_frame(), RESUME);
+        assert!(!gate.is_paused());
+        assert_eq!(gate.begin_frame(), RENDER);
+    }
+
+    #[test]
+    fn keepalive_renders_hidden_surfaces() {
+        let mut gate = RenderGate::new().with_keepalive(Duration::from_secs(3600));
+        gate.set_visible(false);
+        // nothing was rendered yet, so the keepalive is due right away
+        assert_eq!(gate.begin_frame(), RENDER);
+        assert_eq!(gate.begin_frame(), GateDecision::Skip);
+        gate.set_visible(true);
+        assert_eq!(gate.begin_frame(), RESUME);
+
+        let mut gate = RenderGate::new().with_keepalive(Duration::ZERO);
+        gate.set_visible(false);
+        assert_eq!(gate.begin_frame(), RENDER);
+        assert_eq!(gate.begin_frame(), RENDER);
+        assert!(!gate.is_paused());
+    }
+
+    #[cfg(feature = "vulkan")]
+    #[test]
+    fn follows_surface_events() {
+        let (sender, events) = channel();
+        let mut gate = RenderGate::new().with_events(events);
+        sender.send(SurfaceEvent::Visible(false)).unwrap();
+        assert_eq!(gate.begin_frame(), GateDecision::Skip);
+        assert!(!gate.is_visible());
+        // focus alone doesn't pause by default
+        sender.send(SurfaceEvent::Visible(true)).unwrap();
+        sender.send(SurfaceEvent::Focused(false)).unwrap();
+        assert_eq!(gate.begin_frame(), RESUME);
+        assert!(!gate.is_focused());
+        // only the last of several events counts
+        sender.send(SurfaceEvent::Visible(false)).unwrap();
+        sender.send(SurfaceEvent::Visible(true)).unwrap();
+        assert_eq!(gate.begin_frame(), RENDER);
+    }
+
+    #[cfg(feature = "vulkan")]
+    #[test]
+    fn can_pause_unfocused() {
+        let (sender, events) = channel();
+        let mut gate = RenderGate::new().with_events(events).pause_unfocused(true);
+        sender.send(SurfaceEvent::Focused(false)).unwrap();
+        assert_eq!(gate.begin_frame(), GateDecision::Skip);
+        assert!(gate.is_visible());
+        sender.send(SurfaceEvent::Focused(true)).unwrap();
+        assert_eq!(gate.begin_frame(), RESUME);
+    }
+}
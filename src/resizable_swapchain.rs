@@ -0,0 +1,155 @@
+//! A [`Swapchain`] that follows size requests, e.g. a panel's preferred pixel size
+//!
+//! A swapchain [following](ResizableSwapchain::follow) a client resizes to the preferred
+//! sizes in the client's [`SurfaceEvent`]s, which a [`ClientHandle`] gets from
+//! [`publish_surface_event`]. [`ResizableSwapchain::request_size`] requests a size directly.
+//!
+//! With an [idle policy](ResizableSwapchain::with_idle_reclaim) the images of a swapchain that
+//! hasn't rendered for a while are freed from [`ResizableSwapchain::reclaim_idle`], and
+//! recreated by the next [`ResizableSwapchain::prepare_next_image`].
+//!
+//! [`ClientHandle`]: stardust_xr_fusion::ClientHandle
+//! [`publish_surface_event`]: crate::backend::publish_surface_event
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use stardust_xr_fusion::drawable::DmatexSize;
+
+use crate::{
+    backend::{Backend, SurfaceEvent, SurfaceEvents},
+    render_device::RenderDevice,
+    swapchain::{IdleReclaim, Swapchain, SwapchainError, SwapchainFrameHandle},
+};
+
+const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Recreates the images of the wrapped swapchain once size requests stopped changing for the
+/// debounce duration, so dragging a panel doesn't reallocate every frame
+pub struct ResizableSwapchain<T = ()> {
+    swapchain: Swapchain<T>,
+    pending: Option<(DmatexSize, Instant)>,
+    debounce: Duration,
+    on_resize: Option<Box<dyn FnMut(&DmatexSize) + Send>>,
+    idle: Option<(Duration, IdleReclaim)>,
+    last_frame: Instant,
+    events: Option<SurfaceEvents>,
+}
+
+impl<T> ResizableSwapchain<T> {
+    pub fn new(swapchain: Swapchain<T>) -> Self {
+        Self {
+            swapchain,
+            pending: None,
+            debounce: DEFAULT_DEBOUNCE,
+            on_resize: None,
+            idle: None,
+            last_frame: Instant::now(),
+            events: None,
+        }
+    }
+    /// how long the requested size has to stay the same before the images are recreated,
+    /// defaults to 100ms
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+    /// called with the new size right after the images were recreated
+    pub fn on_resize(mut self, on_resize: impl FnMut(&DmatexSize) + Send + 'static) -> Self {
+        self.on_resize = Some(Box::new(on_resize));
+        self
+    }
+    /// follows the preferred sizes of `client`, see [`Backend::surface_events`]
+    pub fn follow<B: Backend>(self, client: &Arc<B>) -> Self {
+        self.with_events(client.clone().surface_events())
+    }
+    /// like [`Self::follow`] with an existing subscription
+    pub fn with_events(mut self, events: SurfaceEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+    /// shrinks the swapchain once no frame was prepared for `after`, see [`Swapchain::shrink`]
+    pub fn with_idle_reclaim(mut self, after: Duration, reclaim: IdleReclaim) -> Self {
+        self.idle = Some((after, reclaim));
+        self
+    }
+    /// Frees the images if the idle period passed, call this from a timer of the event loop
+    /// since idle apps don't prepare frames. Returns whether the swapchain was shrunk
+    pub fn reclaim_idle(&mut self, render_dev: &RenderDevice) -> Result<bool, SwapchainError> {
+        let Some((after, reclaim)) = self.idle else {
+            return Ok(false);
+        };
+        if self.swapchain.is_shrunk() || self.last_frame.elapsed() < after {
+            return Ok(false);
+        }
+        self.swapchain.shrink(render_dev, reclaim)?;
+        Ok(true)
+    }
+    /// Requests a new size, every request restarts the debounce timer
+    pub fn request_size(&mut self, size: DmatexSize) {
+        if self.pending.is_none() && same_size(&size, self.swapchain.size()) {
+            return;
+        }
+        self.pending = Some((size, Instant::now()));
+    }
+    /// whether a requested size is still waiting for the debounce to pass
+    pub fn resize_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+    /// Applies a settled size request, recreates reclaimed images and hands out the next image.
+    /// A size request that fails to apply is dropped, the swapchain keeps its old size
+    pub fn prepare_next_image(
+        &mut self,
+        render_dev: &RenderDevice,
+    ) -> Result<SwapchainFrameHandle<'_, T>, SwapchainError> {
+        self.last_frame = Instant::now();
+        // every request restarts the debounce, only the latest one matters
+        let preferred = self
+            .events
+            .iter()
+            .flat_map(|v| v.try_iter())
+            .filter_map(|event| match event {
+                SurfaceEvent::PreferredSize(size) => Some(size),
+                _ => None,
+            })
+            .last();
+        if let Some(size) = preferred {
+            self.request_size(size);
+        }
+        if let Some((_, requested)) = &self.pending
+            && requested.elapsed() >= self.debounce
+        {
+            let (size, _) = self.pending.take().unwrap();
+            if !same_size(&size, self.swapchain.size()) {
+                self.swapchain.resize(render_dev, size)?;
+                if let Some(on_resize) = &mut self.on_resize {
+                    on_resize(self.swapchain.size());
+                }
+            }
+        }
+        if self.swapchain.is_shrunk() {
+            self.swapchain.expand(render_dev)?;
+        }
+        Ok(self.swapchain.prepare_next_image())
+    }
+    pub fn swapchain(&self) -> &Swapchain<T> {
+        &self.swapchain
+    }
+    pub fn swapchain_mut(&mut self) -> &mut Swapchain<T> {
+        &mut self.swapchain
+    }
+    pub fn into_inner(self) -> Swapchain<T> {
+        self.swapchain
+    }
+}
+
+fn same_size(a: &DmatexSize, b: &DmatexSize) -> bool {
+    match (a, b) {
+        (DmatexSize::Dim1D(a), DmatexSize::Dim1D(b)) => a == b,
+        (DmatexSize::Dim2D(a), DmatexSize::Dim2D(b)) => a.x == b.x && a.y == b.y,
+        (DmatexSize::Dim3D(a), DmatexSize::Dim3D(b)) => a.x == b.x && a.y == b.y && a.z == b.z,
+        _ => false,
+    }
+}
@@ -0,0 +1,197 @@
+//! Gpu drawn 2D surfaces using skia, rendering straight into the images of a [`Swapchain`]
+//!
+//! Skia shares the vulkan device and render queue with cme. Every frame skia renders and
+//! waits for its work on the cpu while holding the queue, then an empty submission hands the
+//! image to the server, so skia never has to know about the timeline.
+
+use std::{ffi::c_void, sync::Arc};
+
+use skia_safe::{
+    Canvas, ColorType,
+    gpu::{
+        DirectContext, SurfaceOrigin, SyncCpu, backend_render_targets, direct_contexts, surfaces,
+        vk,
+    },
+};
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use vulkano::{
+    Handle, VulkanObject,
+    command_buffer::{SemaphoreSubmitInfo, SubmitInfo},
+    device::{Device, DeviceOwned, Queue},
+    format::Format,
+    image::ImageUsage,
+    sync::PipelineStages,
+};
+
+use crate::{
+    backend::Backend,
+    format::DmatexFormat,
+    render_device::RenderDevice,
+    swapchain::{Swapchain, SwapchainError},
+};
+
+/// A swapchain skia renders into with its vulkan backend
+pub struct SkiaSurface {
+    /// dropped first, it still references the device
+    context: DirectContext,
+    dev: Arc<Device>,
+    queue: Arc<Queue>,
+    swapchain: Swapchain,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    vk_format: vk::Format,
+}
+
+impl SkiaSurface {
+    /// `format` has to be rgba8 or bgra8, unorm or srgb
+    ///
+    /// # Safety
+    /// skia submits to `queue` through its raw handle, only while cme holds the queue
+    pub unsafe fn new<B: Backend>(
+        client: &Arc<B>,
+        queue: &Arc<Queue>,
+        render_dev: &RenderDevice,
+        width: u32,
+        height: u32,
+        format: &DmatexFormat,
+    ) -> Result<Self, SkiaError> {
+        let (color_type, vk_format) = match format.vk_format() {
+            Format::R8G8B8A8_UNORM => (ColorType::RGBA8888, vk::Format::R8G8B8A8_UNORM),
+            Format::R8G8B8A8_SRGB => (ColorType::RGBA8888, vk::Format::R8G8B8A8_SRGB),
+            Format::B8G8R8A8_UNORM => (ColorType::BGRA8888, vk::Format::B8G8R8A8_UNORM),
+            Format::B8G8R8A8_SRGB => (ColorType::BGRA8888, vk::Format::B8G8R8A8_SRGB),
+            format => panic!("skia surfaces need an rgba8 or bgra8 format, got {format:?}"),
+        };
+        let dev = queue.device().clone();
+        let instance = dev.instance().clone();
+        let get_proc = |of: vk::GetProcOf| unsafe {
+            let function = match of {
+                vk::GetProcOf::Instance(handle, name) => instance
+                    .library()
+                    .get_instance_proc_addr(Handle::from_raw(handle as u64), name),
+                vk::GetProcOf::Device(handle, name) => (instance.fns().v1_0.get_device_proc_addr)(
+                    Handle::from_raw(handle as u64),
+                    name,
+                ),
+            };
+            function.map_or(std::ptr::null(), |f| f as *const c_void)
+        };
+        let backend_context = unsafe {
+            vk::BackendContext::new(
+                instance.handle().as_raw() as _,
+                dev.physical_device().handle().as_raw() as _,
+                dev.handle().as_raw() as _,
+                (
+                    queue.handle().as_raw() as _,
+                    queue.queue_family_index() as usize,
+                ),
+                &get_proc,
+            )
+        };
+        let context =
+            direct_contexts::make_vulkan(&backend_context, None).ok_or(SkiaError::NoContext)?;
+        let swapchain = Swapchain::builder(
+            client,
+            &dev,
+            render_dev,
+            DmatexSize::Dim2D([width, height].into()),
+            format,
+            ImageUsage::COLOR_ATTACHMENT
+                | ImageUsage::SAMPLED
+                | ImageUsage::TRANSFER_SRC
+                | ImageUsage::TRANSFER_DST,
+        )
+        .build()?;
+        Ok(Self {
+            context,
+            dev,
+            queue: queue.clone(),
+            swapchain,
+            width,
+            height,
+            color_type,
+            vk_format,
+        })
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn context(&mut self) -> &mut DirectContext {
+        &mut self.context
+    }
+    /// Draws the next image with `draw` and submits it, the whole image is redrawn every frame
+    pub fn draw(&mut self, draw: impl FnOnce(&Canvas)) -> Result<DmatexSubmitInfo, SwapchainError> {
+        let Self {
+            context,
+            dev,
+            queue,
+            swapchain,
+            width,
+            height,
+            color_type,
+            vk_format,
+        } = self;
+        let frame = swapchain.prepare_next_image();
+        let image = frame.image();
+        frame.submit(dev, queue, |wait, mut guard, signal| {
+            let image_info = unsafe {
+                vk::ImageInfo::new(
+                    image.handle().as_raw() as _,
+                    vk::Alloc::default(),
+                    vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT,
+                    vk::ImageLayout::UNDEFINED,
+                    *vk_format,
+                    1,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+            let target =
+                backend_render_targets::make_vk((*width as i32, *height as i32), &image_info);
+            let mut surface = surfaces::wrap_backend_render_target(
+                context,
+                &target,
+                SurfaceOrigin::TopLeft,
+                *color_type,
+                None,
+                None,
+            )
+            .expect("skia can't render into the swapchain image");
+            draw(surface.canvas());
+            // skia submits to the raw queue, the guard keeps everyone else off it meanwhile
+            context.flush_and_submit_surface(&mut surface, SyncCpu::Yes);
+            drop(surface);
+            unsafe {
+                guard.submit(
+                    &[SubmitInfo {
+                        wait_semaphores: vec![SemaphoreSubmitInfo {
+                            stages: PipelineStages::ALL_COMMANDS,
+                            ..SemaphoreSubmitInfo::new(wait)
+                        }],
+                        signal_semaphores: vec![SemaphoreSubmitInfo {
+                            stages: PipelineStages::ALL_COMMANDS,
+                            ..SemaphoreSubmitInfo::new(signal)
+                        }],
+                        ..Default::default()
+                    }],
+                    None,
+                )
+            }
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SkiaError {
+    #[error("skia can't create a vulkan context on the device")]
+    NoContext,
+    #[error(transparent)]
+    Swapchain(#[from] SwapchainError),
+}
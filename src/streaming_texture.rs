@@ -0,0 +1,216 @@
+//! CPU updated surfaces like plots or terminal emulators, only the rows that changed are copied
+
+use std::{ops::Range, sync::Arc};
+
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CommandBufferUsage, CopyBufferToImageInfo,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, Queue},
+    image::ImageUsage,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::{
+    backend::Backend,
+    format::DmatexFormat,
+    pixel_surface::PixelSurface,
+    render_device::RenderDevice,
+    swapchain::{Swapchain, SwapchainError},
+};
+
+/// A 2D swapchain fed from a cpu side copy of its pixels. Every swapchain image has its own
+/// persistently mapped staging buffer, which is reused once the server released the image
+pub struct StreamingTexture {
+    dev: Arc<Device>,
+    swapchain: Swapchain,
+    width: u32,
+    height: u32,
+    row_pitch: usize,
+    pixels: Vec<u8>,
+    /// rows each swapchain image still has to catch up on,
+    /// the swapchain hands out its images round robin
+    image_damage: [Option<Range<u32>>; 3],
+    next_image: usize,
+    staging: [Subbuffer<[u8]>; 3],
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+}
+
+impl StreamingTexture {
+    /// `format` can be any uncompressed single plane format, the pixels are tightly packed
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        width: u32,
+        height: u32,
+        format: &DmatexFormat,
+    ) -> Result<Self, SwapchainError> {
+        assert!(
+            !format.is_compressed() && format.vk_format().planes().is_empty(),
+            "streaming textures need an uncompressed single plane format, got {:?}",
+            format.vk_format()
+        );
+        assert!(width > 0 && height > 0, "streaming texture size can't be 0");
+        let swapchain = Swapchain::builder(
+            client,
+            dev,
+            render_dev,
+            DmatexSize::Dim2D([width, height].into()),
+            format,
+            ImageUsage::TRANSFER_DST,
+        )
+        .build()?;
+        let row_pitch = width as usize * format.vk_format().block_size() as usize;
+        let size = row_pitch * height as usize;
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+        let staging = [(); 3].map(|_| {
+            Buffer::new_slice::<u8>(
+                memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::TRANSFER_SRC,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                size as u64,
+            )
+            .unwrap()
+        });
+        Ok(Self {
+            dev: dev.clone(),
+            swapchain,
+            width,
+            height,
+            row_pitch,
+            pixels: vec![0; size],
+            image_damage: [Some(0..height), Some(0..height), Some(0..height)],
+            next_image: 0,
+            staging,
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+        })
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// bytes per row of [`Self::pixels`]
+    pub fn row_pitch(&self) -> usize {
+        self.row_pitch
+    }
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+    /// direct access to the pixels, changes have to be reported with [`Self::mark_rows_dirty`]
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+    /// gives `update` the bytes of `rows` and marks them as dirty
+    pub fn update_rows(&mut self, rows: Range<u32>, update: impl FnOnce(&mut [u8])) {
+        let rows = rows.start.min(self.height)..rows.end.min(self.height);
+        update(&mut self.pixels[self.row_range(&rows)]);
+        self.mark_rows_dirty(rows);
+    }
+    pub fn mark_rows_dirty(&mut self, rows: Range<u32>) {
+        let rows = rows.start.min(self.height)..rows.end.min(self.height);
+        if rows.is_empty() {
+            return;
+        }
+        for damage in &mut self.image_damage {
+            *damage = Some(match damage {
+                Some(v) => v.start.min(rows.start)..v.end.max(rows.end),
+                None => rows.clone(),
+            });
+        }
+    }
+    pub fn mark_all_dirty(&mut self) {
+        self.image_damage = [
+            Some(0..self.height),
+            Some(0..self.height),
+            Some(0..self.height),
+        ];
+    }
+    /// Copies the dirty rows into the next swapchain image,
+    /// returns None if nothing changed since the last present
+    pub fn present(
+        &mut self,
+        queue: &Arc<Queue>,
+    ) -> Result<Option<DmatexSubmitInfo>, SwapchainError> {
+        let index = self.next_image;
+        let Some(rows) = self.image_damage[index].take() else {
+            return Ok(None);
+        };
+        self.next_image = (self.next_image + 1) % self.image_damage.len();
+        let bytes = self.row_range(&rows);
+        let frame = self.swapchain.prepare_next_image();
+        // the last copy out of this staging buffer is done once the server released the image
+        frame.blocking_release_wait();
+        let staging = &self.staging[index];
+        staging.write().unwrap()[bytes.clone()].copy_from_slice(&self.pixels[bytes.clone()]);
+
+        let image = frame.image();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    buffer_offset: bytes.start as u64,
+                    buffer_row_length: self.width,
+                    image_subresource: image.subresource_layers(),
+                    image_offset: [0, rows.start, 0],
+                    image_extent: [self.width, rows.end - rows.start, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(staging.clone(), image.clone())
+            })
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        frame
+            .submit_command_buffer(&self.dev, queue, command_buffer)
+            .map(Some)
+    }
+
+    fn row_range(&self, rows: &Range<u32>) -> Range<usize> {
+        rows.start as usize * self.row_pitch..rows.end as usize * self.row_pitch
+    }
+}
+
+impl PixelSurface for StreamingTexture {
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn height(&self) -> u32 {
+        self.height
+    }
+    fn bytes_per_pixel(&self) -> usize {
+        self.row_pitch / self.width as usize
+    }
+    fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.pixels.chunks_exact_mut(self.row_pitch)
+    }
+    fn flush(
+        &mut self,
+        queue: &Arc<Queue>,
+        damage: Range<u32>,
+    ) -> Result<Option<DmatexSubmitInfo>, SwapchainError> {
+        self.mark_rows_dirty(damage);
+        self.present(queue)
+    }
+}
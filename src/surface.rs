@@ -0,0 +1,163 @@
+//! A swapchain wired up to where its frames are shown, for apps that just want something
+//! to draw on
+//!
+//! The target gets the [`DmatexSubmitInfo`] of every submitted frame, usually it sets a
+//! material parameter of a model part to it. A surface [following](Surface::follow) its
+//! client resizes to the preferred sizes in the client's surface events and skips frames
+//! while they say it's hidden, see [`ResizableSwapchain`] and [`RenderGate`].
+
+use std::{sync::Arc, time::Duration};
+
+use stardust_xr_fusion::{
+    drawable::{DmatexSize, DmatexSubmitInfo},
+    node::{NodeError, NodeResult},
+};
+use thiserror::Error;
+use vulkano::{
+    Validated, VulkanError,
+    command_buffer::PrimaryAutoCommandBuffer,
+    device::{Device, Queue, QueueGuard},
+    image::Image,
+    sync::semaphore::Semaphore,
+};
+
+use crate::{
+    backend::Backend,
+    render_device::RenderDevice,
+    render_gate::{GateDecision, RenderGate},
+    resizable_swapchain::ResizableSwapchain,
+    swapchain::{IdleReclaim, Swapchain, SwapchainError, SwapchainFrameHandle},
+};
+
+type SurfaceTarget = Box<dyn FnMut(&DmatexSubmitInfo) -> NodeResult<()> + Send>;
+
+/// Owns a swapchain and hands every submitted frame to its target
+pub struct Surface<T = ()> {
+    swapchain: ResizableSwapchain<T>,
+    gate: RenderGate,
+    target: SurfaceTarget,
+}
+
+impl<T> Surface<T> {
+    /// `target` is called with every submitted frame, e.g. to set a material parameter
+    pub fn new(
+        swapchain: Swapchain<T>,
+        target: impl FnMut(&DmatexSubmitInfo) -> NodeResult<()> + Send + 'static,
+    ) -> Self {
+        Self {
+            swapchain: ResizableSwapchain::new(swapchain),
+            gate: RenderGate::new(),
+            target: Box::new(target),
+        }
+    }
+    /// see [`ResizableSwapchain::on_resize`]
+    pub fn on_resize(mut self, on_resize: impl FnMut(&DmatexSize) + Send + 'static) -> Self {
+        self.swapchain = self.swapchain.on_resize(on_resize);
+        self
+    }
+    /// see [`ResizableSwapchain::with_idle_reclaim`]
+    pub fn with_idle_reclaim(mut self, after: Duration, reclaim: IdleReclaim) -> Self {
+        self.swapchain = self.swapchain.with_idle_reclaim(after, reclaim);
+        self
+    }
+    /// see [`ResizableSwapchain::reclaim_idle`]
+    pub fn reclaim_idle(&mut self, render_dev: &RenderDevice) -> Result<bool, SwapchainError> {
+        self.swapchain.reclaim_idle(render_dev)
+    }
+    /// follows the visibility, focus and preferred size of `client`, see
+    /// [`RenderGate::follow`] and [`ResizableSwapchain::follow`]
+    pub fn follow<B: Backend>(mut self, client: &Arc<B>) -> Self {
+        self.gate = std::mem::take(&mut self.gate).follow(client);
+        self.swapchain = self.swapchain.follow(client);
+        self
+    }
+    /// replaces the default gate, e.g. with one using [`RenderGate::with_keepalive`]
+    pub fn with_gate(mut self, gate: RenderGate) -> Self {
+        self.gate = gate;
+        self
+    }
+    /// the image size is changed once requests settle, see [`ResizableSwapchain`]
+    pub fn request_size(&mut self, size: DmatexSize) {
+        self.swapchain.request_size(size);
+    }
+    /// hidden surfaces skip their frames until the next visibility event, see [`RenderGate`]
+    pub fn set_visible(&mut self, visible: bool) {
+        self.gate.set_visible(visible);
+    }
+    pub fn is_visible(&self) -> bool {
+        self.gate.is_visible()
+    }
+    pub fn swapchain(&self) -> &Swapchain<T> {
+        self.swapchain.swapchain()
+    }
+    pub fn swapchain_mut(&mut self) -> &mut Swapchain<T> {
+        self.swapchain.swapchain_mut()
+    }
+    /// The next frame to draw, None if the surface is hidden and this frame is skipped
+    pub fn begin_frame(
+        &mut self,
+        render_dev: &RenderDevice,
+    ) -> Result<Option<SurfaceFrame<'_, T>>, SwapchainError> {
+        let GateDecision::Render { full_damage } = self.gate.begin_frame() else {
+            return Ok(None);
+        };
+        Ok(Some(SurfaceFrame {
+            frame: self.swapchain.prepare_next_image(render_dev)?,
+            target: &mut self.target,
+            full_damage,
+        }))
+    }
+}
+
+/// A frame of a [`Surface`], submitting it hands it to the surface's target
+pub struct SurfaceFrame<'a, T = ()> {
+    frame: SwapchainFrameHandle<'a, T>,
+    target: &'a mut SurfaceTarget,
+    /// the surface was hidden before this frame, everything has to be redrawn
+    pub full_damage: bool,
+}
+
+impl<'a, T> SurfaceFrame<'a, T> {
+    pub fn handle(&self) -> &SwapchainFrameHandle<'a, T> {
+        &self.frame
+    }
+    pub fn image(&self) -> Arc<Image> {
+        self.frame.image()
+    }
+    /// see [`SwapchainFrameHandle::submit`]
+    pub fn submit(
+        self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        submit: impl FnOnce(
+            Arc<Semaphore>,
+            QueueGuard,
+            Arc<Semaphore>,
+        ) -> Result<(), Validated<VulkanError>>,
+    ) -> Result<DmatexSubmitInfo, SurfaceError> {
+        let info = self.frame.submit(dev, render_queue, submit)?;
+        (self.target)(&info)?;
+        Ok(info)
+    }
+    /// see [`SwapchainFrameHandle::submit_command_buffer`]
+    pub fn submit_command_buffer(
+        self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        command_buffer: Arc<PrimaryAutoCommandBuffer>,
+    ) -> Result<DmatexSubmitInfo, SurfaceError> {
+        let info = self
+            .frame
+            .submit_command_buffer(dev, render_queue, command_buffer)?;
+        (self.target)(&info)?;
+        Ok(info)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SurfaceError {
+    #[error(transparent)]
+    Swapchain(#[from] SwapchainError),
+    #[error("failed to hand the frame to the surface's target: {0}")]
+    Target(#[from] NodeError),
+}
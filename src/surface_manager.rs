@@ -0,0 +1,127 @@
+//! Many swapchains rendered and submitted together
+
+use std::sync::Arc;
+
+use stardust_xr_fusion::drawable::DmatexSubmitInfo;
+use vulkano::{
+    Validated, VulkanError,
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    device::{Device, Queue, QueueGuard},
+    image::Image,
+    sync::semaphore::Semaphore,
+};
+
+use crate::swapchain::{Swapchain, SwapchainBuilder, SwapchainError, SwapchainFrameHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SurfaceId(u64);
+
+/// Owns the swapchains of all panels of a client, they share one command buffer allocator
+/// and are acquired and submitted as one frame
+pub struct SurfaceManager {
+    dev: Arc<Device>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    surfaces: Vec<(SurfaceId, Swapchain)>,
+    next_id: u64,
+}
+
+impl SurfaceManager {
+    pub fn new(dev: &Arc<Device>) -> Self {
+        Self {
+            dev: dev.clone(),
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            surfaces: Vec::new(),
+            next_id: 0,
+        }
+    }
+    pub fn add_surface(&mut self, builder: SwapchainBuilder) -> Result<SurfaceId, SwapchainError> {
+        let swapchain = builder
+            .command_buffer_allocator(&self.command_buffer_allocator)
+            .build()?;
+        let id = SurfaceId(self.next_id);
+        self.next_id += 1;
+        self.surfaces.push((id, swapchain));
+        Ok(id)
+    }
+    pub fn remove_surface(&mut self, id: SurfaceId) -> Option<Swapchain> {
+        let index = self.surfaces.iter().position(|(v, _)| *v == id)?;
+        Some(self.surfaces.remove(index).1)
+    }
+    pub fn surface(&self, id: SurfaceId) -> Option<&Swapchain> {
+        self.surfaces.iter().find(|(v, _)| *v == id).map(|(_, v)| v)
+    }
+    pub fn surfaces(&self) -> impl Iterator<Item = SurfaceId> + '_ {
+        self.surfaces.iter().map(|(id, _)| *id)
+    }
+    /// prepares the next image of every surface
+    pub fn begin_frame(&mut self) -> ManagedFrame<'_> {
+        self.begin_frame_filtered(|_| true)
+    }
+    /// prepares the next image of only the listed surfaces
+    pub fn begin_frame_for(&mut self, ids: &[SurfaceId]) -> ManagedFrame<'_> {
+        self.begin_frame_filtered(|id| ids.contains(&id))
+    }
+    fn begin_frame_filtered(&mut self, filter: impl Fn(SurfaceId) -> bool) -> ManagedFrame<'_> {
+        let frames = self
+            .surfaces
+            .iter_mut()
+            .filter(|(id, _)| filter(*id))
+            .map(|(id, swapchain)| (*id, swapchain.prepare_next_image()))
+            .collect();
+        ManagedFrame {
+            dev: self.dev.clone(),
+            frames,
+        }
+    }
+}
+
+/// The images of all surfaces rendered this frame
+pub struct ManagedFrame<'a> {
+    dev: Arc<Device>,
+    frames: Vec<(SurfaceId, SwapchainFrameHandle<'a>)>,
+}
+impl<'a> ManagedFrame<'a> {
+    pub fn surfaces(&self) -> impl Iterator<Item = SurfaceId> + '_ {
+        self.frames.iter().map(|(id, _)| *id)
+    }
+    pub fn image(&self, id: SurfaceId) -> Option<Arc<Image>> {
+        self.frame(id).map(SwapchainFrameHandle::image)
+    }
+    pub fn frame(&self, id: SurfaceId) -> Option<&SwapchainFrameHandle<'a>> {
+        self.frames.iter().find(|(v, _)| *v == id).map(|(_, v)| v)
+    }
+    /// waits until the server released every image of this frame, in one place instead of
+    /// stalling once per surface during submission
+    pub fn blocking_release_wait(&self) {
+        for (_, frame) in &self.frames {
+            frame.blocking_release_wait();
+        }
+    }
+    /// submits every surface, `submit` is called once per surface like in
+    /// [`SwapchainFrameHandle::submit`]. Stops at the first error
+    pub fn submit(
+        self,
+        render_queue: &Arc<Queue>,
+        mut submit: impl FnMut(
+            SurfaceId,
+            Arc<Semaphore>,
+            QueueGuard,
+            Arc<Semaphore>,
+        ) -> Result<(), Validated<VulkanError>>,
+    ) -> Result<Vec<(SurfaceId, DmatexSubmitInfo)>, SwapchainError> {
+        self.blocking_release_wait();
+        let dev = self.dev;
+        self.frames
+            .into_iter()
+            .map(|(id, frame)| {
+                let info = frame.submit(&dev, render_queue, |wait, guard, signal| {
+                    submit(id, wait, guard, signal)
+                })?;
+                Ok((id, info))
+            })
+            .collect()
+    }
+}
@@ -1,140 +1,2189 @@
-use std::{os::fd::AsFd, sync::Arc, time::Instant};
+use std::{
+    collections::VecDeque,
+    os::fd::OwnedFd,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use stardust_xr_fusion::{
-    ClientHandle,
     drawable::{DmatexSize, DmatexSubmitInfo},
+    node::NodeError,
 };
+use thiserror::Error;
+use tracing::warn;
 use vulkano::{
-    device::{Device, Queue, QueueGuard},
-    image::{Image, ImageUsage},
-    sync::semaphore::{
-        ExternalSemaphoreHandleType, ExternalSemaphoreHandleTypes, ImportSemaphoreFdInfo,
-        Semaphore, SemaphoreCreateInfo, SemaphoreImportFlags,
+    Validated, ValidationError, VulkanError,
+    command_buffer::{
+        BlitImageInfo, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferSubmitInfo,
+        CommandBufferUsage, PrimaryAutoCommandBuffer, SemaphoreSubmitInfo, SubmitInfo,
+        allocator::StandardCommandBufferAllocator,
+        sys::{CommandBuffer, RecordingCommandBuffer},
+    },
+    device::{Device, DeviceOwned, Queue, QueueGuard},
+    format::{Format, FormatFeatures},
+    image::{Image, ImageLayout, ImageUsage, sampler::Filter, view::ImageView},
+    sync::{
+        AccessFlags, DependencyInfo, ImageMemoryBarrier, PipelineStages,
+        QueueFamilyOwnershipTransfer, Sharing,
+        fence::{Fence, FenceCreateInfo},
+        semaphore::Semaphore,
+    },
+};
+
+#[cfg(feature = "udmabuf")]
+use crate::udmabuf::UdmabufAllocator;
+use crate::{
+    backend::Backend,
+    caps::{DeviceCapability, DeviceCaps},
+    debug::set_object_name,
+    device_watcher::{DeviceToken, DeviceWatcher},
+    diagnostics::{DiagnosticEvent, report},
+    dmatex::{
+        Dmatex, DmatexBuilder, DmatexImportError, SharedMemory, Srgbness, attach_to_timeline,
+        exportable_semaphore, timeline_wait_semaphore,
     },
+    format::DmatexFormat,
+    post_process::PostProcess,
+    profiler::GpuProfiler,
+    render_device::{RenderDevice, RenderDeviceCreationError},
+    sync::{SyncProviderFactory, unblock},
+    syncobj_eventfd::timeline_eventfd,
 };
 
-use crate::{dmatex::Dmatex, format::DmatexFormat, render_device::RenderDevice};
+/// A ring of dmatexes handed out round robin, each frame waits for the server to release
+/// its image and signals the point the server acquires it at.
+///
+/// Threading: the swapchain is `Send` and `Sync` when `T` is `Send`. Frames are handed out
+/// one at a time by [`Self::prepare_next_image`], or several at once through a shared
+/// reference by [`Self::prepare_next_image_shared`], up to one per image. A
+/// [`SwapchainFrameHandle`] is `Send` and carries the per-image data with it, so frames can
+/// be recorded and submitted on worker threads, in any order, or recorded on workers and
+/// submitted together on the thread owning the queue
+pub struct Swapchain<T = (), const IMAGES: usize = 3> {
+    images: [Arc<Dmatex>; IMAGES],
+    image_state: [Mutex<SwapchainImage<T>>; IMAGES],
+    /// how many images are actually used, the slots after them repeat the first image,
+    /// see [`SwapchainBuilder::min_images`]
+    image_count: usize,
+    next_image: AtomicUsize,
+    /// images replaced by placeholders while idle, see [`Swapchain::shrink`]
+    placeholders: [bool; IMAGES],
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    foreign_ownership_transfers: bool,
+    /// the layout frames render in and the one the server gets the image in
+    layout_transitions: Option<(ImageLayout, ImageLayout)>,
+    host_sync: bool,
+    max_frames_in_flight: Option<usize>,
+    tracking: FrameTracking,
+    profiler: Option<Mutex<GpuProfiler>>,
+    post_process: Option<Arc<PostProcess>>,
+    /// goes stale when the server switches gpus, see [`SwapchainBuilder::device_watcher`]
+    device_token: Option<DeviceToken>,
+    preserve_contents: bool,
+    /// the old contents copied over by the last resize, see [`Swapchain::take_preserved_frame`]
+    preserved_frame: Option<DmatexSubmitInfo>,
+    backpressure: Backpressure,
+    config: SwapchainConfig,
+    /// only called with `&mut self`, the mutex just makes the swapchain `Sync`
+    create_data: Mutex<Box<dyn FnMut(&Arc<Dmatex>) -> T + Send>>,
+}
+
+struct SwapchainImage<T> {
+    /// the release point of the last frame that was rendered into this image
+    release: u64,
+    /// None while a frame handle of this image holds it
+    data: Option<T>,
+}
+impl<T> SwapchainImage<T> {
+    fn new(release: u64, data: T) -> Mutex<Self> {
+        Mutex::new(Self {
+            release,
+            data: Some(data),
+        })
+    }
+}
+
+/// What submitted frames update, shared with the frame handles
+#[derive(Default)]
+struct FrameTracking {
+    /// images and acquire points of submitted frames, oldest first,
+    /// only tracked with a frames in flight limit
+    in_flight: Mutex<VecDeque<(Arc<Dmatex>, u64)>>,
+    /// the image and acquire point of the last submitted frame
+    last_presented: Mutex<Option<(Arc<Dmatex>, u64)>>,
+    /// what the gpu work of submitted frames uses, until their acquire points are signalled
+    retained: Mutex<VecDeque<RetainedFrame>>,
+}
+impl FrameTracking {
+    fn release_finished(&self) {
+        self.retained
+            .lock()
+            .unwrap()
+            .retain(|frame| !frame.is_done());
+    }
+}
+
+/// Objects a frame's raw submissions use, vulkan doesn't keep them alive on its own
+#[derive(Default)]
+struct FrameResources {
+    command_buffers: Vec<Arc<CommandBuffer>>,
+    /// the user's, they keep the resources they use alive in turn
+    user_command_buffers: Vec<Arc<PrimaryAutoCommandBuffer>>,
+    semaphores: Vec<Arc<Semaphore>>,
+    /// images read by the frame's work that the swapchain might drop, e.g. the source of a
+    /// preserving blit
+    images: Vec<Arc<Dmatex>>,
+}
+impl FrameResources {
+    /// Waits for `queues` to finish everything after a failed submission, nothing says how
+    /// far the gpu got with the parts that did get submitted
+    fn drop_after_idle(self, queues: &[&Arc<Queue>]) {
+        for queue in queues {
+            if let Err(err) = queue.with(|mut guard| guard.wait_idle()) {
+                warn!("failed to wait for the queue after a failed submission: {err}");
+            }
+        }
+    }
+}
 
-pub struct Swapchain<const IMAGES: usize = 3> {
-    images: [(Arc<Dmatex>, u64); IMAGES],
-    next_image: usize,
+/// The resources of a submitted frame, dropped once its acquire point is signalled since
+/// every submission of the frame comes before the signal
+struct RetainedFrame {
+    dmatex: Arc<Dmatex>,
+    acquire_point: u64,
+    resources: FrameResources,
+}
+impl RetainedFrame {
+    fn is_done(&self) -> bool {
+        self.dmatex
+            .timeline
+            .blocking_wait(self.acquire_point, Some(Instant::now()))
+            .is_ok()
+    }
+}
+
+/// Everything needed to create the images again, e.g. on resize
+struct SwapchainConfig {
+    client: Arc<dyn Backend>,
+    dev: Arc<Device>,
+    size: DmatexSize,
+    format: DmatexFormat,
+    array_layers: Option<u32>,
+    usage: ImageUsage,
+    name: Option<String>,
+    clear_queue: Option<Arc<Queue>>,
+    initial_clear: bool,
+    concurrent_sharing: Option<Vec<u32>>,
+    disjoint: bool,
+    sync: Option<SyncProviderFactory>,
+    srgbness: Srgbness,
+    shared_memory: bool,
+    row_pitch_alignment: Option<u64>,
+    /// None needs every image, see [`SwapchainBuilder::min_images`]
+    min_images: Option<usize>,
+    /// what the current images are suballocated from, with [`SwapchainBuilder::shared_memory`]
+    memory_pool: Option<Arc<SharedMemory>>,
+    /// the render node the images were last created for, to expand a shrunk swapchain again
+    render_node_id: u64,
+    #[cfg(feature = "udmabuf")]
+    udmabuf: Option<Arc<UdmabufAllocator>>,
+}
+impl SwapchainConfig {
+    fn create_image(
+        &self,
+        render_dev: &RenderDevice,
+        index: usize,
+    ) -> Result<Arc<Dmatex>, SwapchainError> {
+        #[cfg(feature = "udmabuf")]
+        if let Some(allocator) = &self.udmabuf {
+            let vk_format = self.format.vk_format();
+            let fourcc = self
+                .format
+                .drm_fourcc()
+                .ok_or(SwapchainError::UdmabufFormat(vk_format))?;
+            let srgb = self
+                .srgbness
+                .resolve(vk_format)
+                .ok_or(DmatexImportError::NoSrgbVariant(vk_format))?;
+            let dmabuf = allocator
+                .allocate_image(&self.size, fourcc, srgb)
+                .ok_or(SwapchainError::UdmabufFormat(vk_format))?
+                .map_err(|err| match err {
+                    rustix::io::Errno::NOMEM => DmatexImportError::OutOfMemory,
+                    err => DmatexImportError::Udmabuf(err),
+                })?;
+            let timeline = self
+                .sync
+                .as_ref()
+                .map(|factory| factory(render_dev))
+                .transpose()
+                .map_err(DmatexImportError::Timeline)?;
+            let image = Arc::new(Dmatex::import_dyn(
+                self.client.clone(),
+                &self.dev,
+                render_dev,
+                dmabuf,
+                timeline,
+                self.usage,
+            )?);
+            unsafe { image.timeline.signal(0) }.map_err(DmatexImportError::Timeline)?;
+            return Ok(image);
+        }
+        let mut builder = DmatexBuilder::new(
+            self.client.clone(),
+            &self.dev,
+            render_dev,
+            self.size.clone(),
+            &self.format,
+            self.usage,
+        );
+        if let Some(array_layers) = self.array_layers {
+            builder = builder.array_layers(array_layers);
+        }
+        if let Some(name) = &self.name {
+            builder = builder.name(format!("{name} [{index}]"));
+        }
+        if let Some(queue) = &self.clear_queue {
+            builder = builder.clear_queue(queue);
+        }
+        builder = builder.initial_clear(self.initial_clear);
+        if let Some(queue_family_indices) = &self.concurrent_sharing {
+            builder = builder.concurrent_sharing(queue_family_indices.iter().copied());
+        }
+        builder = builder.disjoint(self.disjoint);
+        if let Some(factory) = &self.sync {
+            builder = builder.sync_provider(factory.clone());
+        }
+        builder = builder.srgbness(self.srgbness);
+        if let Some(memory) = &self.memory_pool {
+            builder = builder.shared_memory(memory);
+        }
+        if let Some(alignment) = self.row_pitch_alignment {
+            builder = builder.row_pitch_alignment(alignment);
+        }
+        let cleared = builder.clears();
+        let image = Arc::new(builder.try_build()?);
+        // a cleared image gets point 0 signalled by the clear
+        if !cleared {
+            unsafe { image.timeline.signal(0) }.map_err(DmatexImportError::Timeline)?;
+        }
+        Ok(image)
+    }
+    /// Creates every image, or as many as [`SwapchainBuilder::min_images`] allows when
+    /// memory runs out
+    fn create_images<T, const IMAGES: usize>(
+        &mut self,
+        render_dev: &RenderDevice,
+        create_data: &mut dyn FnMut(&Arc<Dmatex>) -> T,
+    ) -> Result<SwapchainImages<T, IMAGES>, SwapchainError> {
+        // the old allocation stays alive until the old images are dropped
+        self.memory_pool = self.shared_memory.then(|| SharedMemory::new(IMAGES));
+        let min_images = self.min_images.unwrap_or(IMAGES).clamp(1, IMAGES);
+        let mut created = Vec::with_capacity(IMAGES);
+        for index in 0..IMAGES {
+            match self.create_image(render_dev, index) {
+                Ok(dmatex) => created.push(dmatex),
+                Err(SwapchainError::Image(DmatexImportError::OutOfMemory))
+                    if index >= min_images =>
+                {
+                    report(DiagnosticEvent::FewerSwapchainImages {
+                        requested: IMAGES,
+                        created: index,
+                    });
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let image_count = created.len();
+        let images = std::array::from_fn(|index| created.get(index).unwrap_or(&created[0]).clone());
+        let state = images
+            .each_ref()
+            .map(|dmatex| SwapchainImage::new(0, create_data(dmatex)));
+        self.render_node_id = render_dev.drm_node_id();
+        Ok((images, state, image_count))
+    }
 }
 
+/// the images with their state and how many of them are actually used
+type SwapchainImages<T, const IMAGES: usize> = (
+    [Arc<Dmatex>; IMAGES],
+    [Mutex<SwapchainImage<T>>; IMAGES],
+    usize,
+);
+
 impl Swapchain {
-    pub fn new(
-        client: &Arc<ClientHandle>,
+    pub fn new<B: Backend>(
+        client: &Arc<B>,
         dev: &Arc<Device>,
         render_dev: &RenderDevice,
         size: DmatexSize,
         format: &DmatexFormat,
         array_layers: Option<u32>,
         usage: ImageUsage,
+    ) -> Result<Self, SwapchainError> {
+        let mut builder = Self::builder(client, dev, render_dev, size, format, usage);
+        builder.array_layers = array_layers;
+        builder.build()
+    }
+    pub fn builder<'a, B: Backend>(
+        client: &Arc<B>,
+        dev: &'a Arc<Device>,
+        render_dev: &'a RenderDevice,
+        size: DmatexSize,
+        format: &'a DmatexFormat,
+        usage: ImageUsage,
+    ) -> SwapchainBuilder<'a> {
+        SwapchainBuilder {
+            client: client.clone(),
+            dev,
+            render_dev,
+            size,
+            format,
+            array_layers: None,
+            usage,
+            name: None,
+            clear_queue: None,
+            initial_clear: true,
+            concurrent_sharing: None,
+            disjoint: false,
+            sync: None,
+            srgbness: Srgbness::Auto,
+            shared_memory: false,
+            row_pitch_alignment: None,
+            min_images: None,
+            foreign_ownership_transfers: false,
+            layout_transitions: None,
+            host_sync: false,
+            #[cfg(feature = "udmabuf")]
+            udmabuf: None,
+            max_frames_in_flight: None,
+            gpu_profiling: false,
+            post_process: None,
+            device_token: None,
+            preserve_contents: false,
+            backpressure_threshold: DEFAULT_BACKPRESSURE_THRESHOLD,
+            on_backpressure: None,
+            command_buffer_allocator: None,
+        }
+    }
+}
+impl<T> Swapchain<T> {
+    /// Like [`Swapchain::new`], with `create_data` called for every image to create the
+    /// per-image state handed out by [`SwapchainFrameHandle::data`], again whenever the
+    /// images get recreated
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        size: DmatexSize,
+        format: &DmatexFormat,
+        array_layers: Option<u32>,
+        usage: ImageUsage,
+        create_data: impl FnMut(&Arc<Dmatex>) -> T + Send + 'static,
+    ) -> Result<Self, SwapchainError> {
+        let mut builder = Swapchain::builder(client, dev, render_dev, size, format, usage);
+        builder.array_layers = array_layers;
+        builder.build_with(create_data)
+    }
+}
+
+pub struct SwapchainBuilder<'a> {
+    client: Arc<dyn Backend>,
+    dev: &'a Arc<Device>,
+    render_dev: &'a RenderDevice,
+    size: DmatexSize,
+    format: &'a DmatexFormat,
+    array_layers: Option<u32>,
+    usage: ImageUsage,
+    name: Option<String>,
+    clear_queue: Option<&'a Arc<Queue>>,
+    initial_clear: bool,
+    concurrent_sharing: Option<Vec<u32>>,
+    disjoint: bool,
+    sync: Option<SyncProviderFactory>,
+    srgbness: Srgbness,
+    shared_memory: bool,
+    row_pitch_alignment: Option<u64>,
+    min_images: Option<usize>,
+    foreign_ownership_transfers: bool,
+    layout_transitions: Option<(ImageLayout, ImageLayout)>,
+    host_sync: bool,
+    #[cfg(feature = "udmabuf")]
+    udmabuf: Option<Arc<UdmabufAllocator>>,
+    max_frames_in_flight: Option<usize>,
+    gpu_profiling: bool,
+    post_process: Option<Arc<PostProcess>>,
+    device_token: Option<DeviceToken>,
+    preserve_contents: bool,
+    backpressure_threshold: Duration,
+    on_backpressure: Option<Box<dyn FnMut(Duration) + Send>>,
+    command_buffer_allocator: Option<Arc<StandardCommandBufferAllocator>>,
+}
+impl<'a> SwapchainBuilder<'a> {
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = Some(array_layers);
+        self
+    }
+    /// Debug label for the swapchain, each image is named `"{name} [{index}]"`,
+    /// see [`DmatexBuilder::name`](crate::dmatex::DmatexBuilder::name)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    /// see [`DmatexBuilder::clear_queue`]
+    pub fn clear_queue(mut self, queue: &'a Arc<Queue>) -> Self {
+        self.clear_queue = Some(queue);
+        self
+    }
+    /// see [`DmatexBuilder::initial_clear`]
+    pub fn initial_clear(mut self, enabled: bool) -> Self {
+        self.initial_clear = enabled;
+        self
+    }
+    /// see [`DmatexBuilder::concurrent_sharing`]
+    pub fn concurrent_sharing(
+        mut self,
+        queue_family_indices: impl IntoIterator<Item = u32>,
     ) -> Self {
-        let images = [(); _]
-            .map(|_| {
-                Arc::new(Dmatex::new(
-                    client,
-                    dev,
-                    render_dev,
-                    size.clone(),
-                    format,
-                    array_layers,
-                    usage,
+        self.concurrent_sharing = Some(queue_family_indices.into_iter().collect());
+        self
+    }
+    /// see [`DmatexBuilder::disjoint`]
+    pub fn disjoint(mut self, enabled: bool) -> Self {
+        self.disjoint = enabled;
+        self
+    }
+    /// see [`DmatexBuilder::sync_provider`], used for every image
+    pub fn sync_provider(mut self, factory: SyncProviderFactory) -> Self {
+        self.sync = Some(factory);
+        self
+    }
+    /// see [`DmatexBuilder::srgbness`]
+    pub fn srgbness(mut self, srgbness: Srgbness) -> Self {
+        self.srgbness = srgbness;
+        self
+    }
+    /// Suballocates all images from a single exported allocation instead of giving each its
+    /// own, the server gets the planes at each image's offset into it. A resize then
+    /// allocates once instead of once per image while the old images are still alive.
+    /// Ignored for disjoint images and drivers that need dedicated allocations, adopted
+    /// images only use it once they're recreated
+    pub fn shared_memory(mut self, enabled: bool) -> Self {
+        self.shared_memory = enabled;
+        self
+    }
+    /// see [`DmatexBuilder::row_pitch_alignment`]
+    pub fn row_pitch_alignment(mut self, alignment: u64) -> Self {
+        self.row_pitch_alignment = Some(alignment);
+        self
+    }
+    /// Gets by with as few as `min` images when allocating the others runs out of memory,
+    /// e.g. for large panels on integrated gpus, instead of panicking. Fewer images mean
+    /// more waiting for the server, a [`DiagnosticEvent::FewerSwapchainImages`] is reported.
+    /// The same applies when the images are recreated on resize
+    pub fn min_images(mut self, min: usize) -> Self {
+        self.min_images = Some(min);
+        self
+    }
+    /// Wraps every submission in queue family ownership transfers from and back to
+    /// `VK_QUEUE_FAMILY_FOREIGN_EXT`, your work sees the image in `GENERAL` layout.
+    /// Needed for a clean synchronization validation report, see [`crate::debug::enable_validation`]
+    pub fn foreign_ownership_transfers(mut self, enabled: bool) -> Self {
+        self.foreign_ownership_transfers = enabled;
+        self
+    }
+    /// Transitions the image into `layout` before your work and into `final_layout` after it,
+    /// e.g. `ColorAttachmentOptimal` and `General`, so your command buffers don't have to.
+    /// The first frame of every image starts from `Undefined`
+    pub fn layout_transitions(mut self, layout: ImageLayout, final_layout: ImageLayout) -> Self {
+        self.layout_transitions = Some((layout, final_layout));
+        self
+    }
+    /// Synchronizes with the server on the cpu instead of through sync files, for drivers
+    /// that can't import or export them like lavapipe. [`SwapchainFrameHandle::submit`] blocks
+    /// until rendering finished. Always on for devices without [sync fd
+    /// support](DeviceCaps::gpu_sync)
+    pub fn host_sync(mut self, enabled: bool) -> Self {
+        self.host_sync = enabled;
+        self
+    }
+    /// Allocates the images from udmabuf instead of exporting them from vulkan, see
+    /// [`crate::udmabuf`]. Only linear single plane formats are supported, the initial clear
+    /// is skipped
+    #[cfg(feature = "udmabuf")]
+    pub fn udmabuf(mut self, allocator: &Arc<UdmabufAllocator>) -> Self {
+        self.udmabuf = Some(allocator.clone());
+        self
+    }
+    /// Limits how many submitted frames may still be rendering on the gpu,
+    /// [`Swapchain::prepare_next_image`] blocks until the oldest one finished.
+    /// Bounds latency independently of how fast the server releases images
+    pub fn max_frames_in_flight(mut self, frames: usize) -> Self {
+        self.max_frames_in_flight = Some(frames.max(1));
+        self
+    }
+    /// Measures the gpu time of every submission with timestamp queries, see
+    /// [`Swapchain::gpu_profiler`]. Disabled by default
+    pub fn gpu_profiling(mut self, enabled: bool) -> Self {
+        self.gpu_profiling = enabled;
+        self
+    }
+    /// Runs `pass` over every frame after your work, frames can override it with
+    /// [`SwapchainFrameHandle::set_post_process`]
+    pub fn post_process(mut self, pass: &Arc<PostProcess>) -> Self {
+        self.post_process = Some(pass.clone());
+        self
+    }
+    /// Tracks whether the server switched gpus since the swapchain was created, see
+    /// [`Swapchain::device_changed`]
+    pub fn device_watcher(mut self, watcher: &DeviceWatcher) -> Self {
+        self.device_token = Some(watcher.token());
+        self
+    }
+    /// Copies the last submitted frame, scaled, into the first image after a
+    /// [resize](Swapchain::resize), so the panel keeps showing it instead of flashing until
+    /// the next frame is rendered, see [`Swapchain::take_preserved_frame`]. Needs the
+    /// [clear queue](Self::clear_queue) to blit on and a format and modifier that support
+    /// linear blits, does nothing with [foreign ownership
+    /// transfers](Self::foreign_ownership_transfers)
+    pub fn preserve_contents(mut self, enabled: bool) -> Self {
+        self.preserve_contents = enabled;
+        self
+    }
+    /// How long waiting for the server to release an image may take before the frame counts
+    /// as stalled in [`Swapchain::backpressure_stats`], 4ms by default
+    pub fn backpressure_threshold(mut self, threshold: Duration) -> Self {
+        self.backpressure_threshold = threshold;
+        self
+    }
+    /// Called with the wait duration whenever a frame waited longer than the
+    /// [threshold](Self::backpressure_threshold) for the server, e.g. to lower the resolution
+    /// or frame rate. Runs on the thread that waited
+    pub fn on_backpressure(mut self, callback: impl FnMut(Duration) + Send + 'static) -> Self {
+        self.on_backpressure = Some(Box::new(callback));
+        self
+    }
+    /// allocator for the command buffers the swapchain records internally,
+    /// lets several swapchains share one
+    pub fn command_buffer_allocator(
+        mut self,
+        allocator: &Arc<StandardCommandBufferAllocator>,
+    ) -> Self {
+        self.command_buffer_allocator = Some(allocator.clone());
+        self
+    }
+    /// Fails if an image can't be created, running out of memory only fails once fewer
+    /// than [`Self::min_images`] could be
+    pub fn build(self) -> Result<Swapchain, SwapchainError> {
+        self.build_with(|_| ())
+    }
+    /// builds a swapchain with per-image state, see [`Swapchain::new_with`]
+    pub fn build_with<T>(
+        self,
+        create_data: impl FnMut(&Arc<Dmatex>) -> T + Send + 'static,
+    ) -> Result<Swapchain<T>, SwapchainError> {
+        let mut create_data: Box<dyn FnMut(&Arc<Dmatex>) -> T + Send> = Box::new(create_data);
+        let mut config = self.config();
+        let images = config.create_images(self.render_dev, &mut create_data)?;
+        Ok(self.finish(config, images, create_data))
+    }
+    /// Takes over existing dmatexes instead of creating new ones, e.g. ones handed over by
+    /// [`Swapchain::into_images`] of another producer. Each image comes with the timeline
+    /// point after which it's free to render into, its first frame acquires the point after.
+    /// The dmatexes should match the size and format given to the builder, which are used
+    /// when the images get recreated
+    pub fn adopt(self, images: [(Arc<Dmatex>, u64); 3]) -> Swapchain {
+        self.adopt_with(images, |_| ())
+    }
+    /// like [`Self::adopt`] with per-image state, see [`Swapchain::new_with`]
+    pub fn adopt_with<T>(
+        self,
+        images: [(Arc<Dmatex>, u64); 3],
+        mut create_data: impl FnMut(&Arc<Dmatex>) -> T + Send + 'static,
+    ) -> Swapchain<T> {
+        let config = self.config();
+        let state = images
+            .each_ref()
+            .map(|(dmatex, release)| SwapchainImage::new(*release, create_data(dmatex)));
+        let images = (images.map(|(dmatex, _)| dmatex), state, 3);
+        self.finish(config, images, Box::new(create_data))
+    }
+    /// what the images get created from
+    fn config(&self) -> SwapchainConfig {
+        SwapchainConfig {
+            client: self.client.clone(),
+            dev: self.dev.clone(),
+            size: self.size,
+            format: self.format.clone(),
+            array_layers: self.array_layers,
+            // the blit reads the old images and writes the new ones
+            usage: if self.preserve_contents {
+                self.usage | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST
+            } else {
+                self.usage
+            },
+            name: self.name.clone(),
+            clear_queue: self.clear_queue.cloned(),
+            initial_clear: self.initial_clear,
+            concurrent_sharing: self.concurrent_sharing.clone(),
+            disjoint: self.disjoint,
+            sync: self.sync.clone(),
+            srgbness: self.srgbness,
+            shared_memory: self.shared_memory,
+            row_pitch_alignment: self.row_pitch_alignment,
+            min_images: self.min_images,
+            memory_pool: None,
+            render_node_id: self.render_dev.drm_node_id(),
+            #[cfg(feature = "udmabuf")]
+            udmabuf: self.udmabuf.clone(),
+        }
+    }
+    fn finish<T>(
+        self,
+        config: SwapchainConfig,
+        (images, image_state, image_count): SwapchainImages<T, 3>,
+        create_data: Box<dyn FnMut(&Arc<Dmatex>) -> T + Send>,
+    ) -> Swapchain<T> {
+        let caps = DeviceCaps::new(self.dev);
+        if !self.host_sync {
+            DeviceCaps::check(caps.sync_fd_import, DeviceCapability::SyncFdImport);
+            DeviceCaps::check(caps.sync_fd_export, DeviceCapability::SyncFdExport);
+        }
+        Swapchain {
+            images,
+            image_state,
+            image_count,
+            next_image: AtomicUsize::new(0),
+            placeholders: [false; 3],
+            command_buffer_allocator: self.command_buffer_allocator.unwrap_or_else(|| {
+                Arc::new(StandardCommandBufferAllocator::new(
+                    self.dev.clone(),
+                    Default::default(),
                 ))
-            })
-            .map(|v| (v, 0));
-        for image in &images {
-            unsafe {
-                image.0.timeline.signal(0).unwrap();
+            }),
+            foreign_ownership_transfers: self.foreign_ownership_transfers,
+            layout_transitions: self.layout_transitions,
+            host_sync: self.host_sync || !caps.gpu_sync(),
+            max_frames_in_flight: self.max_frames_in_flight,
+            tracking: FrameTracking::default(),
+            profiler: self
+                .gpu_profiling
+                .then(|| Mutex::new(GpuProfiler::new(self.dev))),
+            post_process: self.post_process,
+            device_token: self.device_token,
+            preserve_contents: self.preserve_contents,
+            preserved_frame: None,
+            backpressure: Backpressure {
+                threshold: self.backpressure_threshold,
+                stalled_frames: AtomicU64::new(0),
+                longest_wait_nanos: AtomicU64::new(0),
+                callback: Mutex::new(self.on_backpressure),
+            },
+            config,
+            create_data: Mutex::new(create_data),
+        }
+    }
+}
+
+impl<T, const IMAGES: usize> Swapchain<T, IMAGES> {
+    /// Hands out the next image, blocks first if the
+    /// [frames in flight limit](SwapchainBuilder::max_frames_in_flight) is reached. A
+    /// [shrunk](Self::shrink) swapchain is expanded first, panics if that or waiting for a
+    /// frame in flight fails
+    pub fn prepare_next_image(&mut self) -> SwapchainFrameHandle<'_, T> {
+        self.try_prepare_next_image()
+            .unwrap_or_else(|err| panic!("failed to prepare the next image: {err}"))
+    }
+    /// Like [`Self::prepare_next_image`], but returns the error if expanding a shrunk
+    /// swapchain or waiting for a frame in flight fails
+    pub fn try_prepare_next_image(
+        &mut self,
+    ) -> Result<SwapchainFrameHandle<'_, T>, SwapchainError> {
+        if self.is_shrunk() {
+            let render_dev = RenderDevice::from_node_id(self.config.render_node_id)?;
+            self.expand(&render_dev)?;
+        }
+        self.prepare_next_image_shared()
+    }
+    /// Like [`Self::try_prepare_next_image`] through a shared reference, so several frames can
+    /// be handed out at once, e.g. to record them on different threads. Each image can only be
+    /// handed out to one frame at a time, images that are already out are skipped and
+    /// [`SwapchainError::AllImagesHandedOut`] is returned once every [image](Self::images) is
+    /// outstanding. A shrunk swapchain has to be [expanded](Self::expand) before
+    pub fn prepare_next_image_shared(&self) -> Result<SwapchainFrameHandle<'_, T>, SwapchainError> {
+        if let Some(limit) = self.max_frames_in_flight {
+            loop {
+                let mut in_flight = self.tracking.in_flight.lock().unwrap();
+                if in_flight.len() < limit {
+                    break;
+                }
+                let (dmatex, point) = in_flight.pop_front().unwrap();
+                // other frames can be submitted in the meantime
+                drop(in_flight);
+                if let Err(err) = dmatex.timeline.blocking_wait(point, None) {
+                    // it's still in flight for the next try
+                    self.tracking
+                        .in_flight
+                        .lock()
+                        .unwrap()
+                        .push_front((dmatex, point));
+                    return Err(SwapchainError::Syncobj(err));
+                }
             }
         }
-        Self {
-            images,
-            next_image: 0,
-        }
-    }
-    pub fn prepare_next_image(&mut self) -> SwapchainFrameHandle {
-        let images_len = self.images.len();
-        let (image, previous_release) = &mut self.images[self.next_image];
-        self.next_image += 1;
-        self.next_image %= images_len;
-        let acquire_point = *previous_release + 1;
-        let previous_server_release = *previous_release;
-        *previous_release = acquire_point + 1;
-        SwapchainFrameHandle {
+        if let Some(profiler) = &self.profiler {
+            profiler.lock().unwrap().collect();
+        }
+        self.tracking.release_finished();
+        let (index, mut image, data) = (0..self.image_count)
+            .find_map(|_| {
+                let index = self
+                    .next_image
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |index| {
+                        Some((index + 1) % self.image_count)
+                    })
+                    .unwrap();
+                let mut image = self.image_state[index].lock().unwrap();
+                let data = image.data.take()?;
+                Some((index, image, data))
+            })
+            .ok_or(SwapchainError::AllImagesHandedOut)?;
+        debug_assert!(
+            !self.placeholders[index],
+            "call Swapchain::expand before handing out shared frames of a shrunk swapchain"
+        );
+        let slot = &self.image_state[index];
+        let acquire_point = image.release + 1;
+        let previous_server_release = image.release;
+        image.release = acquire_point + 1;
+        Ok(SwapchainFrameHandle {
             previous_server_release,
             server_acquire: acquire_point,
-            next_server_release: *previous_release,
-            image: image.clone(),
+            next_server_release: image.release,
+            index,
+            image: self.images[index].clone(),
+            data: Some(data),
+            slot,
+            tracking: &self.tracking,
+            track_in_flight: self.max_frames_in_flight.is_some(),
+            resources: FrameResources::default(),
+            profiler: self.profiler.as_ref(),
+            post_process: self.post_process.clone(),
+            backpressure: &self.backpressure,
+            command_buffer_allocator: self.command_buffer_allocator.clone(),
+            foreign_ownership_transfers: self.foreign_ownership_transfers,
+            layout_transitions: self.layout_transitions,
+            host_sync: self.host_sync,
+            acquire_signalled: false,
+        })
+    }
+    /// how many submitted frames might still be rendering, always 0 without a
+    /// [frames in flight limit](SwapchainBuilder::max_frames_in_flight)
+    pub fn frames_in_flight(&self) -> usize {
+        let mut in_flight = self.tracking.in_flight.lock().unwrap();
+        // completed frames from the front don't have to be waited on anymore
+        while let Some((dmatex, point)) = in_flight.front()
+            && dmatex
+                .timeline
+                .blocking_wait(*point, Some(Instant::now()))
+                .is_ok()
+        {
+            in_flight.pop_front();
+        }
+        self.tracking.release_finished();
+        in_flight.len()
+    }
+    /// frames that waited long for the server since creation or the last reset
+    pub fn backpressure_stats(&self) -> BackpressureStats {
+        BackpressureStats {
+            stalled_frames: self.backpressure.stalled_frames.load(Ordering::Relaxed),
+            longest_wait: Duration::from_nanos(
+                self.backpressure.longest_wait_nanos.load(Ordering::Relaxed),
+            ),
+        }
+    }
+    pub fn reset_backpressure_stats(&self) {
+        self.backpressure.stalled_frames.store(0, Ordering::Relaxed);
+        self.backpressure
+            .longest_wait_nanos
+            .store(0, Ordering::Relaxed);
+    }
+    /// timings of submitted frames, if [enabled](SwapchainBuilder::gpu_profiling). Frames
+    /// can't be submitted while the profiler is borrowed
+    pub fn gpu_profiler(&self) -> Option<MutexGuard<'_, GpuProfiler>> {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.lock().unwrap())
+    }
+    /// All images in the order they're handed out, indexed by [`SwapchainFrameHandle::index`],
+    /// e.g. for recording command buffers once per image. They change on resize, and there
+    /// can be fewer than `IMAGES` with [`SwapchainBuilder::min_images`]
+    pub fn images(&self) -> &[Arc<Dmatex>] {
+        &self.images[..self.image_count]
+    }
+    /// Hands the images over to another producer, together with the timeline point after
+    /// which each one is free, see [`SwapchainBuilder::adopt`]. With fewer
+    /// [images](Self::images) than `IMAGES` the extra entries repeat the first one
+    pub fn into_images(self) -> [(Arc<Dmatex>, u64); IMAGES] {
+        let mut state = self.image_state.into_iter();
+        self.images
+            .map(|dmatex| (dmatex, state.next().unwrap().into_inner().unwrap().release))
+    }
+    /// Tears the swapchain down without freeing memory the server still reads: waits for the
+    /// release point of every image and frame in flight, unregisters the dmatexes and only
+    /// then drops them. The server keeps the frame it shows until it's replaced, so stop
+    /// showing the swapchain first, e.g. by removing the model or panel using it
+    pub async fn shutdown(self) -> Result<(), ShutdownError> {
+        let mut state = self.image_state.iter();
+        let mut waits = self
+            .images()
+            .iter()
+            .map(|dmatex| {
+                (
+                    dmatex.clone(),
+                    state.next().unwrap().lock().unwrap().release,
+                )
+            })
+            .collect::<Vec<_>>();
+        // images replaced by a resize can still have frames in flight
+        for (dmatex, acquire_point) in self.tracking.in_flight.lock().unwrap().iter() {
+            if !waits.iter().any(|(v, _)| Arc::ptr_eq(v, dmatex)) {
+                waits.push((dmatex.clone(), acquire_point + 1));
+            }
+        }
+        let dmatexes = unblock(move || {
+            for (dmatex, release_point) in &waits {
+                dmatex.timeline.blocking_wait(*release_point, None)?;
+            }
+            Ok::<_, WaitError>(waits)
+        })
+        .await?;
+        for (dmatex, _) in &dmatexes {
+            dmatex.unregister()?;
+        }
+        drop(self);
+        drop(dmatexes);
+        Ok(())
+    }
+    pub fn size(&self) -> &DmatexSize {
+        &self.config.size
+    }
+    /// Recreates all images and their per-image state with a new size. Frames that are
+    /// still in flight keep the old images alive until they're done. The old images and
+    /// size are kept if this fails
+    pub fn resize(
+        &mut self,
+        render_dev: &RenderDevice,
+        size: DmatexSize,
+    ) -> Result<(), SwapchainError> {
+        let old_size = std::mem::replace(&mut self.config.size, size);
+        let images = self
+            .config
+            .create_images(render_dev, self.create_data.get_mut().unwrap())
+            .inspect_err(|_| self.config.size = old_size)?;
+        (self.images, self.image_state, self.image_count) = images;
+        *self.next_image.get_mut() = 0;
+        self.placeholders = [false; IMAGES];
+        self.preserved_frame = None;
+        let last_presented = self.tracking.last_presented.get_mut().unwrap().take();
+        if self.preserve_contents
+            && !self.foreign_ownership_transfers
+            && let Some((old, acquire_point)) = last_presented
+        {
+            self.preserve(&old, acquire_point);
         }
+        Ok(())
+    }
+    /// The frame a [content preserving](SwapchainBuilder::preserve_contents) resize copied
+    /// the old contents into, submit it to the server like a rendered frame right after
+    /// the resize, None if nothing was copied
+    pub fn take_preserved_frame(&mut self) -> Option<DmatexSubmitInfo> {
+        self.preserved_frame.take()
+    }
+    /// blits `old` into the first image and makes it the first frame of the new images
+    fn preserve(&mut self, old: &Arc<Dmatex>, acquire_point: u64) {
+        let Some(queue) = self.config.clear_queue.clone() else {
+            return;
+        };
+        // the old image can't be read on another device, e.g. after a gpu switch
+        if old.image.device() != &self.config.dev {
+            return;
+        }
+        let new = self.images[0].clone();
+        if !supports_linear_blit(&old.image) || !supports_linear_blit(&new.image) {
+            return;
+        }
+        let layout = self
+            .layout_transitions
+            .map_or(ImageLayout::General, |(_, final_layout)| final_layout);
+        let blit = record_blit(
+            &self.command_buffer_allocator,
+            queue.queue_family_index(),
+            &old.image,
+            &new.image,
+            layout,
+        );
+        let mut resources = FrameResources {
+            command_buffers: vec![blit.clone()],
+            ..Default::default()
+        };
+        // the copy becomes the frame at acquire point 1, like a rendered one
+        let result = if self.host_sync {
+            old.timeline
+                .blocking_wait(acquire_point, None)
+                .map_err(SwapchainError::Syncobj)
+                .and_then(|()| {
+                    let fence = Arc::new(
+                        Fence::new(self.config.dev.clone(), FenceCreateInfo::default()).unwrap(),
+                    );
+                    queue.with(|mut guard| unsafe {
+                        guard.submit(
+                            &[SubmitInfo {
+                                command_buffers: vec![CommandBufferSubmitInfo::new(blit)],
+                                ..Default::default()
+                            }],
+                            Some(&fence),
+                        )
+                    })?;
+                    fence.wait(None)?;
+                    unsafe { new.timeline.signal(1) }.map_err(SwapchainError::Syncobj)
+                })
+        } else {
+            let dev = &self.config.dev;
+            let wait = timeline_wait_semaphore(dev, &old.timeline, acquire_point);
+            let signal = exportable_semaphore(dev);
+            // the raw command buffer doesn't keep the old image alive
+            resources.images.push(old.clone());
+            resources.semaphores.extend([wait.clone(), signal.clone()]);
+            queue
+                .with(|mut guard| {
+                    submit_command_buffers(&mut guard, [blit], Some(wait), Some(signal.clone()))
+                })
+                .map(|()| attach_to_timeline(&new.timeline, &signal, 1))
+        };
+        if let Err(err) = result {
+            warn!("failed to preserve the swapchain's contents: {err}");
+            resources.drop_after_idle(&[&queue]);
+            return;
+        }
+        self.tracking
+            .retained
+            .get_mut()
+            .unwrap()
+            .push_back(RetainedFrame {
+                dmatex: new.clone(),
+                acquire_point: 1,
+                resources,
+            });
+        self.image_state[0].get_mut().unwrap().release = 2;
+        *self.next_image.get_mut() = 1 % self.image_count;
+        *self.tracking.last_presented.get_mut().unwrap() = Some((new.clone(), 1));
+        self.preserved_frame = Some(DmatexSubmitInfo {
+            dmatex_id: new.dmatex_id,
+            acquire_point: 1,
+            release_point: 2,
+        });
+    }
+    /// Replaces images with tiny placeholders to free their memory while nothing renders, e.g.
+    /// for clock widgets that redraw once a minute. The next [`Self::prepare_next_image`]
+    /// expands it again on the same render device, [`Self::expand`] does that ahead of time
+    /// and has to be called before [`Self::prepare_next_image_shared`]. Frames in flight keep
+    /// their images alive until they're done. With
+    /// [shared memory](SwapchainBuilder::shared_memory) only [`IdleReclaim::All`] frees
+    /// memory, the kept image holds on to the whole allocation. Images replaced before an
+    /// error stay placeholders
+    pub fn shrink(
+        &mut self,
+        render_dev: &RenderDevice,
+        reclaim: IdleReclaim,
+    ) -> Result<(), SwapchainError> {
+        let next_image = *self.next_image.get_mut();
+        let last = (next_image + self.image_count - 1) % self.image_count;
+        let size = std::mem::replace(&mut self.config.size, placeholder_size(&self.config.size));
+        // placeholders get their own tiny allocations
+        let memory_pool = self.config.memory_pool.take();
+        let mut result = Ok(());
+        for index in 0..self.image_count {
+            if self.placeholders[index] || (reclaim == IdleReclaim::KeepLast && index == last) {
+                continue;
+            }
+            if let Err(err) = self.replace_image(render_dev, index) {
+                result = Err(err);
+                break;
+            }
+            self.placeholders[index] = true;
+        }
+        self.config.size = size;
+        self.config.memory_pool = match reclaim {
+            IdleReclaim::KeepLast => memory_pool,
+            IdleReclaim::All => self.config.shared_memory.then(|| SharedMemory::new(IMAGES)),
+        };
+        result
+    }
+    /// whether [`Self::shrink`] replaced any image that wasn't expanded again
+    pub fn is_shrunk(&self) -> bool {
+        self.placeholders.contains(&true)
+    }
+    /// Recreates the images replaced by [`Self::shrink`] at full size, the ones that
+    /// couldn't be stay placeholders and are retried by the next call
+    pub fn expand(&mut self, render_dev: &RenderDevice) -> Result<(), SwapchainError> {
+        for index in 0..self.image_count {
+            if self.placeholders[index] {
+                self.replace_image(render_dev, index)?;
+                self.placeholders[index] = false;
+            }
+        }
+        Ok(())
+    }
+    fn replace_image(
+        &mut self,
+        render_dev: &RenderDevice,
+        index: usize,
+    ) -> Result<(), SwapchainError> {
+        let dmatex = self.config.create_image(render_dev, index)?;
+        self.config.render_node_id = render_dev.drm_node_id();
+        let data = (self.create_data.get_mut().unwrap())(&dmatex);
+        self.image_state[index] = SwapchainImage::new(0, data);
+        self.images[index] = dmatex;
+        Ok(())
+    }
+    /// Replaces the format the images are created with on the next [`Self::resize`] or
+    /// [`Self::recreate_on`], e.g. with the one negotiated for the new device after a
+    /// [gpu switch](Self::device_changed)
+    pub fn set_format(&mut self, format: &DmatexFormat) {
+        self.config.format = format.clone();
+    }
+    /// Whether the [watched](SwapchainBuilder::device_watcher) server switched gpus since the
+    /// swapchain was created or last [recreated](Self::recreate_on), it has to be recreated
+    /// on the new device then
+    pub fn device_changed(&self) -> bool {
+        self.device_token
+            .as_ref()
+            .is_some_and(DeviceToken::is_stale)
+    }
+    /// Recreates the swapchain on a new device after [`SwapchainError::DeviceLost`] or a
+    /// [gpu switch](Self::device_changed), `render_dev` is usually the result of
+    /// [`RenderDevice::reopen`] or [`DeviceWatcher::poll`]. The per-image data gets
+    /// recreated too, so its create function should take device objects from the dmatex it's
+    /// passed instead of capturing them. The clear queue has to come from the new device.
+    /// If creating the images fails the swapchain can't render until this succeeds
+    pub fn recreate_on(
+        &mut self,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        clear_queue: Option<&Arc<Queue>>,
+    ) -> Result<(), SwapchainError> {
+        // work on the lost device never completes. Retained resources stay until their
+        // acquire points are signalled, the old device can still be busy after a gpu switch
+        self.tracking.in_flight.get_mut().unwrap().clear();
+        // the old images are gone, they can't be shown or copied anymore
+        *self.tracking.last_presented.get_mut().unwrap() = None;
+        self.preserved_frame = None;
+        if let Some(token) = &mut self.device_token {
+            token.renew();
+        }
+        self.config.dev = dev.clone();
+        self.config.clear_queue = clear_queue.cloned();
+        self.command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            dev.clone(),
+            Default::default(),
+        ));
+        if self.profiler.is_some() {
+            self.profiler = Some(Mutex::new(GpuProfiler::new(dev)));
+        }
+        (self.images, self.image_state, self.image_count) = self
+            .config
+            .create_images(render_dev, self.create_data.get_mut().unwrap())?;
+        *self.next_image.get_mut() = 0;
+        self.placeholders = [false; IMAGES];
+        Ok(())
+    }
+}
+
+/// Which images [`Swapchain::shrink`] replaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleReclaim {
+    /// all but the image handed out last, which the server keeps showing
+    KeepLast,
+    /// every image, for surfaces that aren't visible anyway
+    All,
+}
+
+fn placeholder_size(size: &DmatexSize) -> DmatexSize {
+    match size {
+        DmatexSize::Dim1D(_) => DmatexSize::Dim1D(1),
+        DmatexSize::Dim2D(_) => DmatexSize::Dim2D([1, 1].into()),
+        DmatexSize::Dim3D(_) => DmatexSize::Dim3D([1, 1, 1].into()),
     }
 }
-pub struct SwapchainFrameHandle {
+
+// keep the guarantees documented on Swapchain from regressing
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    const fn assert_sync<T: Sync>() {}
+    assert_send::<Swapchain>();
+    assert_sync::<Swapchain>();
+    assert_send::<SwapchainFrameHandle<'static>>();
+    assert_send::<FrameSubmission<'static>>();
+};
+
+/// A frame of a [`Swapchain`], owns its image's per-image data until it's submitted or
+/// dropped. A frame dropped without submitting, or whose submission failed, hands its
+/// timeline points to the image's next frame
+pub struct SwapchainFrameHandle<'a, T = ()> {
     previous_server_release: u64,
     server_acquire: u64,
     next_server_release: u64,
+    index: usize,
     image: Arc<Dmatex>,
+    /// taken out of the image's slot, always Some until the handle is dropped
+    data: Option<T>,
+    slot: &'a Mutex<SwapchainImage<T>>,
+    tracking: &'a FrameTracking,
+    track_in_flight: bool,
+    /// what this frame's submissions used so far
+    resources: FrameResources,
+    profiler: Option<&'a Mutex<GpuProfiler>>,
+    post_process: Option<Arc<PostProcess>>,
+    backpressure: &'a Backpressure,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    foreign_ownership_transfers: bool,
+    layout_transitions: Option<(ImageLayout, ImageLayout)>,
+    host_sync: bool,
+    /// whether the acquire point was signalled or attached to a submission
+    acquire_signalled: bool,
 }
-impl SwapchainFrameHandle {
+impl<T> Drop for SwapchainFrameHandle<'_, T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        if let Some(data) = self.data.take() {
+            slot.data = Some(data);
+        }
+        // nothing will signal the acquire point of a frame that was dropped or failed to
+        // submit, the image's next frame takes over its points instead of waiting forever
+        if !self.acquire_signalled {
+            slot.release = self.previous_server_release;
+        }
+    }
+}
+impl<T> SwapchainFrameHandle<'_, T> {
+    /// the state created for this image by [`Swapchain::new_with`]
+    pub fn data(&self) -> &T {
+        self.data.as_ref().unwrap()
+    }
+    pub fn data_mut(&mut self) -> &mut T {
+        self.data.as_mut().unwrap()
+    }
+    /// replaces the [post process pass](SwapchainBuilder::post_process) for this frame,
+    /// None skips it
+    pub fn set_post_process(&mut self, pass: Option<&Arc<PostProcess>>) {
+        self.post_process = pass.cloned();
+    }
+    /// the index of this frame's image in [`Swapchain::images`]
+    pub fn index(&self) -> usize {
+        self.index
+    }
     pub fn image(&self) -> Arc<Image> {
         self.image.image.clone()
     }
+    /// the dmatex of this frame, e.g. to inspect its timeline
+    pub fn dmatex(&self) -> &Arc<Dmatex> {
+        &self.image
+    }
+    /// the point signalled once this frame is rendered, the server reads it from then on
+    pub fn acquire_point(&self) -> u64 {
+        self.server_acquire
+    }
+    /// the point the server signals once it's done with this frame
+    pub fn release_point(&self) -> u64 {
+        self.next_server_release
+    }
+    /// the point the server releases the image's previous frame at, rendering has to wait
+    /// for it
+    pub fn previous_release_point(&self) -> u64 {
+        self.previous_server_release
+    }
+    /// the cached view of the image, see [`Dmatex::create_view`]
+    pub fn view(&self) -> Arc<ImageView> {
+        self.image.create_view()
+    }
+    /// the cached view of a single array layer, see [`Dmatex::create_layer_view`]
+    pub fn layer_view(&self, layer: u32) -> Arc<ImageView> {
+        self.image.create_layer_view(layer)
+    }
     pub fn blocking_release_wait(&self) {
-        self.image
+        self.wait_for_release(None).unwrap();
+    }
+    /// Waits for the server to release the image for at most `timeout`, a timeout usually
+    /// means the server hung or crashed
+    pub fn release_wait_timeout(&self, timeout: Duration) -> Result<(), WaitError> {
+        self.wait_for_release(Some(Instant::now() + timeout))
+            .map_err(WaitError::from)
+    }
+    /// waits for the server's release, long waits are reported as backpressure
+    fn wait_for_release(&self, deadline: Option<Instant>) -> Result<(), rustix::io::Errno> {
+        let start = Instant::now();
+        let result = self
+            .image
             .timeline
-            .blocking_wait(self.previous_server_release, None)
-            .unwrap();
+            .blocking_wait(self.previous_server_release, deadline);
+        self.backpressure.record(start.elapsed());
+        result
+    }
+    /// An eventfd that becomes readable once the server released the image, for waiting in
+    /// custom event loops, see [`timeline_eventfd`]
+    pub fn release_eventfd(&self, render_dev: &RenderDevice) -> Result<OwnedFd, rustix::io::Errno> {
+        timeline_eventfd(
+            render_dev,
+            &self.image.timeline,
+            self.previous_server_release,
+        )
     }
+    /// Moves ownership of the image from `src`'s to `dst`'s queue family by submitting a release
+    /// barrier to `src` and an acquire barrier to `dst`, work submitted to `dst` afterwards sees
+    /// everything submitted to `src` before this call. Ownership has to be back on the render
+    /// queue's family before [`Self::submit`]. Does nothing for concurrently shared images
+    pub fn transfer_ownership(
+        &mut self,
+        src: &Arc<Queue>,
+        dst: &Arc<Queue>,
+        layout: ImageLayout,
+    ) -> Result<(), SwapchainError> {
+        let image = self.image();
+        let (src_index, dst_index) = (src.queue_family_index(), dst.queue_family_index());
+        if src_index == dst_index || matches!(image.sharing(), Sharing::Concurrent(_)) {
+            return Ok(());
+        }
+        let barrier = ImageMemoryBarrier {
+            src_stages: PipelineStages::ALL_COMMANDS,
+            src_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+            dst_stages: PipelineStages::ALL_COMMANDS,
+            dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+            old_layout: layout,
+            new_layout: layout,
+            queue_family_ownership_transfer: Some(
+                QueueFamilyOwnershipTransfer::ExclusiveBetweenLocal {
+                    src_index,
+                    dst_index,
+                },
+            ),
+            subresource_range: image.subresource_range(),
+            ..ImageMemoryBarrier::image(image)
+        };
+        let released = Arc::new(Semaphore::from_pool(src.device().clone()).unwrap());
+        let release = record_barrier(&self.command_buffer_allocator, src_index, barrier.clone());
+        let acquire = record_barrier(&self.command_buffer_allocator, dst_index, barrier);
+        let resources = &mut self.resources;
+        resources
+            .command_buffers
+            .extend([release.clone(), acquire.clone()]);
+        resources.semaphores.push(released.clone());
+        // the render queue's submission waits for these, they're done by the acquire point
+        src.with(|mut guard| {
+            submit_command_buffers(&mut guard, [release], None, Some(released.clone()))
+        })?;
+        dst.with(|mut guard| submit_command_buffers(&mut guard, [acquire], Some(released), None))
+    }
+    /// Waits for the server to release the image, then calls `submit` with the semaphore to
+    /// wait on, the queue and the semaphore to signal once rendering is done.
+    /// Errors from `submit` are passed through, [`SwapchainError::DeviceLost`] means the
+    /// swapchain has to be [recreated](Swapchain::recreate_on)
     pub fn submit(
         self,
         dev: &Arc<Device>,
         render_queue: &Arc<Queue>,
-        submit: impl FnOnce(Arc<Semaphore>, QueueGuard, Arc<Semaphore>),
-    ) -> DmatexSubmitInfo {
-        let wait_semaphore = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
-        self.image.timeline.blocking_wait(self.previous_server_release, None).unwrap();
-        unsafe {
+        submit: impl FnOnce(
+            Arc<Semaphore>,
+            QueueGuard,
+            Arc<Semaphore>,
+        ) -> Result<(), Validated<VulkanError>>,
+    ) -> Result<DmatexSubmitInfo, SwapchainError> {
+        self.submit_on(dev, render_queue, render_queue, submit)
+    }
+    /// Like [`Self::submit`], but the server's release is waited for and the acquire point
+    /// signalled on `sync_queue`, chained to the rendering on `render_queue` with semaphores
+    /// waited on in all stages. `sync_queue` can be of any queue family, the image's
+    /// ownership stays with `render_queue`'s
+    pub fn submit_on(
+        mut self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        sync_queue: &Arc<Queue>,
+        submit: impl FnOnce(
+            Arc<Semaphore>,
+            QueueGuard,
+            Arc<Semaphore>,
+        ) -> Result<(), Validated<VulkanError>>,
+    ) -> Result<DmatexSubmitInfo, SwapchainError> {
+        self.wait_for_release(None)
+            .map_err(SwapchainError::Syncobj)?;
+        #[cfg(feature = "capture")]
+        let capture = crate::capture::begin_frame(dev.instance());
+        if let Err(err) = self.submit_frame(dev, render_queue, sync_queue, submit) {
+            std::mem::take(&mut self.resources).drop_after_idle(&[render_queue, sync_queue]);
+            return Err(err);
+        }
+        let info = self.submitted();
+        #[cfg(feature = "capture")]
+        if let Some(capture) = capture {
+            capture.end(&info, self.image.name());
+        }
+        Ok(info)
+    }
+    /// the submissions of [`Self::submit_on`] after the release, everything they use goes
+    /// into the frame's resources
+    fn submit_frame(
+        &mut self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        sync_queue: &Arc<Queue>,
+        submit: impl FnOnce(
+            Arc<Semaphore>,
+            QueueGuard,
+            Arc<Semaphore>,
+        ) -> Result<(), Validated<VulkanError>>,
+    ) -> Result<(), SwapchainError> {
+        let split = !Arc::ptr_eq(render_queue, sync_queue);
+        let wait_semaphore = if self.host_sync {
+            // the release was already waited for above
+            let semaphore = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            self.resources.semaphores.push(semaphore.clone());
+            sync_queue.with(|mut guard| host_signal(&mut guard, semaphore.clone()))?;
+            semaphore
+        } else {
+            timeline_wait_semaphore(dev, &self.image.timeline, self.previous_server_release)
+        };
+        if let Some(name) = self.image.name() {
+            set_object_name(wait_semaphore.as_ref(), &format!("{name} wait"));
+        }
+        let submit_semaphore = if self.host_sync {
+            Arc::new(Semaphore::from_pool(dev.clone()).unwrap())
+        } else {
+            exportable_semaphore(dev)
+        };
+        if let Some(name) = self.image.name() {
+            set_object_name(submit_semaphore.as_ref(), &format!("{name} submit"));
+        }
+        self.resources
+            .semaphores
+            .extend([wait_semaphore.clone(), submit_semaphore.clone()]);
+        // the timeline's semaphores only ever go to the sync queue
+        let (wait_semaphore, render_done) = if split && !self.host_sync {
+            let released = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            let render_done = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            self.resources
+                .semaphores
+                .extend([released.clone(), render_done.clone()]);
+            sync_queue
+                .with(|mut guard| relay(&mut guard, [wait_semaphore], [released.clone()], None))?;
+            (released, render_done)
+        } else {
+            (wait_semaphore, submit_semaphore.clone())
+        };
+        let timestamps = self.profiler_timestamps(render_queue.queue_family_index());
+        let (before, after) = self.wrapping_barriers(render_queue.queue_family_index());
+        self.resources
+            .command_buffers
+            .extend(before.iter().chain(&after).cloned());
+        let render_wait = if before.is_empty() {
             wait_semaphore
-                .import_fd(ImportSemaphoreFdInfo {
-                    file: Some(
-                        self.image
-                            .timeline
-                            .export_sync_file_point(self.previous_server_release)
-                            .unwrap()
-                            .into(),
-                    ),
-                    flags: SemaphoreImportFlags::TEMPORARY,
-                    ..ImportSemaphoreFdInfo::handle_type(ExternalSemaphoreHandleType::SyncFd)
-                })
-                .unwrap()
+        } else {
+            let acquired = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            self.resources.semaphores.push(acquired.clone());
+            render_queue.with(|mut guard| {
+                submit_command_buffers(
+                    &mut guard,
+                    before,
+                    Some(wait_semaphore),
+                    Some(acquired.clone()),
+                )
+            })?;
+            acquired
+        };
+        let render_signal = if after.is_empty() {
+            render_done.clone()
+        } else {
+            let semaphore = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            self.resources.semaphores.push(semaphore.clone());
+            semaphore
+        };
+        submit_timed(
+            dev,
+            render_queue,
+            render_wait,
+            render_signal.clone(),
+            timestamps,
+            &mut self.resources,
+            submit,
+        )?;
+        if !after.is_empty() {
+            render_queue.with(|mut guard| {
+                submit_command_buffers(
+                    &mut guard,
+                    after,
+                    Some(render_signal),
+                    Some(render_done.clone()),
+                )
+            })?;
+        }
+        if split && !self.host_sync {
+            sync_queue.with(|mut guard| {
+                relay(&mut guard, [render_done], [submit_semaphore.clone()], None)
+            })?;
         }
-        // TODO: custom pool?
-        let submit_semaphore = Arc::new(
-            Semaphore::new(
-                dev.clone(),
-                SemaphoreCreateInfo {
-                    export_handle_types: ExternalSemaphoreHandleTypes::SYNC_FD,
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        );
-        render_queue.with(|guard| submit(wait_semaphore, guard, submit_semaphore.clone()));
 
-        let fd = unsafe {
-            submit_semaphore
-                .export_fd(ExternalSemaphoreHandleType::SyncFd)
-                .unwrap()
-        };
-        self.image
-            .timeline
-            .import_sync_file_point(fd.as_fd(), self.server_acquire)
-            .unwrap();
+        if self.host_sync {
+            let fence = Arc::new(Fence::new(dev.clone(), FenceCreateInfo::default()).unwrap());
+            sync_queue.with(|mut guard| host_wait(&mut guard, submit_semaphore, &fence))?;
+            fence.wait(None)?;
+            unsafe { self.image.timeline.signal(self.server_acquire) }
+                .map_err(SwapchainError::Syncobj)?;
+        } else {
+            attach_to_timeline(&self.image.timeline, &submit_semaphore, self.server_acquire);
+        }
+        self.acquire_signalled = true;
+        Ok(())
+    }
 
+    /// records the submitted frame with the swapchain
+    fn submitted(&mut self) -> DmatexSubmitInfo {
+        let frame = (self.image.clone(), self.server_acquire);
+        self.tracking
+            .retained
+            .lock()
+            .unwrap()
+            .push_back(RetainedFrame {
+                dmatex: self.image.clone(),
+                acquire_point: self.server_acquire,
+                resources: std::mem::take(&mut self.resources),
+            });
+        if self.track_in_flight {
+            self.tracking
+                .in_flight
+                .lock()
+                .unwrap()
+                .push_back(frame.clone());
+        }
+        *self.tracking.last_presented.lock().unwrap() = Some(frame);
         DmatexSubmitInfo {
             dmatex_id: self.image.dmatex_id,
             acquire_point: self.server_acquire,
             release_point: self.next_server_release,
         }
     }
+    /// the profiler's timestamp writes for this frame, if profiling is enabled
+    fn profiler_timestamps(
+        &self,
+        queue_family_index: u32,
+    ) -> Option<(Arc<CommandBuffer>, Arc<CommandBuffer>)> {
+        self.profiler?
+            .lock()
+            .unwrap()
+            .begin_frame(&self.command_buffer_allocator, queue_family_index)
+    }
+    /// The barriers recorded before and after the user's work, ownership transfers from and
+    /// back to the foreign queue family outside of the layout transitions. The post process
+    /// pass runs first after the user's work
+    fn wrapping_barriers(
+        &self,
+        queue_family_index: u32,
+    ) -> (Vec<Arc<CommandBuffer>>, Vec<Arc<CommandBuffer>>) {
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        let resting_layout = self
+            .layout_transitions
+            .map_or(ImageLayout::General, |(_, final_layout)| final_layout);
+        if self.foreign_ownership_transfers {
+            let (acquire, release) = self.foreign_barriers(queue_family_index, resting_layout);
+            before.push(acquire);
+            after.push(release);
+        }
+        if let Some((layout, final_layout)) = self.layout_transitions {
+            let image = self.image();
+            let entry = record_barrier(
+                &self.command_buffer_allocator,
+                queue_family_index,
+                ImageMemoryBarrier {
+                    dst_stages: PipelineStages::ALL_COMMANDS,
+                    dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+                    // contents are undefined until the first frame was rendered
+                    old_layout: if self.previous_server_release == 0 {
+                        ImageLayout::Undefined
+                    } else {
+                        final_layout
+                    },
+                    new_layout: layout,
+                    subresource_range: image.subresource_range(),
+                    ..ImageMemoryBarrier::image(image.clone())
+                },
+            );
+            let exit = record_barrier(
+                &self.command_buffer_allocator,
+                queue_family_index,
+                ImageMemoryBarrier {
+                    src_stages: PipelineStages::ALL_COMMANDS,
+                    src_access: AccessFlags::MEMORY_WRITE,
+                    dst_stages: PipelineStages::ALL_COMMANDS,
+                    dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+                    old_layout: layout,
+                    new_layout: final_layout,
+                    subresource_range: image.subresource_range(),
+                    ..ImageMemoryBarrier::image(image)
+                },
+            );
+            before.push(entry);
+            after.insert(0, exit);
+        }
+        if let Some(pass) = &self.post_process {
+            let layout = self
+                .layout_transitions
+                .map_or(ImageLayout::General, |(layout, _)| layout);
+            after.insert(
+                0,
+                pass.record(
+                    &self.command_buffer_allocator,
+                    queue_family_index,
+                    &self.image,
+                    layout,
+                ),
+            );
+        }
+        (before, after)
+    }
+    /// the barriers moving the image from and back to the foreign queue family around a frame
+    fn foreign_barriers(
+        &self,
+        queue_family_index: u32,
+        layout: ImageLayout,
+    ) -> (Arc<CommandBuffer>, Arc<CommandBuffer>) {
+        let image = self.image();
+        let concurrent = matches!(image.sharing(), Sharing::Concurrent(_));
+        let acquire = record_barrier(
+            &self.command_buffer_allocator,
+            queue_family_index,
+            ImageMemoryBarrier {
+                dst_stages: PipelineStages::ALL_COMMANDS,
+                dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+                // contents are undefined until the first frame was rendered
+                old_layout: if self.previous_server_release == 0 {
+                    ImageLayout::Undefined
+                } else {
+                    layout
+                },
+                new_layout: layout,
+                queue_family_ownership_transfer: Some(if concurrent {
+                    QueueFamilyOwnershipTransfer::ConcurrentFromForeign
+                } else {
+                    QueueFamilyOwnershipTransfer::ExclusiveFromForeign {
+                        dst_index: queue_family_index,
+                    }
+                }),
+                subresource_range: image.subresource_range(),
+                ..ImageMemoryBarrier::image(image.clone())
+            },
+        );
+        let release = record_barrier(
+            &self.command_buffer_allocator,
+            queue_family_index,
+            ImageMemoryBarrier {
+                src_stages: PipelineStages::ALL_COMMANDS,
+                src_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+                old_layout: layout,
+                new_layout: layout,
+                queue_family_ownership_transfer: Some(if concurrent {
+                    QueueFamilyOwnershipTransfer::ConcurrentToForeign
+                } else {
+                    QueueFamilyOwnershipTransfer::ExclusiveToForeign {
+                        src_index: queue_family_index,
+                    }
+                }),
+                subresource_range: image.subresource_range(),
+                ..ImageMemoryBarrier::image(image)
+            },
+        );
+        (acquire, release)
+    }
+
+    /// Submits `command_buffer` to `render_queue`, waiting for the server's release and
+    /// signalling the acquire point once it's done
+    pub fn submit_command_buffer(
+        self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        command_buffer: Arc<PrimaryAutoCommandBuffer>,
+    ) -> Result<DmatexSubmitInfo, SwapchainError> {
+        self.submit_command_buffers(dev, render_queue, [command_buffer])
+    }
+    /// Like [`Self::submit_command_buffer`], with the command buffers executed in order in a
+    /// single submission. They're kept alive until the acquire point is signalled
+    pub fn submit_command_buffers(
+        mut self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        command_buffers: impl IntoIterator<Item = Arc<PrimaryAutoCommandBuffer>>,
+    ) -> Result<DmatexSubmitInfo, SwapchainError> {
+        self.resources.user_command_buffers.extend(command_buffers);
+        let command_buffers = self
+            .resources
+            .user_command_buffers
+            .iter()
+            .cloned()
+            .map(CommandBufferSubmitInfo::new)
+            .collect();
+        self.submit(dev, render_queue, |wait, mut guard, signal| unsafe {
+            guard.submit(
+                &[SubmitInfo {
+                    // the image might be touched by any stage, the release has to come first
+                    wait_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(wait)
+                    }],
+                    command_buffers,
+                    signal_semaphores: vec![SemaphoreSubmitInfo {
+                        stages: PipelineStages::ALL_COMMANDS,
+                        ..SemaphoreSubmitInfo::new(signal)
+                    }],
+                    ..Default::default()
+                }],
+                None,
+            )
+        })
+    }
+}
+
+/// Collects the frames of several swapchains, e.g. every panel of an app, to submit them
+/// together in a single queue submission instead of one per frame
+pub struct FrameSubmission<'a, T = ()> {
+    frames: Vec<SwapchainFrameHandle<'a, T>>,
+}
+impl<T> Default for FrameSubmission<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<'a, T> FrameSubmission<'a, T> {
+    pub fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+    pub fn frame(mut self, frame: SwapchainFrameHandle<'a, T>) -> Self {
+        self.push(frame);
+        self
+    }
+    pub fn push(&mut self, frame: SwapchainFrameHandle<'a, T>) {
+        self.frames.push(frame);
+    }
+    /// Waits for the server to release every image, then submits `command_buffers` once,
+    /// waiting on all releases and signalling all acquire points. The submit infos are in
+    /// the order the frames were added, the command buffers are kept alive until every
+    /// acquire point is signalled
+    pub fn submit(
+        self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        command_buffers: impl IntoIterator<Item = Arc<PrimaryAutoCommandBuffer>>,
+    ) -> Result<Vec<DmatexSubmitInfo>, SwapchainError> {
+        self.submit_on(dev, render_queue, render_queue, command_buffers)
+    }
+    /// Like [`Self::submit`], with the releases waited for and the acquire points signalled
+    /// on `sync_queue`, see [`SwapchainFrameHandle::submit_on`]
+    pub fn submit_on(
+        mut self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        sync_queue: &Arc<Queue>,
+        command_buffers: impl IntoIterator<Item = Arc<PrimaryAutoCommandBuffer>>,
+    ) -> Result<Vec<DmatexSubmitInfo>, SwapchainError> {
+        if let Err(err) = self.submit_frames(dev, render_queue, sync_queue, command_buffers) {
+            for frame in &mut self.frames {
+                std::mem::take(&mut frame.resources).drop_after_idle(&[render_queue, sync_queue]);
+            }
+            return Err(err);
+        }
+        Ok(self
+            .frames
+            .iter_mut()
+            .map(SwapchainFrameHandle::submitted)
+            .collect())
+    }
+    /// the submissions of [`Self::submit_on`], everything they use goes into the resources of
+    /// every frame since each of them can be dropped first
+    fn submit_frames(
+        &mut self,
+        dev: &Arc<Device>,
+        render_queue: &Arc<Queue>,
+        sync_queue: &Arc<Queue>,
+        command_buffers: impl IntoIterator<Item = Arc<PrimaryAutoCommandBuffer>>,
+    ) -> Result<(), SwapchainError> {
+        let command_buffers = command_buffers.into_iter().collect::<Vec<_>>();
+        let queue_family_index = render_queue.queue_family_index();
+        let mut wait_semaphores = Vec::new();
+        let mut signal_semaphores = Vec::with_capacity(self.frames.len());
+        let (mut starts, mut acquires, mut releases, mut ends) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for frame in &mut self.frames {
+            frame
+                .wait_for_release(None)
+                .map_err(SwapchainError::Syncobj)?;
+            frame
+                .resources
+                .user_command_buffers
+                .extend(command_buffers.iter().cloned());
+            // host synced frames were released above and get signalled after the fence
+            if frame.host_sync {
+                signal_semaphores.push(None);
+            } else {
+                let wait = timeline_wait_semaphore(
+                    dev,
+                    &frame.image.timeline,
+                    frame.previous_server_release,
+                );
+                let signal = exportable_semaphore(dev);
+                if let Some(name) = frame.image.name() {
+                    set_object_name(wait.as_ref(), &format!("{name} wait"));
+                    set_object_name(signal.as_ref(), &format!("{name} submit"));
+                }
+                frame
+                    .resources
+                    .semaphores
+                    .extend([wait.clone(), signal.clone()]);
+                wait_semaphores.push(wait);
+                signal_semaphores.push(Some(signal));
+            }
+            if let Some((start, end)) = frame.profiler_timestamps(queue_family_index) {
+                frame
+                    .resources
+                    .command_buffers
+                    .extend([start.clone(), end.clone()]);
+                starts.push(start);
+                ends.push(end);
+            }
+            let (before, after) = frame.wrapping_barriers(queue_family_index);
+            frame
+                .resources
+                .command_buffers
+                .extend(before.iter().chain(&after).cloned());
+            acquires.extend(before);
+            releases.extend(after);
+        }
+        let command_buffers = starts
+            .into_iter()
+            .chain(acquires)
+            .map(CommandBufferSubmitInfo::new)
+            .chain(
+                command_buffers
+                    .into_iter()
+                    .map(CommandBufferSubmitInfo::new),
+            )
+            .chain(
+                releases
+                    .into_iter()
+                    .chain(ends)
+                    .map(CommandBufferSubmitInfo::new),
+            )
+            .collect();
+        let fence = self
+            .frames
+            .iter()
+            .any(|frame| frame.host_sync)
+            .then(|| Arc::new(Fence::new(dev.clone(), FenceCreateInfo::default()).unwrap()));
+        let signals = signal_semaphores.iter().flatten().cloned();
+        if Arc::ptr_eq(render_queue, sync_queue) {
+            render_queue.with(|mut guard| unsafe {
+                guard.submit(
+                    &[SubmitInfo {
+                        wait_semaphores: wait_semaphores.into_iter().map(all_stages).collect(),
+                        command_buffers,
+                        signal_semaphores: signals.map(all_stages).collect(),
+                        ..Default::default()
+                    }],
+                    fence.as_ref(),
+                )
+            })?;
+        } else {
+            let released = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            let rendered = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+            for frame in &mut self.frames {
+                frame
+                    .resources
+                    .semaphores
+                    .extend([released.clone(), rendered.clone()]);
+            }
+            sync_queue
+                .with(|mut guard| relay(&mut guard, wait_semaphores, [released.clone()], None))?;
+            render_queue.with(|mut guard| unsafe {
+                guard.submit(
+                    &[SubmitInfo {
+                        wait_semaphores: vec![all_stages(released)],
+                        command_buffers,
+                        signal_semaphores: vec![SemaphoreSubmitInfo::new(rendered.clone())],
+                        ..Default::default()
+                    }],
+                    None,
+                )
+            })?;
+            sync_queue.with(|mut guard| relay(&mut guard, [rendered], signals, fence.as_ref()))?;
+        }
+        if let Some(fence) = &fence {
+            fence.wait(None)?;
+        }
+
+        for (frame, signal) in self.frames.iter_mut().zip(signal_semaphores) {
+            match signal {
+                Some(semaphore) => {
+                    attach_to_timeline(&frame.image.timeline, &semaphore, frame.server_acquire)
+                }
+                None => unsafe { frame.image.timeline.signal(frame.server_acquire) }
+                    .map_err(SwapchainError::Syncobj)?,
+            }
+            frame.acquire_signalled = true;
+        }
+        Ok(())
+    }
+}
+
+fn record_barrier(
+    allocator: &Arc<StandardCommandBufferAllocator>,
+    queue_family_index: u32,
+    barrier: ImageMemoryBarrier,
+) -> Arc<CommandBuffer> {
+    let mut recording = unsafe {
+        RecordingCommandBuffer::new(
+            allocator.clone(),
+            queue_family_index,
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+    }
+    .unwrap();
+    unsafe {
+        recording.pipeline_barrier(&DependencyInfo {
+            image_memory_barriers: [barrier].into_iter().collect(),
+            ..Default::default()
+        })
+    }
+    .unwrap();
+    Arc::new(unsafe { recording.end() }.unwrap())
+}
+
+/// Blits all of `src` scaled into all of `dst`, both are in `layout` before and after.
+/// The old contents of `dst` are discarded
+fn record_blit(
+    allocator: &Arc<StandardCommandBufferAllocator>,
+    queue_family_index: u32,
+    src: &Arc<Image>,
+    dst: &Arc<Image>,
+    layout: ImageLayout,
+) -> Arc<CommandBuffer> {
+    let barrier = |image: &Arc<Image>, old_layout, new_layout| ImageMemoryBarrier {
+        src_stages: PipelineStages::ALL_COMMANDS,
+        src_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+        dst_stages: PipelineStages::ALL_COMMANDS,
+        dst_access: AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE,
+        old_layout,
+        new_layout,
+        subresource_range: image.subresource_range(),
+        ..ImageMemoryBarrier::image(image.clone())
+    };
+    let mut recording = unsafe {
+        RecordingCommandBuffer::new(
+            allocator.clone(),
+            queue_family_index,
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+    }
+    .unwrap();
+    unsafe {
+        recording
+            .pipeline_barrier(&DependencyInfo {
+                image_memory_barriers: [
+                    barrier(src, layout, ImageLayout::General),
+                    barrier(dst, ImageLayout::Undefined, ImageLayout::TransferDstOptimal),
+                ]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            })
+            .unwrap();
+        recording
+            .blit_image(&BlitImageInfo {
+                // the server might still be reading the old image
+                src_image_layout: ImageLayout::General,
+                filter: Filter::Linear,
+                ..BlitImageInfo::images(src.clone(), dst.clone())
+            })
+            .unwrap();
+        recording
+            .pipeline_barrier(&DependencyInfo {
+                image_memory_barriers: [
+                    barrier(src, ImageLayout::General, layout),
+                    barrier(dst, ImageLayout::TransferDstOptimal, layout),
+                ]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    Arc::new(unsafe { recording.end() }.unwrap())
+}
+
+/// whether the image's modifier can be blitted from and to with linear filtering
+fn supports_linear_blit(image: &Image) -> bool {
+    let Some((modifier, _)) = image.drm_format_modifier() else {
+        return false;
+    };
+    let Ok(props) = image
+        .device()
+        .physical_device()
+        .format_properties(image.format())
+    else {
+        return false;
+    };
+    props
+        .drm_format_modifier_properties
+        .iter()
+        .find(|v| v.drm_format_modifier == modifier)
+        .is_some_and(|v| {
+            v.drm_format_modifier_tiling_features.contains(
+                FormatFeatures::BLIT_SRC
+                    | FormatFeatures::BLIT_DST
+                    | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR,
+            )
+        })
+}
+
+/// a raw submission, the caller keeps everything passed in alive until the gpu is done with
+/// it, usually in the frame's [`FrameResources`]
+fn submit_command_buffers(
+    guard: &mut QueueGuard,
+    command_buffers: impl IntoIterator<Item = Arc<CommandBuffer>>,
+    wait: Option<Arc<Semaphore>>,
+    signal: Option<Arc<Semaphore>>,
+) -> Result<(), SwapchainError> {
+    unsafe {
+        guard.submit(
+            &[SubmitInfo {
+                wait_semaphores: wait.into_iter().map(SemaphoreSubmitInfo::new).collect(),
+                command_buffers: command_buffers
+                    .into_iter()
+                    .map(CommandBufferSubmitInfo::new)
+                    .collect(),
+                signal_semaphores: signal.into_iter().map(SemaphoreSubmitInfo::new).collect(),
+                ..Default::default()
+            }],
+            None,
+        )
+    }?;
+    Ok(())
+}
+
+/// Runs the user's submission, between the profiler's timestamp writes if there are any
+fn submit_timed(
+    dev: &Arc<Device>,
+    render_queue: &Arc<Queue>,
+    wait: Arc<Semaphore>,
+    signal: Arc<Semaphore>,
+    timestamps: Option<(Arc<CommandBuffer>, Arc<CommandBuffer>)>,
+    resources: &mut FrameResources,
+    submit: impl FnOnce(
+        Arc<Semaphore>,
+        QueueGuard,
+        Arc<Semaphore>,
+    ) -> Result<(), Validated<VulkanError>>,
+) -> Result<(), SwapchainError> {
+    let Some((start, end)) = timestamps else {
+        render_queue.with(|guard| submit(wait, guard, signal))?;
+        return Ok(());
+    };
+    let started = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+    let finished = Arc::new(Semaphore::from_pool(dev.clone()).unwrap());
+    resources
+        .command_buffers
+        .extend([start.clone(), end.clone()]);
+    resources
+        .semaphores
+        .extend([started.clone(), finished.clone()]);
+    render_queue.with(|mut guard| {
+        submit_command_buffers(&mut guard, [start], Some(wait), Some(started.clone()))
+    })?;
+    render_queue.with(|guard| submit(started, guard, finished.clone()))?;
+    render_queue
+        .with(|mut guard| submit_command_buffers(&mut guard, [end], Some(finished), Some(signal)))
+}
+
+/// an empty submission passing `waits` on to `signals`, for chaining queues
+fn relay(
+    guard: &mut QueueGuard,
+    waits: impl IntoIterator<Item = Arc<Semaphore>>,
+    signals: impl IntoIterator<Item = Arc<Semaphore>>,
+    fence: Option<&Arc<Fence>>,
+) -> Result<(), SwapchainError> {
+    unsafe {
+        guard.submit(
+            &[SubmitInfo {
+                wait_semaphores: waits.into_iter().map(all_stages).collect(),
+                signal_semaphores: signals.into_iter().map(all_stages).collect(),
+                ..Default::default()
+            }],
+            fence,
+        )
+    }?;
+    Ok(())
+}
+
+/// waits before or signals after every stage, the image might be touched by any of them
+fn all_stages(semaphore: Arc<Semaphore>) -> SemaphoreSubmitInfo {
+    SemaphoreSubmitInfo {
+        stages: PipelineStages::ALL_COMMANDS,
+        ..SemaphoreSubmitInfo::new(semaphore)
+    }
+}
+
+/// an empty submission signalling `semaphore`
+fn host_signal(guard: &mut QueueGuard, semaphore: Arc<Semaphore>) -> Result<(), SwapchainError> {
+    unsafe {
+        guard.submit(
+            &[SubmitInfo {
+                signal_semaphores: vec![SemaphoreSubmitInfo::new(semaphore)],
+                ..Default::default()
+            }],
+            None,
+        )
+    }?;
+    Ok(())
+}
+
+/// an empty submission waiting for `semaphore` and then signalling `fence`
+fn host_wait(
+    guard: &mut QueueGuard,
+    semaphore: Arc<Semaphore>,
+    fence: &Arc<Fence>,
+) -> Result<(), SwapchainError> {
+    unsafe {
+        guard.submit(
+            &[SubmitInfo {
+                wait_semaphores: vec![SemaphoreSubmitInfo {
+                    stages: PipelineStages::ALL_COMMANDS,
+                    ..SemaphoreSubmitInfo::new(semaphore)
+                }],
+                ..Default::default()
+            }],
+            Some(fence),
+        )
+    }?;
+    Ok(())
+}
+
+const DEFAULT_BACKPRESSURE_THRESHOLD: Duration = Duration::from_millis(4);
+
+/// Tracks frames that had to wait long for the server to release their image
+struct Backpressure {
+    threshold: Duration,
+    stalled_frames: AtomicU64,
+    longest_wait_nanos: AtomicU64,
+    callback: Mutex<Option<Box<dyn FnMut(Duration) + Send>>>,
+}
+impl Backpressure {
+    fn record(&self, wait: Duration) {
+        if wait < self.threshold {
+            return;
+        }
+        self.stalled_frames.fetch_add(1, Ordering::Relaxed);
+        self.longest_wait_nanos
+            .fetch_max(wait.as_nanos() as u64, Ordering::Relaxed);
+        if let Some(callback) = self.callback.lock().unwrap().as_mut() {
+            callback(wait);
+        }
+    }
+}
+
+/// How often frames waited longer than the
+/// [backpressure threshold](SwapchainBuilder::backpressure_threshold) for the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackpressureStats {
+    pub stalled_frames: u64,
+    pub longest_wait: Duration,
+}
+
+#[derive(Debug, Error)]
+pub enum WaitError {
+    #[error("timed out waiting for the server to release the image")]
+    Timeout,
+    #[error("the timeline syncobj was destroyed")]
+    TimelineDestroyed,
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(rustix::io::Errno),
+}
+impl From<rustix::io::Errno> for WaitError {
+    fn from(err: rustix::io::Errno) -> Self {
+        use rustix::io::Errno;
+        match err {
+            Errno::TIME | Errno::TIMEDOUT => Self::Timeout,
+            Errno::NOENT => Self::TimelineDestroyed,
+            err => Self::Syncobj(err),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ShutdownError {
+    #[error("failed to wait for the server to release an image: {0}")]
+    Wait(#[from] WaitError),
+    #[error("failed to unregister a dmatex: {0}")]
+    Unregister(#[from] NodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum SwapchainError {
+    #[error("the vulkan device was lost")]
+    DeviceLost,
+    #[error("vulkan error: {0}")]
+    Vulkan(VulkanError),
+    #[error("validation error: {0}")]
+    Validation(Box<ValidationError>),
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(rustix::io::Errno),
+    #[error("failed to create a swapchain image: {0}")]
+    Image(DmatexImportError),
+    #[error("udmabuf images need a single plane format with a drm fourcc, got {0:?}")]
+    UdmabufFormat(Format),
+    #[error("every image of the swapchain is already handed out to a frame")]
+    AllImagesHandedOut,
+    #[error("failed to reopen the render device: {0}")]
+    RenderDevice(#[from] RenderDeviceCreationError),
+}
+impl From<VulkanError> for SwapchainError {
+    fn from(err: VulkanError) -> Self {
+        match err {
+            VulkanError::DeviceLost => Self::DeviceLost,
+            err => Self::Vulkan(err),
+        }
+    }
+}
+impl From<DmatexImportError> for SwapchainError {
+    fn from(err: DmatexImportError) -> Self {
+        match err {
+            DmatexImportError::DeviceLost => Self::DeviceLost,
+            err => Self::Image(err),
+        }
+    }
+}
+impl From<Validated<VulkanError>> for SwapchainError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        match err {
+            Validated::Error(err) => err.into(),
+            Validated::ValidationError(err) => Self::Validation(err),
+        }
+    }
 }
@@ -0,0 +1,127 @@
+//! How dmatexes synchronize with the server. Every dmatex has a [`SyncProvider`] holding its
+//! timeline, a drm timeline syncobj unless the builders are given something else
+
+use std::{
+    future::Future,
+    os::fd::{BorrowedFd, OwnedFd},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Instant,
+};
+
+use rustix::io::Errno;
+use timeline_syncobj::timeline_syncobj::TimelineSyncObj;
+
+use crate::render_device::RenderDevice;
+
+/// A timeline of points that gpu work and the server signal and wait on
+pub trait SyncProvider: Send + Sync + 'static {
+    /// Signals `point` from the cpu
+    ///
+    /// # Safety
+    /// the server reads the image once its acquire point is signalled,
+    /// so everything rendering into it has to be done
+    unsafe fn signal(&self, point: u64) -> Result<(), Errno>;
+    /// waits until `point` is signalled, `deadline` None waits forever
+    fn blocking_wait(&self, point: u64, deadline: Option<Instant>) -> Result<(), Errno>;
+    /// signals `point` once `sync_file` is signalled
+    fn import_sync_file_point(&self, sync_file: BorrowedFd<'_>, point: u64) -> Result<(), Errno>;
+    /// a sync file that is signalled once `point` is
+    fn export_sync_file_point(&self, point: u64) -> Result<OwnedFd, Errno>;
+    /// the drm syncobj handed to the server
+    fn export(&self) -> Result<OwnedFd, Errno>;
+    /// The highest signalled point, found with non-blocking waits, points are never
+    /// unsignalled. Providers that can read the value directly should override this
+    fn current_point(&self) -> u64 {
+        let reached = |point| self.blocking_wait(point, Some(Instant::now())).is_ok();
+        let mut low = 0;
+        let mut high = 1;
+        while reached(high) {
+            low = high;
+            let Some(next) = high.checked_mul(2) else {
+                return u64::MAX;
+            };
+            high = next;
+        }
+        // `low` is signalled and `high` isn't
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if reached(mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl SyncProvider for TimelineSyncObj {
+    unsafe fn signal(&self, point: u64) -> Result<(), Errno> {
+        unsafe { TimelineSyncObj::signal(self, point) }
+    }
+    fn blocking_wait(&self, point: u64, deadline: Option<Instant>) -> Result<(), Errno> {
+        TimelineSyncObj::blocking_wait(self, point, deadline)
+    }
+    fn import_sync_file_point(&self, sync_file: BorrowedFd<'_>, point: u64) -> Result<(), Errno> {
+        TimelineSyncObj::import_sync_file_point(self, sync_file, point)
+    }
+    fn export_sync_file_point(&self, point: u64) -> Result<OwnedFd, Errno> {
+        TimelineSyncObj::export_sync_file_point(self, point)
+    }
+    fn export(&self) -> Result<OwnedFd, Errno> {
+        TimelineSyncObj::export(self)
+    }
+}
+
+/// Creates the sync provider of every new dmatex
+pub type SyncProviderFactory =
+    Arc<dyn Fn(&RenderDevice) -> Result<Box<dyn SyncProvider>, Errno> + Send + Sync>;
+
+/// the default, a new drm timeline syncobj on the render node
+pub fn timeline_syncobj(render_dev: &RenderDevice) -> Result<Box<dyn SyncProvider>, Errno> {
+    Ok(Box::new(TimelineSyncObj::create(render_dev.drm_node())?))
+}
+
+/// Runs blocking timeline waits on their own thread so they can be awaited on any executor
+pub(crate) fn unblock<R: Send + 'static>(
+    f: impl FnOnce() -> R + Send + 'static,
+) -> impl Future<Output = R> + Send {
+    let state = Arc::new(Mutex::new(UnblockState {
+        result: None,
+        waker: None,
+    }));
+    let thread_state = state.clone();
+    thread::spawn(move || {
+        let result = f();
+        let mut state = thread_state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    Unblock { state }
+}
+
+struct UnblockState<R> {
+    result: Option<R>,
+    waker: Option<Waker>,
+}
+struct Unblock<R> {
+    state: Arc<Mutex<UnblockState<R>>>,
+}
+impl<R> Future for Unblock<R> {
+    type Output = R;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
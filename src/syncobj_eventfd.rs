@@ -0,0 +1,85 @@
+//! Eventfds that become readable once a timeline point is signalled, for waiting on frames
+//! from calloop/epoll/mio event loops without a blocking thread. Needs linux 6.6 or newer
+
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+
+use rustix::{
+    event::{EventfdFlags, eventfd},
+    fs::{Mode, OFlags, major, minor, open},
+    io::Errno,
+    ioctl::{Updater, ioctl, opcode},
+};
+
+use crate::{render_device::RenderDevice, sync::SyncProvider};
+
+/// Creates an eventfd that's signalled once `point` on `timeline` is signalled, it stays valid
+/// after the timeline is dropped. Read it to reset it
+pub fn timeline_eventfd(
+    render_dev: &RenderDevice,
+    timeline: &dyn SyncProvider,
+    point: u64,
+) -> Result<OwnedFd, Errno> {
+    let node_id = render_dev.drm_node_id();
+    // a private fd for the node, so the syncobj handle can't collide with anyone else's
+    let node = open(
+        format!("/dev/char/{}:{}", major(node_id), minor(node_id)),
+        OFlags::RDWR | OFlags::CLOEXEC,
+        Mode::empty(),
+    )?;
+    let syncobj = timeline.export()?;
+    let mut handle = DrmSyncobjHandle {
+        handle: 0,
+        flags: 0,
+        fd: syncobj.as_raw_fd(),
+        pad: 0,
+    };
+    unsafe { ioctl(&node, Updater::<SYNCOBJ_FD_TO_HANDLE, _>::new(&mut handle)) }?;
+    let event = eventfd(0, EventfdFlags::CLOEXEC)?;
+    let mut eventfd_args = DrmSyncobjEventfd {
+        handle: handle.handle,
+        flags: 0,
+        point,
+        fd: event.as_fd().as_raw_fd(),
+        pad: 0,
+    };
+    let result = unsafe { ioctl(&node, Updater::<SYNCOBJ_EVENTFD, _>::new(&mut eventfd_args)) };
+    // the eventfd keeps its own reference to the syncobj
+    let mut destroy = DrmSyncobjDestroy {
+        handle: handle.handle,
+        pad: 0,
+    };
+    unsafe { ioctl(&node, Updater::<SYNCOBJ_DESTROY, _>::new(&mut destroy)) }?;
+    result?;
+    Ok(event)
+}
+
+const SYNCOBJ_DESTROY: rustix::ioctl::Opcode = opcode::read_write::<DrmSyncobjDestroy>(b'd', 0xC0);
+const SYNCOBJ_FD_TO_HANDLE: rustix::ioctl::Opcode =
+    opcode::read_write::<DrmSyncobjHandle>(b'd', 0xC2);
+const SYNCOBJ_EVENTFD: rustix::ioctl::Opcode = opcode::read_write::<DrmSyncobjEventfd>(b'd', 0xCF);
+
+/// `struct drm_syncobj_destroy` from `drm/drm.h`
+#[repr(C)]
+struct DrmSyncobjDestroy {
+    handle: u32,
+    pad: u32,
+}
+
+/// `struct drm_syncobj_handle` from `drm/drm.h`
+#[repr(C)]
+struct DrmSyncobjHandle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+    pad: u32,
+}
+
+/// `struct drm_syncobj_eventfd` from `drm/drm.h`
+#[repr(C)]
+struct DrmSyncobjEventfd {
+    handle: u32,
+    flags: u32,
+    point: u64,
+    fd: i32,
+    pad: u32,
+}
@@ -0,0 +1,173 @@
+//! memfd backed dmabufs from `/dev/udmabuf`, for software vulkan (lavapipe) and CI machines
+//! without a gpu that can export dmabufs
+//!
+//! The images are linear and imported into vulkan with [`Dmatex::import`], so the device only
+//! has to support importing dmabufs with the linear drm format modifier, it still needs
+//! `ext_image_drm_format_modifier` like every import. Exporting sync files is often missing
+//! as well there, see [`SwapchainBuilder::host_sync`]. Timeline syncobjs still need a drm node
+//! that supports them, it doesn't have to be the device doing the rendering.
+//!
+//! [`Dmatex::import`]: crate::dmatex::Dmatex::import
+//! [`SwapchainBuilder::host_sync`]: crate::swapchain::SwapchainBuilder::host_sync
+
+use std::{
+    ffi::c_void,
+    fs::File,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use rustix::{
+    fs::{MemfdFlags, SealFlags, fcntl_add_seals, ftruncate, memfd_create},
+    io::Errno,
+    ioctl::{Ioctl, IoctlOutput, Opcode, opcode},
+};
+use stardust_xr_fusion::drawable::DmatexSize;
+use vulkano::{
+    device::physical::{PhysicalDevice, PhysicalDeviceType},
+    format::Format,
+};
+
+use crate::{
+    dmatex::{ExternalDmabuf, ExternalPlane},
+    format::VulkanoFormatExtension,
+};
+
+/// row pitch alignment that every importer copes with
+const ROW_ALIGNMENT: u64 = 256;
+const PAGE_SIZE: u64 = 4096;
+
+/// Whether `phys_dev` is a software device like lavapipe, which can't export its memory as
+/// dmabufs and needs udmabuf. Imports need `ext_image_drm_format_modifier` either way
+pub fn needs_udmabuf(phys_dev: &PhysicalDevice) -> bool {
+    phys_dev.properties().device_type == PhysicalDeviceType::Cpu
+}
+
+/// A handle to `/dev/udmabuf`
+pub struct UdmabufAllocator {
+    device: File,
+}
+
+/// A dmabuf together with the memfd backing it, the memfd can be mapped for cpu access
+#[derive(Debug)]
+pub struct Udmabuf {
+    pub dmabuf: OwnedFd,
+    pub memfd: OwnedFd,
+    pub size: u64,
+}
+
+impl UdmabufAllocator {
+    pub fn open() -> std::io::Result<Self> {
+        Self::open_path("/dev/udmabuf")
+    }
+    pub fn open_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            device: File::options().read(true).write(true).open(path)?,
+        })
+    }
+    /// Allocates `size` bytes, rounded up to whole pages
+    pub fn allocate(&self, size: u64) -> Result<Udmabuf, Errno> {
+        let size = size.next_multiple_of(PAGE_SIZE);
+        let memfd = memfd_create(
+            "cme-udmabuf",
+            MemfdFlags::CLOEXEC | MemfdFlags::ALLOW_SEALING,
+        )?;
+        ftruncate(&memfd, size)?;
+        // udmabuf refuses memfds that could shrink under it
+        fcntl_add_seals(&memfd, SealFlags::SHRINK)?;
+        let dmabuf = unsafe {
+            rustix::ioctl::ioctl(
+                &self.device,
+                CreateUdmabuf(UdmabufCreate {
+                    memfd: memfd.as_raw_fd() as u32,
+                    flags: UDMABUF_FLAGS_CLOEXEC,
+                    offset: 0,
+                    size,
+                }),
+            )?
+        };
+        Ok(Udmabuf {
+            dmabuf,
+            memfd,
+            size,
+        })
+    }
+    /// Allocates a linear single plane image for [`Dmatex::import`](crate::dmatex::Dmatex::import),
+    /// returns None for multi-planar or unknown formats
+    pub fn allocate_image(
+        &self,
+        size: &DmatexSize,
+        fourcc: DrmFourcc,
+        srgb: bool,
+    ) -> Option<Result<ExternalDmabuf, Errno>> {
+        self.allocate_mappable_image(size, fourcc, srgb)
+            .map(|v| v.map(|(dmabuf, _)| dmabuf))
+    }
+    /// Like [`Self::allocate_image`], together with the memfd to map for writing the pixels
+    pub fn allocate_mappable_image(
+        &self,
+        size: &DmatexSize,
+        fourcc: DrmFourcc,
+        srgb: bool,
+    ) -> Option<Result<(ExternalDmabuf, OwnedFd), Errno>> {
+        let format = Format::from_drm_fourcc(fourcc)?;
+        if format.planes().len() > 1 {
+            return None;
+        }
+        let [width, height, depth] = match size {
+            DmatexSize::Dim1D(v) => [*v, 1, 1],
+            DmatexSize::Dim2D(v) => [v.x, v.y, 1],
+            DmatexSize::Dim3D(v) => [v.x, v.y, v.z],
+        };
+        let row_pitch = (width as u64 * format.block_size()).next_multiple_of(ROW_ALIGNMENT);
+        let buf = match self.allocate(row_pitch * height as u64 * depth as u64) {
+            Ok(v) => v,
+            Err(err) => return Some(Err(err)),
+        };
+        let dmabuf = ExternalDmabuf {
+            size: size.clone(),
+            fourcc,
+            modifier: DrmModifier::Linear.into(),
+            srgb,
+            planes: vec![ExternalPlane {
+                fd: buf.dmabuf,
+                offset: 0,
+                row_pitch,
+            }],
+        };
+        Some(Ok((dmabuf, buf.memfd)))
+    }
+}
+
+const UDMABUF_FLAGS_CLOEXEC: u32 = 0x01;
+const UDMABUF_CREATE: Opcode = opcode::write::<UdmabufCreate>(b'u', 0x42);
+
+/// `struct udmabuf_create` from `linux/udmabuf.h`
+#[repr(C)]
+struct UdmabufCreate {
+    memfd: u32,
+    flags: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// `UDMABUF_CREATE` returns the new dmabuf fd instead of writing it somewhere
+struct CreateUdmabuf(UdmabufCreate);
+unsafe impl Ioctl for CreateUdmabuf {
+    type Output = OwnedFd;
+    const IS_MUTATING: bool = false;
+
+    fn opcode(&self) -> Opcode {
+        UDMABUF_CREATE
+    }
+    fn as_ptr(&mut self) -> *mut c_void {
+        (&raw mut self.0).cast()
+    }
+    unsafe fn output_from_ptr(
+        out: IoctlOutput,
+        _extract_output: *mut c_void,
+    ) -> rustix::io::Result<Self::Output> {
+        Ok(unsafe { OwnedFd::from_raw_fd(out) })
+    }
+}
@@ -0,0 +1,304 @@
+//! Streams texture data into dmatexes on a dedicated transfer queue
+
+use std::{collections::VecDeque, ops::Range, sync::Arc};
+
+use thiserror::Error;
+use tracing::warn;
+use vulkano::{
+    DeviceSize, Validated, ValidationError, VulkanError,
+    buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CommandBufferSubmitInfo, CommandBufferUsage,
+        CopyBufferToImageInfo, PrimaryAutoCommandBuffer, SemaphoreSubmitInfo, SubmitInfo,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, DeviceOwned, Queue},
+    image::ImageSubresourceLayers,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::{
+        PipelineStages,
+        fence::{Fence, FenceCreateInfo},
+        semaphore::Semaphore,
+    },
+};
+
+use crate::dmatex::{Dmatex, attach_to_timeline, exportable_semaphore, timeline_wait_semaphore};
+
+/// 16MiB
+const DEFAULT_STAGING_SIZE: DeviceSize = 16 * 1024 * 1024;
+/// satisfies the copy offset alignment of every color format
+const STAGING_ALIGNMENT: DeviceSize = 16;
+
+/// Region of a single array layer to upload, `data` is tightly packed.
+/// For compressed formats `data` holds whole blocks and the region has to be block aligned
+#[derive(Debug, Clone)]
+pub struct UploadRegion {
+    pub offset: [u32; 3],
+    pub extent: [u32; 3],
+    pub array_layer: u32,
+}
+
+/// When the upload may start and which timeline point it signals on completion
+#[derive(Debug, Clone, Copy)]
+pub struct UploadPoints {
+    /// the upload waits for this point, e.g. the server's last release
+    pub wait: Option<u64>,
+    pub signal: u64,
+}
+
+/// Owns a transfer queue and a staging ring buffer, so uploads don't contend with rendering.
+/// The dmatex must be owned by the transfer queue's family or use concurrent sharing
+pub struct Uploader {
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    staging: Subbuffer<[u8]>,
+    head: DeviceSize,
+    /// uploads in submission order
+    in_flight: VecDeque<InFlightUpload>,
+}
+
+/// An upload the gpu might still be working on, with everything its raw submission uses
+struct InFlightUpload {
+    /// the part of the ring it reads, None for buffers that didn't fit into the ring
+    range: Option<Range<DeviceSize>>,
+    _staging: Subbuffer<[u8]>,
+    _command_buffer: Arc<PrimaryAutoCommandBuffer>,
+    _semaphores: Vec<Arc<Semaphore>>,
+    fence: Arc<Fence>,
+}
+impl InFlightUpload {
+    fn is_done(&self) -> bool {
+        self.fence.is_signaled().unwrap_or(false)
+    }
+}
+
+impl Uploader {
+    pub fn new(queue: &Arc<Queue>) -> Self {
+        Self::with_staging_size(queue, DEFAULT_STAGING_SIZE)
+    }
+    pub fn with_staging_size(queue: &Arc<Queue>, staging_size: DeviceSize) -> Self {
+        let dev = queue.device();
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+        Self {
+            queue: queue.clone(),
+            // TODO: error handling
+            staging: staging_buffer(&memory_allocator, staging_size).unwrap(),
+            memory_allocator,
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            head: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+    /// uploads all layers of the whole image, `data` is tightly packed layer after layer
+    pub fn upload_image(
+        &mut self,
+        dmatex: &Dmatex,
+        data: &[u8],
+        points: UploadPoints,
+    ) -> Result<(), UploadError> {
+        let image = &dmatex.image;
+        self.upload(
+            dmatex,
+            data,
+            BufferImageCopy {
+                image_subresource: image.subresource_layers(),
+                image_extent: image.extent(),
+                ..Default::default()
+            },
+            points,
+        )
+    }
+    pub fn upload_region(
+        &mut self,
+        dmatex: &Dmatex,
+        data: &[u8],
+        region: UploadRegion,
+        points: UploadPoints,
+    ) -> Result<(), UploadError> {
+        let layers = region.array_layer..region.array_layer + 1;
+        self.upload(
+            dmatex,
+            data,
+            BufferImageCopy {
+                image_subresource: ImageSubresourceLayers {
+                    array_layers: layers,
+                    ..dmatex.image.subresource_layers()
+                },
+                image_offset: region.offset,
+                image_extent: region.extent,
+                ..Default::default()
+            },
+            points,
+        )
+    }
+    /// blocks until every upload finished
+    pub fn wait_idle(&mut self) -> Result<(), UploadError> {
+        while let Some(upload) = self.in_flight.front() {
+            upload.fence.wait(None)?;
+            self.in_flight.pop_front();
+        }
+        Ok(())
+    }
+
+    fn upload(
+        &mut self,
+        dmatex: &Dmatex,
+        data: &[u8],
+        copy: BufferImageCopy,
+        points: UploadPoints,
+    ) -> Result<(), UploadError> {
+        // one-off staging buffers don't get freed by the ring
+        while self.in_flight.front().is_some_and(InFlightUpload::is_done) {
+            self.in_flight.pop_front();
+        }
+        let dev = self.queue.device().clone();
+        let (staging, range) = match self.reserve(data.len() as DeviceSize)? {
+            Some(range) => (self.staging.clone().slice(range.clone()), Some(range)),
+            // doesn't fit into the ring at all
+            None => (
+                staging_buffer(&self.memory_allocator, data.len() as DeviceSize)?,
+                None,
+            ),
+        };
+        staging.write().unwrap().copy_from_slice(data);
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+        builder.copy_buffer_to_image(CopyBufferToImageInfo {
+            regions: [copy].into(),
+            ..CopyBufferToImageInfo::buffer_image(staging.clone(), dmatex.image.clone())
+        })?;
+        let command_buffer = builder.build()?;
+
+        let wait = points
+            .wait
+            .map(|point| timeline_wait_semaphore(&dev, &dmatex.timeline, point));
+        let signal = exportable_semaphore(&dev);
+        let fence = Arc::new(Fence::new(dev.clone(), FenceCreateInfo::default())?);
+        self.queue.with(|mut guard| unsafe {
+            guard.submit(
+                &[SubmitInfo {
+                    wait_semaphores: wait
+                        .iter()
+                        .map(|semaphore| SemaphoreSubmitInfo {
+                            stages: PipelineStages::ALL_TRANSFER,
+                            ..SemaphoreSubmitInfo::new(semaphore.clone())
+                        })
+                        .collect(),
+                    command_buffers: vec![CommandBufferSubmitInfo::new(command_buffer.clone())],
+                    signal_semaphores: vec![SemaphoreSubmitInfo::new(signal.clone())],
+                    ..Default::default()
+                }],
+                Some(&fence),
+            )
+        })?;
+        attach_to_timeline(&dmatex.timeline, &signal, points.signal);
+        // the raw submission doesn't keep anything alive on its own
+        self.in_flight.push_back(InFlightUpload {
+            range,
+            _staging: staging,
+            _command_buffer: command_buffer,
+            _semaphores: wait.into_iter().chain([signal]).collect(),
+            fence,
+        });
+        Ok(())
+    }
+
+    /// Finds space for `size` bytes in the ring, waiting on old uploads if needed
+    fn reserve(&mut self, size: DeviceSize) -> Result<Option<Range<DeviceSize>>, UploadError> {
+        let capacity = self.staging.size();
+        if size > capacity {
+            return Ok(None);
+        }
+        let mut start = self.head.next_multiple_of(STAGING_ALIGNMENT);
+        if start + size > capacity {
+            start = 0;
+        }
+        let range = start..start + size;
+        // free everything that overlaps, oldest first
+        while let Some(upload) = self.in_flight.front() {
+            let overlaps = upload
+                .range
+                .as_ref()
+                .is_some_and(|used| used.start < range.end && range.start < used.end);
+            if !overlaps && !upload.is_done() {
+                break;
+            }
+            upload.fence.wait(None)?;
+            self.in_flight.pop_front();
+        }
+        self.head = range.end;
+        Ok(Some(range))
+    }
+}
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        // the staging memory has to outlive the uploads reading it
+        if let Err(err) = self.wait_idle() {
+            warn!("failed to wait for uploads in flight: {err}");
+        }
+    }
+}
+
+fn staging_buffer(
+    allocator: &Arc<StandardMemoryAllocator>,
+    size: DeviceSize,
+) -> Result<Subbuffer<[u8]>, UploadError> {
+    Buffer::new_slice(
+        allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        size,
+    )
+    .map_err(UploadError::Staging)
+}
+
+#[derive(Debug, Error)]
+pub enum UploadError {
+    #[error("failed to allocate a staging buffer: {0}")]
+    Staging(Validated<AllocateBufferError>),
+    #[error("the vulkan device was lost")]
+    DeviceLost,
+    #[error("vulkan error: {0}")]
+    Vulkan(VulkanError),
+    #[error("validation error: {0}")]
+    Validation(Box<ValidationError>),
+}
+impl From<VulkanError> for UploadError {
+    fn from(err: VulkanError) -> Self {
+        match err {
+            VulkanError::DeviceLost => Self::DeviceLost,
+            err => Self::Vulkan(err),
+        }
+    }
+}
+impl From<Validated<VulkanError>> for UploadError {
+    fn from(err: Validated<VulkanError>) -> Self {
+        match err {
+            Validated::Error(err) => err.into(),
+            Validated::ValidationError(err) => Self::Validation(err),
+        }
+    }
+}
+impl From<Box<ValidationError>> for UploadError {
+    fn from(err: Box<ValidationError>) -> Self {
+        Self::Validation(err)
+    }
+}
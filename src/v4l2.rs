@@ -0,0 +1,212 @@
+//! Zero-copy camera frames from V4L2 capture devices, for passthrough cameras and webcam viewers
+//!
+//! The capture queue itself is driven by the app with `VIDIOC_REQBUFS`/`QBUF`/`DQBUF` through
+//! whatever V4L2 binding it uses. Every buffer is exported once with [`export_buffer`] and
+//! imported into a [`V4l2Bridge`], which turns dequeued buffers into acquire points and tells
+//! when a buffer can be queued again.
+
+use std::{
+    os::fd::{AsFd, FromRawFd, OwnedFd},
+    sync::Arc,
+};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use rustix::{
+    fs::OFlags,
+    io::Errno,
+    ioctl::{Updater, ioctl, opcode},
+};
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use vulkano::{device::Device, image::ImageUsage};
+
+use crate::{
+    backend::Backend,
+    dmatex::{BridgedDmatex, Dmatex, DmatexImportError, ExternalDmabuf, ExternalPlane},
+    render_device::RenderDevice,
+};
+
+pub const V4L2_BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+pub const V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE: u32 = 9;
+
+pub const V4L2_PIX_FMT_YUYV: u32 = u32::from_le_bytes(*b"YUYV");
+pub const V4L2_PIX_FMT_NV12: u32 = u32::from_le_bytes(*b"NV12");
+/// NV12 with the planes in separate buffers, from the multi-planar api
+pub const V4L2_PIX_FMT_NV12M: u32 = u32::from_le_bytes(*b"NM12");
+
+/// The negotiated capture format, from `VIDIOC_G_FMT`
+#[derive(Debug, Clone)]
+pub struct V4l2Format {
+    pub width: u32,
+    pub height: u32,
+    pub pixelformat: u32,
+    /// `bytesperline` of every memory plane, one for the single-planar api
+    pub bytes_per_line: Vec<u32>,
+}
+
+impl V4l2Format {
+    pub fn fourcc(&self) -> Option<DrmFourcc> {
+        Some(match self.pixelformat {
+            V4L2_PIX_FMT_YUYV => DrmFourcc::Yuyv,
+            V4L2_PIX_FMT_NV12 | V4L2_PIX_FMT_NV12M => DrmFourcc::Nv12,
+            _ => return None,
+        })
+    }
+    /// The layout of one buffer, `fds` holds one exported fd per memory plane
+    pub fn to_dmabuf(&self, fds: &[OwnedFd]) -> Result<ExternalDmabuf, V4l2Error> {
+        let fourcc = self
+            .fourcc()
+            .ok_or(V4l2Error::UnsupportedFormat(self.pixelformat))?;
+        let planes = match (self.pixelformat, fds, self.bytes_per_line.as_slice()) {
+            (V4L2_PIX_FMT_YUYV, [fd], [pitch]) => vec![linear_plane(fd, 0, *pitch)?],
+            // the chroma plane follows the luma plane in the same buffer
+            (V4L2_PIX_FMT_NV12, [fd], [pitch]) => vec![
+                linear_plane(fd, 0, *pitch)?,
+                linear_plane(fd, *pitch as u64 * self.height as u64, *pitch)?,
+            ],
+            (V4L2_PIX_FMT_NV12M, [luma, chroma], [luma_pitch, chroma_pitch]) => vec![
+                linear_plane(luma, 0, *luma_pitch)?,
+                linear_plane(chroma, 0, *chroma_pitch)?,
+            ],
+            _ => return Err(V4l2Error::PlaneCount),
+        };
+        Ok(ExternalDmabuf {
+            size: DmatexSize::Dim2D([self.width, self.height].into()),
+            fourcc,
+            modifier: DrmModifier::Linear.into(),
+            srgb: false,
+            planes,
+        })
+    }
+}
+
+fn linear_plane(fd: &OwnedFd, offset: u64, pitch: u32) -> Result<ExternalPlane, V4l2Error> {
+    Ok(ExternalPlane {
+        fd: fd.try_clone().map_err(V4l2Error::Dup)?,
+        offset,
+        row_pitch: pitch as u64,
+    })
+}
+
+/// `VIDIOC_EXPBUF`, exports memory plane `plane` of buffer `index` as a dmabuf
+pub fn export_buffer(
+    video: impl AsFd,
+    buf_type: u32,
+    index: u32,
+    plane: u32,
+) -> Result<OwnedFd, V4l2Error> {
+    let mut args = V4l2ExportBuffer {
+        type_: buf_type,
+        index,
+        plane,
+        flags: (OFlags::CLOEXEC | OFlags::RDWR).bits(),
+        fd: -1,
+        reserved: [0; 11],
+    };
+    unsafe { ioctl(video, Updater::<VIDIOC_EXPBUF, _>::new(&mut args)) }
+        .map_err(V4l2Error::Export)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(args.fd) })
+}
+
+/// Imports the capture buffers once and turns every dequeued buffer into a timeline point
+pub struct V4l2Bridge<B: Backend> {
+    client: Arc<B>,
+    dev: Arc<Device>,
+    usage: ImageUsage,
+    /// indexed by the v4l2 buffer index
+    buffers: Vec<BridgedDmatex>,
+}
+
+impl<B: Backend> V4l2Bridge<B> {
+    pub fn new(client: &Arc<B>, dev: &Arc<Device>, usage: ImageUsage) -> Self {
+        Self {
+            client: client.clone(),
+            dev: dev.clone(),
+            usage,
+            buffers: Vec::new(),
+        }
+    }
+    /// Imports every buffer of the queue, `fds[i]` are the exported memory planes of buffer
+    /// `i`. Replaces the previous buffers, call this again after `VIDIOC_REQBUFS`
+    pub fn import_buffers(
+        &mut self,
+        render_dev: &RenderDevice,
+        format: &V4l2Format,
+        fds: Vec<Vec<OwnedFd>>,
+    ) -> Result<(), V4l2Error> {
+        self.buffers = fds
+            .into_iter()
+            .map(|fds| {
+                Ok(BridgedDmatex::new(Dmatex::try_import(
+                    &self.client,
+                    &self.dev,
+                    render_dev,
+                    format.to_dmabuf(&fds)?,
+                    self.usage,
+                )?))
+            })
+            .collect::<Result<_, V4l2Error>>()?;
+        Ok(())
+    }
+    pub fn get(&self, index: u32) -> Option<&Dmatex> {
+        self.buffers.get(index as usize).map(|b| &b.dmatex)
+    }
+    /// Marks a buffer returned by `VIDIOC_DQBUF` as ready for the server, don't queue it again
+    /// before [`Self::is_released`] or [`Self::wait_release`] say so
+    pub fn dequeued(&mut self, index: u32) -> Result<DmatexSubmitInfo, V4l2Error> {
+        let buffer = self
+            .buffers
+            .get(index as usize)
+            .ok_or(V4l2Error::UnknownBuffer(index))?;
+        // the driver is done writing once the buffer is dequeued
+        buffer.signal_frame().map_err(V4l2Error::Syncobj)
+    }
+    /// whether the server stopped reading from buffer `index`, so it can be queued again
+    pub fn is_released(&self, index: u32) -> bool {
+        self.buffers
+            .get(index as usize)
+            .is_none_or(BridgedDmatex::is_released)
+    }
+    /// blocks until buffer `index` can be queued again
+    pub fn wait_release(&self, index: u32) -> Result<(), V4l2Error> {
+        let Some(buffer) = self.buffers.get(index as usize) else {
+            return Ok(());
+        };
+        buffer.wait_release(None).map_err(V4l2Error::Syncobj)
+    }
+    /// drops all imported buffers, e.g. before the queue is freed with `VIDIOC_REQBUFS`
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+}
+
+const VIDIOC_EXPBUF: rustix::ioctl::Opcode = opcode::read_write::<V4l2ExportBuffer>(b'V', 16);
+
+/// `struct v4l2_exportbuffer` from `linux/videodev2.h`
+#[repr(C)]
+struct V4l2ExportBuffer {
+    type_: u32,
+    index: u32,
+    plane: u32,
+    flags: u32,
+    fd: i32,
+    reserved: [u32; 11],
+}
+
+#[derive(Debug, Error)]
+pub enum V4l2Error {
+    #[error("unsupported v4l2 pixel format {0:#010x}")]
+    UnsupportedFormat(u32),
+    #[error("the number of buffer fds doesn't match the pixel format")]
+    PlaneCount,
+    #[error("failed to export the capture buffer: {0}")]
+    Export(Errno),
+    #[error("failed to duplicate a buffer fd: {0}")]
+    Dup(std::io::Error),
+    #[error("no buffer with index {0} was imported")]
+    UnknownBuffer(u32),
+    #[error("timeline syncobj error: {0}")]
+    Syncobj(Errno),
+    #[error(transparent)]
+    Import(#[from] DmatexImportError),
+}
@@ -0,0 +1,162 @@
+//! Wraps surfaces exported from libva with `vaExportSurfaceHandle` as dmatexes
+
+use std::{
+    os::fd::{FromRawFd, OwnedFd},
+    sync::Arc,
+};
+
+use drm_fourcc::DrmFourcc;
+use stardust_xr_fusion::drawable::DmatexSize;
+use thiserror::Error;
+use vulkano::{device::Device, image::ImageUsage};
+
+use crate::{
+    backend::Backend,
+    dmatex::{Dmatex, DmatexImportError, ExternalDmabuf, ExternalPlane},
+    render_device::RenderDevice,
+};
+
+/// Same layout as libva's `VADRMPRIMESurfaceDescriptor`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct VaDrmPrimeSurfaceDescriptor {
+    pub fourcc: u32,
+    pub width: u32,
+    pub height: u32,
+    pub num_objects: u32,
+    pub objects: [VaDrmPrimeObject; 4],
+    pub num_layers: u32,
+    pub layers: [VaDrmPrimeLayer; 4],
+}
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct VaDrmPrimeObject {
+    pub fd: i32,
+    pub size: u32,
+    pub drm_format_modifier: u64,
+}
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct VaDrmPrimeLayer {
+    pub drm_format: u32,
+    pub num_planes: u32,
+    pub object_index: [u32; 4],
+    pub offset: [u32; 4],
+    pub pitch: [u32; 4],
+}
+
+impl VaDrmPrimeSurfaceDescriptor {
+    /// Handles both `VA_EXPORT_SURFACE_COMPOSED_LAYERS` (one NV12/P010 layer) and
+    /// `VA_EXPORT_SURFACE_SEPARATE_LAYERS` (a luma and a chroma layer) exports, with planes
+    /// either sharing one object or spread across several.
+    ///
+    /// # Safety
+    /// the object fds have to be open and must not be used or closed by anyone else afterwards
+    pub unsafe fn into_dmabuf(self) -> Result<ExternalDmabuf, VaImportError> {
+        let num_objects = (self.num_objects as usize).min(4);
+        let objects = self.objects[..num_objects]
+            .iter()
+            .map(|o| unsafe { OwnedFd::from_raw_fd(o.fd) })
+            .collect::<Vec<_>>();
+        let layers = &self.layers[..(self.num_layers as usize).min(4)];
+
+        let fourcc = match layers {
+            [layer] => layer.drm_format,
+            [luma, chroma] => match (
+                DrmFourcc::try_from(luma.drm_format),
+                DrmFourcc::try_from(chroma.drm_format),
+            ) {
+                (Ok(DrmFourcc::R8), Ok(DrmFourcc::Gr88)) => DrmFourcc::Nv12 as u32,
+                (Ok(DrmFourcc::R16), Ok(DrmFourcc::Gr1616)) => DrmFourcc::P010 as u32,
+                _ => return Err(VaImportError::UnsupportedLayers),
+            },
+            _ => return Err(VaImportError::UnsupportedLayers),
+        };
+        let fourcc =
+            DrmFourcc::try_from(fourcc).map_err(|_| VaImportError::UnknownFourcc(fourcc))?;
+        if !matches!(fourcc, DrmFourcc::Nv12 | DrmFourcc::P010) {
+            return Err(VaImportError::UnsupportedFormat(fourcc));
+        }
+
+        let plane_refs = layers
+            .iter()
+            .flat_map(|layer| {
+                (0..(layer.num_planes as usize).min(4)).map(|i| {
+                    (
+                        layer.object_index[i] as usize,
+                        layer.offset[i],
+                        layer.pitch[i],
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        if plane_refs.len() != 2 {
+            return Err(VaImportError::PlaneCount(plane_refs.len()));
+        }
+        let first_object = plane_refs[0].0;
+        if first_object >= num_objects {
+            return Err(VaImportError::InvalidObjectIndex(first_object));
+        }
+        let modifier = self.objects[first_object].drm_format_modifier;
+        let planes = plane_refs
+            .into_iter()
+            .map(|(object, offset, pitch)| {
+                let fd = objects
+                    .get(object)
+                    .ok_or(VaImportError::InvalidObjectIndex(object))?
+                    .try_clone()
+                    .map_err(VaImportError::Dup)?;
+                Ok(ExternalPlane {
+                    fd,
+                    offset: offset as u64,
+                    row_pitch: pitch as u64,
+                })
+            })
+            .collect::<Result<Vec<_>, VaImportError>>()?;
+
+        Ok(ExternalDmabuf {
+            size: DmatexSize::Dim2D([self.width, self.height].into()),
+            fourcc,
+            modifier,
+            srgb: false,
+            planes,
+        })
+    }
+}
+
+impl Dmatex {
+    /// Imports a decoded VA surface, sampling it needs a sampler ycbcr conversion
+    ///
+    /// # Safety
+    /// see [`VaDrmPrimeSurfaceDescriptor::into_dmabuf`]
+    pub unsafe fn from_va_surface<B: Backend>(
+        client: &Arc<B>,
+        dev: &Arc<Device>,
+        render_dev: &RenderDevice,
+        descriptor: VaDrmPrimeSurfaceDescriptor,
+        usage: ImageUsage,
+    ) -> Result<Self, VaImportError> {
+        let dmabuf = unsafe { descriptor.into_dmabuf() }?;
+        Ok(Self::try_import(client, dev, render_dev, dmabuf, usage)?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VaImportError {
+    #[error(
+        "unsupported layer layout, expected one NV12/P010 layer or separate luma/chroma layers"
+    )]
+    UnsupportedLayers,
+    #[error("unknown drm fourcc {0:X}")]
+    UnknownFourcc(u32),
+    #[error("unsupported format {0}, only NV12 and P010 are supported")]
+    UnsupportedFormat(DrmFourcc),
+    #[error("expected 2 planes, got {0}")]
+    PlaneCount(usize),
+    #[error("plane references missing object {0}")]
+    InvalidObjectIndex(usize),
+    #[error("failed to dup object fd: {0}")]
+    Dup(std::io::Error),
+    #[error(transparent)]
+    Import(#[from] DmatexImportError),
+}
@@ -0,0 +1,284 @@
+//! Imports `zwp_linux_dmabuf_v1` buffers of a nested wayland compositor as dmatexes, e.g. to
+//! show a 2D app inside a panel. Independent of any wayland server library: forward the
+//! `zwp_linux_buffer_params_v1` requests to [`WlDmabufParams`] and the `wl_buffer` lifecycle
+//! to [`WlDmabufCache`]
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    os::fd::{AsFd, FromRawFd, OwnedFd},
+    sync::Arc,
+};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use rustix::{
+    io::Errno,
+    ioctl::{Updater, ioctl, opcode},
+};
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use thiserror::Error;
+use tracing::warn;
+use vulkano::{device::Device, format::Format, image::ImageUsage};
+
+use crate::{
+    backend::Backend,
+    dmatex::{BridgedDmatex, Dmatex, DmatexImportError, ExternalDmabuf, ExternalPlane},
+    fourcc::VulkanoFormatExtension,
+    render_device::RenderDevice,
+};
+
+/// `zwp_linux_buffer_params_v1.flags`
+pub const FLAG_Y_INVERT: u32 = 1;
+pub const FLAG_INTERLACED: u32 = 2;
+pub const FLAG_BOTTOM_FIRST: u32 = 4;
+
+/// The planes collected from `zwp_linux_buffer_params_v1.add`, the errors line up with the
+/// protocol errors the params object has to be sent
+#[derive(Debug, Default)]
+pub struct WlDmabufParams {
+    planes: Vec<Option<WlDmabufPlane>>,
+    modifier: Option<u64>,
+}
+#[derive(Debug)]
+struct WlDmabufPlane {
+    fd: OwnedFd,
+    offset: u32,
+    stride: u32,
+}
+
+/// A buffer created from [`WlDmabufParams::create`], ready for [`WlDmabufCache::import`]
+#[derive(Debug)]
+pub struct WlDmabufBuffer {
+    pub dmabuf: ExternalDmabuf,
+    /// the client renders upside down, the first row is the bottom one
+    pub y_invert: bool,
+}
+
+impl WlDmabufParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `zwp_linux_buffer_params_v1.add`
+    pub fn add(
+        &mut self,
+        fd: OwnedFd,
+        plane_idx: u32,
+        offset: u32,
+        stride: u32,
+        modifier_hi: u32,
+        modifier_lo: u32,
+    ) -> Result<(), WlDmabufError> {
+        let plane_idx = plane_idx as usize;
+        if plane_idx >= 4 {
+            return Err(WlDmabufError::PlaneIdx);
+        }
+        let modifier = ((modifier_hi as u64) << 32) | modifier_lo as u64;
+        if *self.modifier.get_or_insert(modifier) != modifier {
+            return Err(WlDmabufError::InvalidFormat);
+        }
+        if self.planes.len() <= plane_idx {
+            self.planes.resize_with(plane_idx + 1, || None);
+        }
+        if self.planes[plane_idx].is_some() {
+            return Err(WlDmabufError::PlaneSet);
+        }
+        self.planes[plane_idx] = Some(WlDmabufPlane { fd, offset, stride });
+        Ok(())
+    }
+    /// `zwp_linux_buffer_params_v1.create` and `create_immed`, `format` is the drm fourcc
+    pub fn create(
+        self,
+        width: i32,
+        height: i32,
+        format: u32,
+        flags: u32,
+    ) -> Result<WlDmabufBuffer, WlDmabufError> {
+        let fourcc = DrmFourcc::try_from(format).map_err(|_| WlDmabufError::InvalidFormat)?;
+        let vk_format = Format::from_drm_fourcc(fourcc).ok_or(WlDmabufError::InvalidFormat)?;
+        if width <= 0 || height <= 0 {
+            return Err(WlDmabufError::InvalidDimensions);
+        }
+        if flags & (FLAG_INTERLACED | FLAG_BOTTOM_FIRST) != 0 {
+            return Err(WlDmabufError::Unsupported);
+        }
+        let planes = self
+            .planes
+            .into_iter()
+            .map(|p| p.ok_or(WlDmabufError::Incomplete))
+            .collect::<Result<Vec<_>, _>>()?;
+        let modifier = self.modifier.unwrap_or_default();
+        let format_planes = vk_format.planes().len().max(1);
+        // modifiers can add planes, e.g. for compression metadata, those are checked against
+        // the device on import
+        let plane_count_matches = if modifier == u64::from(DrmModifier::Linear) {
+            planes.len() == format_planes
+        } else {
+            planes.len() >= format_planes
+        };
+        if !plane_count_matches {
+            return Err(WlDmabufError::Incomplete);
+        }
+        Ok(WlDmabufBuffer {
+            dmabuf: ExternalDmabuf {
+                size: DmatexSize::Dim2D([width as u32, height as u32].into()),
+                fourcc,
+                modifier,
+                srgb: false,
+                planes: planes
+                    .into_iter()
+                    .map(|p| ExternalPlane {
+                        fd: p.fd,
+                        offset: p.offset as u64,
+                        row_pitch: p.stride as u64,
+                    })
+                    .collect(),
+            },
+            y_invert: flags & FLAG_Y_INVERT != 0,
+        })
+    }
+}
+
+/// Imports each `wl_buffer` once and turns commits into timeline points, telling when a
+/// buffer can be released back to its client. `K` identifies the wl_buffer, e.g. its object id
+pub struct WlDmabufCache<B: Backend, K> {
+    client: Arc<B>,
+    dev: Arc<Device>,
+    usage: ImageUsage,
+    buffers: HashMap<K, CachedBuffer>,
+}
+struct CachedBuffer {
+    bridged: BridgedDmatex,
+    /// fd of the first plane, for waiting on the client's implicit fence
+    dmabuf: OwnedFd,
+    y_invert: bool,
+    /// the buffer was committed and wl_buffer.release wasn't sent yet
+    pending_release: bool,
+}
+
+impl<B: Backend, K: Hash + Eq + Clone> WlDmabufCache<B, K> {
+    pub fn new(client: &Arc<B>, dev: &Arc<Device>, usage: ImageUsage) -> Self {
+        Self {
+            client: client.clone(),
+            dev: dev.clone(),
+            usage,
+            buffers: HashMap::new(),
+        }
+    }
+    /// Imports a newly created wl_buffer, replacing whatever was cached for `key`. The buffer
+    /// comes from the nested client, on error send `zwp_linux_buffer_params_v1.failed`
+    pub fn import(
+        &mut self,
+        render_dev: &RenderDevice,
+        key: K,
+        buffer: WlDmabufBuffer,
+    ) -> Result<(), DmatexImportError> {
+        let dmabuf = buffer.dmabuf.planes[0]
+            .fd
+            .try_clone()
+            .map_err(DmatexImportError::Dup)?;
+        let dmatex = Dmatex::try_import(
+            &self.client,
+            &self.dev,
+            render_dev,
+            buffer.dmabuf,
+            self.usage,
+        )?;
+        self.buffers.insert(
+            key,
+            CachedBuffer {
+                bridged: BridgedDmatex::new(dmatex),
+                dmabuf,
+                y_invert: buffer.y_invert,
+                pending_release: false,
+            },
+        );
+        Ok(())
+    }
+    pub fn get(&self, key: &K) -> Option<&Dmatex> {
+        self.buffers.get(key).map(|b| &b.bridged.dmatex)
+    }
+    pub fn is_y_inverted(&self, key: &K) -> Option<bool> {
+        self.buffers.get(key).map(|b| b.y_invert)
+    }
+    /// A `wl_surface.commit` with this buffer attached. The acquire point is signalled once
+    /// the client's rendering into the buffer finished, None if `key` wasn't imported
+    pub fn commit(&mut self, key: &K) -> Result<Option<DmatexSubmitInfo>, Errno> {
+        let Some(buffer) = self.buffers.get_mut(key) else {
+            return Ok(None);
+        };
+        // wayland clients sync implicitly, their fence lives in the dmabuf
+        let submit = match export_read_fence(&buffer.dmabuf)
+            .and_then(|fence| buffer.bridged.fenced_frame(fence.as_fd()))
+        {
+            Ok(submit) => submit,
+            Err(err) => {
+                warn!("can't wait for the client's implicit fence, presenting right away: {err}");
+                buffer.bridged.signal_frame()?
+            }
+        };
+        buffer.pending_release = true;
+        Ok(Some(submit))
+    }
+    /// the buffers the server stopped reading from since the last call, send
+    /// `wl_buffer.release` for each of them
+    pub fn poll_releases(&mut self) -> Vec<K> {
+        self.buffers
+            .iter_mut()
+            .filter(|(_, b)| b.pending_release && b.bridged.is_released())
+            .map(|(key, b)| {
+                b.pending_release = false;
+                key.clone()
+            })
+            .collect()
+    }
+    /// `wl_buffer.destroy`
+    pub fn remove(&mut self, key: &K) {
+        self.buffers.remove(key);
+    }
+    /// drops all imported buffers, e.g. when the client disconnects
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+}
+
+/// a sync file signalled once all writes to `dmabuf` are done, needs linux 6.0 or newer
+fn export_read_fence(dmabuf: &OwnedFd) -> Result<OwnedFd, Errno> {
+    let mut args = DmaBufExportSyncFile {
+        flags: DMA_BUF_SYNC_READ,
+        fd: -1,
+    };
+    unsafe {
+        ioctl(
+            dmabuf,
+            Updater::<DMA_BUF_IOCTL_EXPORT_SYNC_FILE, _>::new(&mut args),
+        )
+    }?;
+    Ok(unsafe { OwnedFd::from_raw_fd(args.fd) })
+}
+
+const DMA_BUF_SYNC_READ: u32 = 1;
+const DMA_BUF_IOCTL_EXPORT_SYNC_FILE: rustix::ioctl::Opcode =
+    opcode::read_write::<DmaBufExportSyncFile>(b'b', 2);
+
+/// `struct dma_buf_export_sync_file` from `linux/dma-buf.h`
+#[repr(C)]
+struct DmaBufExportSyncFile {
+    flags: u32,
+    fd: i32,
+}
+
+#[derive(Debug, Error)]
+pub enum WlDmabufError {
+    #[error("plane index out of bounds")]
+    PlaneIdx,
+    #[error("plane index was already set")]
+    PlaneSet,
+    #[error("missing or too many planes")]
+    Incomplete,
+    #[error("unsupported format or modifier")]
+    InvalidFormat,
+    #[error("invalid width or height")]
+    InvalidDimensions,
+    #[error("interlaced buffers aren't supported")]
+    Unsupported,
+}
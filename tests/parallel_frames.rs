@@ -0,0 +1,264 @@
+//! Frames of one swapchain recorded on worker threads and submitted from the thread owning
+//! the queue, against the mock server on the first gpu that can share dmatexes. Skipped
+//! when there is no such gpu
+
+use std::{sync::Arc, thread};
+
+use stardust_xr_cme::{
+    mock::MockSetup,
+    swapchain::{FrameSubmission, Swapchain, SwapchainError, SwapchainFrameHandle},
+};
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, CopyImageToBufferInfo,
+        PrimaryAutoCommandBuffer, allocator::StandardCommandBufferAllocator,
+    },
+    format::{ClearColorValue, Format},
+    image::ImageUsage,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+const SIZE: u32 = 64;
+
+fn setup() -> Option<MockSetup> {
+    match MockSetup::new() {
+        Ok(setup) => Some(setup),
+        Err(err) => {
+            eprintln!("skipped, no mock setup: {err}");
+            None
+        }
+    }
+}
+
+/// counts the frames rendered into each image in its per-image data
+fn swapchain(setup: &MockSetup) -> Swapchain<u32> {
+    Swapchain::builder(
+        &setup.server,
+        &setup.dev,
+        &setup.render_dev,
+        DmatexSize::Dim2D([SIZE, SIZE].into()),
+        &setup.formats[&Format::R8G8B8A8_UNORM],
+        ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+    )
+    .build_with(|_| 0)
+    .unwrap()
+}
+
+/// a frame recorded on a worker, the readback holds the image after the clear
+struct Recorded<'a> {
+    frame: SwapchainFrameHandle<'a, u32>,
+    command_buffer: Arc<PrimaryAutoCommandBuffer>,
+    readback: Subbuffer<[u8]>,
+    value: u8,
+}
+
+/// clears the frame's image to `value` and copies it into a host visible buffer
+fn record<'a>(
+    queue_family_index: u32,
+    allocator: &Arc<StandardCommandBufferAllocator>,
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    mut frame: SwapchainFrameHandle<'a, u32>,
+    value: u8,
+) -> Recorded<'a> {
+    *frame.data_mut() += 1;
+    let readback = Buffer::new_slice::<u8>(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        (SIZE * SIZE * 4) as u64,
+    )
+    .unwrap();
+    let mut builder = AutoCommandBufferBuilder::primary(
+        allocator.clone(),
+        queue_family_index,
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    builder
+        .clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Float([value as f32 / 255.0; 4]),
+            ..ClearColorImageInfo::image(frame.image())
+        })
+        .unwrap()
+        .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            frame.image(),
+            readback.clone(),
+        ))
+        .unwrap();
+    Recorded {
+        frame,
+        command_buffer: builder.build().unwrap(),
+        readback,
+        value,
+    }
+}
+
+/// every frame of the swapchain recorded on its own thread at the same time
+fn record_in_parallel<'a>(
+    setup: &MockSetup,
+    swapchain: &'a Swapchain<u32>,
+    first_value: u8,
+) -> Vec<Recorded<'a>> {
+    let allocator = Arc::new(StandardCommandBufferAllocator::new(
+        setup.dev.clone(),
+        Default::default(),
+    ));
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(setup.dev.clone()));
+    let queue_family_index = setup.queue.queue_family_index();
+    let frames = (0..swapchain.images().len())
+        .map(|_| swapchain.prepare_next_image_shared().unwrap())
+        .collect::<Vec<_>>();
+    thread::scope(|scope| {
+        let workers = frames
+            .into_iter()
+            .zip(first_value..)
+            .map(|(frame, value)| {
+                let (allocator, memory_allocator) = (&allocator, &memory_allocator);
+                scope.spawn(move || {
+                    record(
+                        queue_family_index,
+                        allocator,
+                        memory_allocator,
+                        frame,
+                        value,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// presents the frame like the server would and checks what the gpu wrote
+fn check(setup: &MockSetup, submit: &DmatexSubmitInfo, readback: &Subbuffer<[u8]>, value: u8) {
+    setup.server.present(submit).unwrap();
+    let pixels = readback.read().unwrap();
+    assert!(
+        pixels.iter().all(|v| *v == value),
+        "frame {value} has the wrong contents"
+    );
+}
+
+#[test]
+fn parallel_record_single_thread_submit() {
+    let Some(setup) = setup() else {
+        return;
+    };
+    let swapchain = swapchain(&setup);
+    for round in 0..3 {
+        let recorded = record_in_parallel(&setup, &swapchain, round * 10 + 1);
+        // submitted newest first, each frame only waits for its own image
+        for recorded in recorded.into_iter().rev() {
+            let submit = recorded
+                .frame
+                .submit_command_buffer(&setup.dev, &setup.queue, recorded.command_buffer)
+                .unwrap();
+            check(&setup, &submit, &recorded.readback, recorded.value);
+        }
+    }
+    // the per-image data went to the workers and came back once the frames were submitted
+    let frame = swapchain.prepare_next_image_shared().unwrap();
+    assert_eq!(*frame.data(), 3);
+}
+
+#[test]
+fn parallel_record_batched_submit() {
+    let Some(setup) = setup() else {
+        return;
+    };
+    let swapchain = swapchain(&setup);
+    for round in 0..3 {
+        let (frames, recorded): (Vec<_>, Vec<_>) =
+            record_in_parallel(&setup, &swapchain, round * 10 + 1)
+                .into_iter()
+                .map(|v| (v.frame, (v.command_buffer, v.readback, v.value)))
+                .unzip();
+        let submission = frames
+            .into_iter()
+            .fold(FrameSubmission::new(), FrameSubmission::frame);
+        let submits = submission
+            .submit(
+                &setup.dev,
+                &setup.queue,
+                recorded
+                    .iter()
+                    .map(|(command_buffer, ..)| command_buffer.clone()),
+            )
+            .unwrap();
+        for (submit, (_, readback, value)) in submits.iter().zip(&recorded) {
+            check(&setup, submit, readback, *value);
+        }
+    }
+}
+
+#[test]
+fn dropped_frames_give_their_points_back() {
+    let Some(setup) = setup() else {
+        return;
+    };
+    let swapchain = swapchain(&setup);
+    let acquire_points = |swapchain: &Swapchain<u32>| {
+        (0..swapchain.images().len())
+            .map(|_| {
+                swapchain
+                    .prepare_next_image_shared()
+                    .unwrap()
+                    .acquire_point()
+            })
+            .collect::<Vec<_>>()
+    };
+    let first = acquire_points(&swapchain);
+    assert_eq!(first, acquire_points(&swapchain));
+    // the frame after the dropped ones doesn't wait on a point nobody signals
+    let allocator = Arc::new(StandardCommandBufferAllocator::new(
+        setup.dev.clone(),
+        Default::default(),
+    ));
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(setup.dev.clone()));
+    let recorded = record(
+        setup.queue.queue_family_index(),
+        &allocator,
+        &memory_allocator,
+        swapchain.prepare_next_image_shared().unwrap(),
+        7,
+    );
+    let submit = recorded
+        .frame
+        .submit_command_buffer(&setup.dev, &setup.queue, recorded.command_buffer)
+        .unwrap();
+    assert_eq!(submit.acquire_point, first[0]);
+    check(&setup, &submit, &recorded.readback, recorded.value);
+}
+
+#[test]
+fn more_frames_than_images() {
+    let Some(setup) = setup() else {
+        return;
+    };
+    let swapchain = swapchain(&setup);
+    let mut frames = (0..swapchain.images().len())
+        .map(|_| swapchain.prepare_next_image_shared().unwrap())
+        .collect::<Vec<_>>();
+    assert!(matches!(
+        swapchain.prepare_next_image_shared(),
+        Err(SwapchainError::AllImagesHandedOut)
+    ));
+    // the freed image is handed out even though it's not the next one in the ring
+    let index = frames.remove(1).index();
+    assert_eq!(
+        swapchain.prepare_next_image_shared().unwrap().index(),
+        index
+    );
+}
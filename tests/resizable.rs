@@ -0,0 +1,63 @@
+//! Resizable swapchains following the preferred sizes the mock server sends on the first gpu
+//! that can share dmatexes. Skipped when there is no such gpu
+
+use std::time::Duration;
+
+use stardust_xr_cme::{
+    backend::SurfaceEvent, mock::MockSetup, resizable_swapchain::ResizableSwapchain,
+    swapchain::Swapchain,
+};
+use stardust_xr_fusion::drawable::DmatexSize;
+use vulkano::{format::Format, image::ImageUsage};
+
+fn size_2d(size: &DmatexSize) -> Option<[u32; 2]> {
+    match size {
+        DmatexSize::Dim2D(v) => Some([v.x, v.y]),
+        _ => None,
+    }
+}
+
+#[test]
+fn follows_preferred_sizes() {
+    let setup = match MockSetup::new() {
+        Ok(setup) => setup,
+        Err(err) => {
+            eprintln!("skipped, no mock setup: {err}");
+            return;
+        }
+    };
+    let swapchain = Swapchain::builder(
+        &setup.server,
+        &setup.dev,
+        &setup.render_dev,
+        DmatexSize::Dim2D([64, 64].into()),
+        &setup.formats[&Format::R8G8B8A8_UNORM],
+        ImageUsage::TRANSFER_DST,
+    )
+    .build()
+    .unwrap();
+    let mut swapchain = ResizableSwapchain::new(swapchain)
+        .with_debounce(Duration::ZERO)
+        .follow(&setup.server);
+
+    // only the latest of the sizes sent since the last frame is used
+    setup
+        .server
+        .send_surface_event(SurfaceEvent::PreferredSize(DmatexSize::Dim2D(
+            [16, 16].into(),
+        )));
+    setup
+        .server
+        .send_surface_event(SurfaceEvent::PreferredSize(DmatexSize::Dim2D(
+            [32, 16].into(),
+        )));
+    setup
+        .server
+        .send_surface_event(SurfaceEvent::Visible(false));
+    swapchain.prepare_next_image(&setup.render_dev).unwrap();
+    assert_eq!(size_2d(swapchain.swapchain().size()), Some([32, 16]));
+
+    // without new events the size stays
+    swapchain.prepare_next_image(&setup.render_dev).unwrap();
+    assert_eq!(size_2d(swapchain.swapchain().size()), Some([32, 16]));
+}
@@ -0,0 +1,254 @@
+//! End to end checks against a real Stardust server, covering the dmabuf, modifier and sync
+//! plumbing the mock server can't. Opt-in, run with
+//! `CME_TEST_SERVER=1 cargo test --test server`
+//!
+//! The server is started headless in its own runtime dir so it doesn't touch a running
+//! session. `CME_SERVER_CMD` overrides the command, `stardust-xr-server --headless` by default
+
+use std::{
+    path::PathBuf,
+    process::{Child, Command},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use stardust_xr_cme::{
+    dmatex::Dmatex, format::DmatexFormat, get_phys_dev_node_id, render_device::RenderDevice,
+    swapchain::Swapchain,
+};
+use stardust_xr_fusion::{
+    ClientHandle,
+    client::Client,
+    drawable::{DmatexSize, DmatexSubmitInfo},
+};
+use vulkano::{
+    VulkanLibrary,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferToImageInfo, CopyImageToBufferInfo,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags},
+    format::Format,
+    image::ImageUsage,
+    instance::{Instance, InstanceCreateInfo},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+const SIZE: u32 = 128;
+const CELL: u32 = 16;
+
+/// the headless server, killed when the test ends
+struct Server {
+    child: Child,
+    runtime_dir: PathBuf,
+}
+
+impl Server {
+    fn start() -> Self {
+        let runtime_dir = std::env::temp_dir().join(format!("cme-test-{}", std::process::id()));
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        let cmd = std::env::var("CME_SERVER_CMD")
+            .unwrap_or_else(|_| "stardust-xr-server --headless".to_string());
+        let mut args = cmd.split_whitespace();
+        let child = Command::new(args.next().expect("CME_SERVER_CMD is empty"))
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &runtime_dir)
+            .spawn()
+            .expect("failed to start the server");
+        // the client looks for the server's socket in the same place
+        unsafe { std::env::set_var("XDG_RUNTIME_DIR", &runtime_dir) };
+        Self { child, runtime_dir }
+    }
+    async fn connect(&self) -> Client {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match Client::connect().await {
+                Ok(client) => return client,
+                Err(err) if Instant::now() > deadline => {
+                    panic!("failed to connect to the server: {err}")
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.runtime_dir);
+    }
+}
+
+/// a device on the gpu the server renders with
+fn create_device(render_dev: &RenderDevice) -> (Arc<Device>, Arc<Queue>) {
+    let instance = Instance::new(
+        VulkanLibrary::new().unwrap(),
+        InstanceCreateInfo {
+            enabled_extensions: Dmatex::required_instance_exts(),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let phys_dev = instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .filter(|p| p.properties().render_major.is_some())
+        .find(|p| get_phys_dev_node_id(p) == render_dev.drm_node_id())
+        .expect("no vulkan device for the server's gpu");
+    let queue_family_index = phys_dev
+        .queue_family_properties()
+        .iter()
+        .position(|q| q.queue_flags.intersects(QueueFlags::GRAPHICS))
+        .unwrap() as u32;
+    let enabled_extensions = Dmatex::device_exts(&phys_dev);
+    let (dev, mut queues) = Device::new(
+        phys_dev,
+        DeviceCreateInfo {
+            enabled_extensions,
+            enabled_features: Dmatex::required_device_features(),
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (dev, queues.next().unwrap())
+}
+
+fn checkerboard() -> Vec<u8> {
+    (0..SIZE * SIZE)
+        .flat_map(|i| {
+            let (x, y) = (i % SIZE, i / SIZE);
+            let v = if (x / CELL + y / CELL) % 2 == 0 {
+                255
+            } else {
+                0
+            };
+            [v, v, v, 255]
+        })
+        .collect()
+}
+
+fn host_buffer(allocator: &Arc<StandardMemoryAllocator>, usage: BufferUsage) -> Subbuffer<[u8]> {
+    Buffer::new_slice::<u8>(
+        allocator.clone(),
+        BufferCreateInfo {
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        (SIZE * SIZE * 4) as u64,
+    )
+    .unwrap()
+}
+
+#[test]
+fn checkerboard_round_trip() {
+    if std::env::var("CME_TEST_SERVER").as_deref() != Ok("1") {
+        eprintln!("skipped, set CME_TEST_SERVER=1 to run against a real server");
+        return;
+    }
+    let server = Server::start();
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(round_trip(&server));
+}
+
+async fn round_trip(server: &Server) {
+    let mut client = server.connect().await;
+    let handle: Arc<ClientHandle> = client.handle();
+    let render_dev = client
+        .await_method(RenderDevice::primary_server_device(&handle))
+        .await
+        .unwrap()
+        .unwrap();
+    let formats = client
+        .await_method(DmatexFormat::enumerate(&handle, &render_dev))
+        .await
+        .unwrap()
+        .unwrap();
+    let format = formats
+        .get(&Format::R8G8B8A8_UNORM)
+        .expect("the server can't import rgba8");
+    let (dev, queue) = create_device(&render_dev);
+
+    let mut swapchain = Swapchain::builder(
+        &handle,
+        &dev,
+        &render_dev,
+        DmatexSize::Dim2D([SIZE, SIZE].into()),
+        format,
+        ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+    )
+    .name("cme integration test")
+    .build()
+    .unwrap();
+    for dmatex in swapchain.images() {
+        client
+            .await_method(dmatex.confirmed())
+            .await
+            .unwrap()
+            .expect("the server rejected the import");
+    }
+
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+    let upload = host_buffer(&memory_allocator, BufferUsage::TRANSFER_SRC);
+    let readback = host_buffer(&memory_allocator, BufferUsage::TRANSFER_DST);
+    let expected = checkerboard();
+    upload.write().unwrap().copy_from_slice(&expected);
+
+    let frame = swapchain.prepare_next_image();
+    let image = frame.image();
+    let mut builder = AutoCommandBufferBuilder::primary(
+        Arc::new(StandardCommandBufferAllocator::new(
+            dev.clone(),
+            Default::default(),
+        )),
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    builder
+        .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+            upload.clone(),
+            image.clone(),
+        ))
+        .unwrap()
+        .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, readback.clone()))
+        .unwrap();
+    let submit: DmatexSubmitInfo = frame
+        .submit_command_buffer(&dev, &queue, builder.build().unwrap())
+        .unwrap();
+
+    // the acquire point is signalled through the exported sync fd once the gpu is done
+    let dmatex = swapchain
+        .images()
+        .iter()
+        .find(|v| v.dmatex_id == submit.dmatex_id)
+        .unwrap();
+    dmatex
+        .timeline
+        .blocking_wait(
+            submit.acquire_point,
+            Some(Instant::now() + Duration::from_secs(5)),
+        )
+        .expect("the acquire point was never signalled");
+    let pixels = readback.read().unwrap();
+    let mismatch = pixels
+        .chunks_exact(4)
+        .zip(expected.chunks_exact(4))
+        .position(|(a, b)| a != b);
+    if let Some(i) = mismatch {
+        let i = i as u32;
+        panic!("pixel ({}, {}) doesn't match", i % SIZE, i / SIZE);
+    }
+}